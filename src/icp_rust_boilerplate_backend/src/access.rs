@@ -0,0 +1,204 @@
+use candid::Principal;
+use ic_cdk::api::{caller, is_controller};
+
+use crate::models::*;
+use crate::Error;
+
+// Actions guarded by the permission matrix
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub(crate) enum Permission {
+    ManageUsers,
+    ViewUsers,
+    UpdateProfile,
+    ManageMotorcycles,
+    ManageLoans,
+    ViewLoans,
+    MakePayments,
+    ManagePools,
+    Invest,
+    ManageSettings,
+    ViewAuditLog,
+    VerifyKyc,
+    ViewPersonalData,
+}
+
+impl UserRole {
+    // Permission matrix: what each role may do
+    fn permissions(&self) -> &'static [Permission] {
+        match self {
+            UserRole::Administrator => &[
+                Permission::ManageUsers,
+                Permission::ViewUsers,
+                Permission::UpdateProfile,
+                Permission::ManageMotorcycles,
+                Permission::ManageLoans,
+                Permission::ViewLoans,
+                Permission::MakePayments,
+                Permission::ManagePools,
+                Permission::Invest,
+                Permission::ManageSettings,
+                Permission::ViewAuditLog,
+                Permission::VerifyKyc,
+                Permission::ViewPersonalData,
+            ],
+            UserRole::Lender => &[
+                Permission::ViewUsers,
+                Permission::UpdateProfile,
+                Permission::ManageMotorcycles,
+                Permission::ManageLoans,
+                Permission::ViewLoans,
+                Permission::ManagePools,
+            ],
+            UserRole::Borrower => &[Permission::UpdateProfile, Permission::MakePayments],
+            UserRole::Investor => &[Permission::UpdateProfile, Permission::Invest],
+        }
+    }
+}
+
+// The calling principal and the records and roles it resolves to
+pub(crate) struct Caller {
+    pub(crate) principal: Principal,
+    pub(crate) user: Option<User>,
+    pub(crate) investor_id: Option<u64>,
+    pub(crate) roles: Vec<UserRole>,
+}
+
+impl Caller {
+    pub(crate) fn has(&self, permission: Permission) -> bool {
+        self.roles
+            .iter()
+            .any(|role| role.permissions().contains(&permission))
+    }
+
+    pub(crate) fn require(&self, permission: Permission) -> Result<(), Error> {
+        if self.has(permission) {
+            Ok(())
+        } else {
+            Err(Error::Unauthorized {
+                msg: format!(
+                    "Caller {} is not permitted to {:?}",
+                    self.principal, permission
+                ),
+            })
+        }
+    }
+
+    pub(crate) fn is_administrator(&self) -> bool {
+        self.roles.contains(&UserRole::Administrator)
+    }
+
+    // Anonymous callers own nothing, whatever records name them as owner
+    pub(crate) fn owns(&self, owner: Principal) -> bool {
+        self.principal == owner && self.principal != Principal::anonymous()
+    }
+
+    // Contact and identity details are visible to their owner and to
+    // callers allowed to see personal data
+    pub(crate) fn sees_personal_data(&self, owner: Principal) -> bool {
+        self.owns(owner) || self.has(Permission::ViewPersonalData)
+    }
+
+    // Callers may act on records they own; administrators may act on any record
+    pub(crate) fn require_owner(&self, owner: Principal, record: &str) -> Result<(), Error> {
+        if self.owns(owner) || self.is_administrator() {
+            Ok(())
+        } else {
+            Err(Error::Unauthorized {
+                msg: format!("Caller {} does not own {}", self.principal, record),
+            })
+        }
+    }
+}
+
+// Resolves `caller()` to its user record and investor ID. Controllers and the
+// administrators listed in the access configuration are always
+// administrators, even before they register as users. The anonymous
+// principal is shared by everyone, so it resolves to no records or roles.
+pub(crate) fn resolve_caller() -> Caller {
+    let principal = caller();
+    if principal == Principal::anonymous() {
+        return Caller {
+            principal,
+            user: None,
+            investor_id: None,
+            roles: Vec::new(),
+        };
+    }
+    // A principal that registered several users acts as the first of them
+    let user = crate::index::users_by_owner(principal)
+        .first()
+        .and_then(|user_id| crate::USERS_STORAGE.with(|storage| storage.borrow().get(user_id)));
+    let investor_id = crate::index::investor_by_owner(principal);
+    let is_listed_administrator =
+        crate::ACCESS_CONFIG.with(|cell| cell.borrow().get().administrators.contains(&principal));
+
+    let mut roles = Vec::new();
+    if let Some(user) = &user {
+        roles.push(user.role);
+    }
+    if investor_id.is_some() && !roles.contains(&UserRole::Investor) {
+        roles.push(UserRole::Investor);
+    }
+    if (is_listed_administrator || is_controller(&principal))
+        && !roles.contains(&UserRole::Administrator)
+    {
+        roles.push(UserRole::Administrator);
+    }
+
+    Caller {
+        principal,
+        user,
+        investor_id,
+        roles,
+    }
+}
+
+// The caller's principal, unless it is anonymous. Records are owned by the
+// principal that registered them, so registration requires an identity.
+pub(crate) fn authenticated_caller() -> Result<Principal, Error> {
+    let principal = caller();
+    if principal == Principal::anonymous() {
+        Err(Error::Unauthorized {
+            msg: "Anonymous callers cannot register; sign in first".to_string(),
+        })
+    } else {
+        Ok(principal)
+    }
+}
+
+// Resolves the caller and checks that one of its roles grants `permission`
+pub(crate) fn authorize(permission: Permission) -> Result<Caller, Error> {
+    let caller = resolve_caller();
+    caller.require(permission)?;
+    Ok(caller)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn caller_with(principal: Principal, roles: Vec<UserRole>) -> Caller {
+        Caller {
+            principal,
+            user: None,
+            investor_id: None,
+            roles,
+        }
+    }
+
+    #[test]
+    fn anonymous_callers_own_nothing() {
+        let anonymous = caller_with(Principal::anonymous(), Vec::new());
+        assert!(!anonymous.owns(Principal::anonymous()));
+        assert!(!anonymous.sees_personal_data(Principal::anonymous()));
+        assert!(anonymous
+            .require_owner(Principal::anonymous(), "user 1")
+            .is_err());
+
+        let owner = Principal::from_slice(&[7; 29]);
+        let signed_in = caller_with(owner, vec![UserRole::Borrower]);
+        assert!(signed_in.sees_personal_data(owner));
+        assert!(signed_in.require_owner(owner, "user 1").is_ok());
+        assert!(!signed_in.has(Permission::ViewPersonalData));
+    }
+}
@@ -0,0 +1,69 @@
+use crate::amount::Amount;
+use crate::loan::{apply_event, LoanEvent};
+use crate::models::*;
+use crate::schedule::days_between;
+use crate::settlement::refresh_status;
+use crate::Error;
+
+// Brings a loan up to date as of `today` (start of the UTC day). Interest accrues per installment:
+// the interest part of every installment that has fallen due is accrued, and
+// installments left unpaid after their due date are flagged as overdue. Each
+// day elapsed since the previous run charges a penalty on the overdue amount,
+// and the loan defaults once its oldest overdue installment is
+// `default_threshold_days` late. A disbursed loan becomes active on its start
+// date. Running twice on the same day is a no-op.
+pub(crate) fn accrue_loan(
+    mut loan: Loan,
+    mut installments: Vec<Installment>,
+    today: u64,
+    config: &AccrualConfig,
+) -> Result<(Loan, Vec<Installment>), Error> {
+    if loan.status == LoanStatus::Disbursed && loan.start_date <= today {
+        apply_event(&mut loan, LoanEvent::Activate)?;
+    }
+    if loan.status != LoanStatus::Active || loan.last_accrued_on == Some(today) {
+        return Ok((loan, installments));
+    }
+    let currency = loan.principal_amount.currency;
+
+    let mut interest_accrued = Amount::zero(currency);
+    let mut overdue_amount = Amount::zero(currency);
+    let mut oldest_overdue: Option<u64> = None;
+    for installment in installments.iter_mut() {
+        if installment.due_date <= today {
+            interest_accrued = interest_accrued.checked_add(&installment.interest_part)?;
+        }
+        refresh_status(installment, today);
+        if installment.status == InstallmentStatus::Overdue {
+            let unpaid = installment
+                .total_due
+                .checked_sub(&installment.principal_paid)?
+                .checked_sub(&installment.interest_paid)?;
+            overdue_amount = overdue_amount.checked_add(&unpaid)?;
+            if oldest_overdue.is_none() {
+                oldest_overdue = Some(installment.due_date);
+            }
+        }
+    }
+
+    let days_elapsed = match loan.last_accrued_on {
+        Some(last) => days_between(last, today),
+        None => 1,
+    };
+    let daily_penalty =
+        overdue_amount.checked_mul_rate(config.penalty_rate_bps as f64 / 10_000.0)?;
+    let penalty = daily_penalty.checked_mul_rate(days_elapsed as f64)?;
+
+    loan.interest_accrued = interest_accrued;
+    loan.penalties_due = loan.penalties_due.checked_add(&penalty)?;
+    loan.days_past_due = match oldest_overdue {
+        Some(due_date) => days_between(due_date, today).min(u32::MAX as u64) as u32,
+        None => 0,
+    };
+    if loan.days_past_due >= config.default_threshold_days {
+        apply_event(&mut loan, LoanEvent::Default)?;
+    }
+    loan.last_accrued_on = Some(today);
+
+    Ok((loan, installments))
+}
@@ -0,0 +1,163 @@
+use candid::CandidType;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+use crate::Error;
+
+// Currency Enum
+#[derive(CandidType, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Hash, Default, Debug)]
+pub enum Currency {
+    #[default]
+    Kes,
+    Ugx,
+    Tzs,
+    Usd,
+}
+
+impl Currency {
+    // Number of minor units digits (e.g. cents) used by the currency
+    pub(crate) fn decimals(&self) -> u32 {
+        match self {
+            Currency::Ugx => 0,
+            Currency::Kes | Currency::Tzs | Currency::Usd => 2,
+        }
+    }
+
+    // ISO 4217 currency code
+    pub(crate) fn code(&self) -> &'static str {
+        match self {
+            Currency::Kes => "KES",
+            Currency::Ugx => "UGX",
+            Currency::Tzs => "TZS",
+            Currency::Usd => "USD",
+        }
+    }
+}
+
+// Monetary amount held as an integer number of minor units (e.g. cents),
+// tagged with its currency so amounts in different currencies never mix.
+#[derive(CandidType, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Hash, Default, Debug)]
+pub struct Amount {
+    pub(crate) minor_units: u64,
+    pub(crate) currency: Currency,
+}
+
+impl Amount {
+    pub(crate) fn new(minor_units: u64, currency: Currency) -> Self {
+        Amount {
+            minor_units,
+            currency,
+        }
+    }
+
+    pub(crate) fn zero(currency: Currency) -> Self {
+        Amount::new(0, currency)
+    }
+
+    // Converts a legacy floating point major-unit value, rounding to the nearest minor unit
+    pub(crate) fn from_major(value: f64, currency: Currency) -> Self {
+        let scale = 10u64.pow(currency.decimals()) as f64;
+        let minor_units = (value * scale).round();
+        if minor_units.is_finite() && minor_units > 0.0 {
+            Amount::new(minor_units as u64, currency)
+        } else {
+            Amount::zero(currency)
+        }
+    }
+
+    pub(crate) fn is_zero(&self) -> bool {
+        self.minor_units == 0
+    }
+
+    pub(crate) fn ensure_same_currency(&self, other: &Amount) -> Result<(), Error> {
+        if self.currency != other.currency {
+            Err(Error::CurrencyMismatch {
+                expected: self.currency,
+                found: other.currency,
+            })
+        } else {
+            Ok(())
+        }
+    }
+
+    pub(crate) fn checked_add(&self, other: &Amount) -> Result<Amount, Error> {
+        self.ensure_same_currency(other)?;
+        self.minor_units
+            .checked_add(other.minor_units)
+            .map(|minor_units| Amount::new(minor_units, self.currency))
+            .ok_or_else(|| Error::Arithmetic {
+                msg: "Amount overflow".to_string(),
+            })
+    }
+
+    pub(crate) fn checked_sub(&self, other: &Amount) -> Result<Amount, Error> {
+        self.ensure_same_currency(other)?;
+        self.minor_units
+            .checked_sub(other.minor_units)
+            .map(|minor_units| Amount::new(minor_units, self.currency))
+            .ok_or_else(|| Error::Arithmetic {
+                msg: "Amount underflow".to_string(),
+            })
+    }
+
+    // Multiplies by a non-negative rate, rounding to the nearest minor unit
+    pub(crate) fn checked_mul_rate(&self, rate: f64) -> Result<Amount, Error> {
+        let minor_units = (self.minor_units as f64 * rate).round();
+        if !minor_units.is_finite() || minor_units < 0.0 || minor_units > u64::MAX as f64 {
+            return Err(Error::Arithmetic {
+                msg: "Amount overflow".to_string(),
+            });
+        }
+        Ok(Amount::new(minor_units as u64, self.currency))
+    }
+
+    pub(crate) fn min(&self, other: &Amount) -> Amount {
+        if other.minor_units < self.minor_units {
+            *other
+        } else {
+            *self
+        }
+    }
+}
+
+// Adds `amount` to its currency's entry in a list of per-currency totals
+pub(crate) fn add_to_totals(totals: &mut Vec<Amount>, amount: &Amount) -> Result<(), Error> {
+    match totals
+        .iter_mut()
+        .find(|total| total.currency == amount.currency)
+    {
+        Some(total) => *total = total.checked_add(amount)?,
+        None => totals.push(*amount),
+    }
+    Ok(())
+}
+
+// Takes `amount` off its currency's entry in a list of per-currency totals,
+// stopping at zero
+pub(crate) fn sub_from_totals(totals: &mut [Amount], amount: &Amount) -> Result<(), Error> {
+    if let Some(total) = totals
+        .iter_mut()
+        .find(|total| total.currency == amount.currency)
+    {
+        *total = total.checked_sub(&total.min(amount))?;
+    }
+    Ok(())
+}
+
+impl fmt::Display for Amount {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let decimals = self.currency.decimals();
+        if decimals == 0 {
+            return write!(f, "{} {}", self.minor_units, self.currency.code());
+        }
+        let scale = 10u64.pow(decimals);
+        write!(
+            f,
+            "{}.{:0width$} {}",
+            self.minor_units / scale,
+            self.minor_units % scale,
+            self.currency.code(),
+            width = decimals as usize
+        )
+    }
+}
@@ -0,0 +1,128 @@
+use candid::Principal;
+use ic_cdk::api::{caller, time};
+use serde::Serialize;
+
+use crate::models::*;
+use crate::EntityKind;
+
+// IDs the canister's configurations are filed under, as
+// `EntityKind::Configuration` records. 4 was the share token configuration,
+// which is no longer kept; its past entries stay in the log.
+pub(crate) const ACCESS_CONFIG_ID: u64 = 0;
+pub(crate) const LEDGER_CONFIG_ID: u64 = 1;
+pub(crate) const ACCRUAL_CONFIG_ID: u64 = 2;
+pub(crate) const FEE_CONFIG_ID: u64 = 3;
+
+fn to_json<T: Serialize>(record: &T) -> String {
+    serde_json::to_string(record).expect("Cannot encode an audited record as JSON")
+}
+
+// Appends a change of a stored record to the audit log and indexes the entry
+// by record and by caller. `before` is the record the change replaced, if any.
+// Writes that leave the record as it was are not logged.
+pub(crate) fn record<T: Serialize>(
+    operation: &str,
+    entity: EntityKind,
+    entity_id: u64,
+    before: Option<&T>,
+    after: &T,
+) {
+    let before = before.map(to_json);
+    let after = to_json(after);
+    if before.as_ref() == Some(&after) {
+        return;
+    }
+    let caller = caller();
+    let entry_id = crate::AUDIT_LOG.with(|log| {
+        let log = log.borrow();
+        let entry = AuditEntry {
+            id: log.len(),
+            caller,
+            timestamp: time(),
+            operation: operation.to_string(),
+            entity,
+            entity_id,
+            before,
+            after,
+        };
+        log.append(&entry).expect("Cannot append to the audit log")
+    });
+
+    let key = AuditEntityKey {
+        entity,
+        entity_id,
+        entry_id,
+    };
+    crate::AUDIT_BY_ENTITY.with(|storage| storage.borrow_mut().insert(key, ()));
+    let key = AuditCallerKey { caller, entry_id };
+    crate::AUDIT_BY_CALLER.with(|storage| storage.borrow_mut().insert(key, ()));
+}
+
+// Audit entry by its position in the log. Entries are never removed, so every
+// indexed ID resolves.
+pub(crate) fn load(entry_id: u64) -> AuditEntry {
+    crate::AUDIT_LOG
+        .with(|log| log.borrow().get(entry_id))
+        .unwrap_or_else(|| ic_cdk::trap(&format!("Audit entry {} is missing", entry_id)))
+}
+
+// IDs of the entries recording changes to one record, or to every record of
+// a kind, in log order
+pub(crate) fn entity_entry_ids(entity: EntityKind, entity_id: Option<u64>) -> Vec<u64> {
+    let start = AuditEntityKey {
+        entity,
+        entity_id: entity_id.unwrap_or(0),
+        entry_id: 0,
+    };
+    let mut entry_ids: Vec<u64> = crate::AUDIT_BY_ENTITY.with(|storage| {
+        storage
+            .borrow()
+            .range(start..)
+            .take_while(|(key, _)| {
+                key.entity == entity && entity_id.map_or(true, |id| key.entity_id == id)
+            })
+            .map(|(key, _)| key.entry_id)
+            .collect()
+    });
+    // Across records the index is ordered by record ID first
+    if entity_id.is_none() {
+        entry_ids.sort_unstable();
+    }
+    entry_ids
+}
+
+// IDs of the entries recording changes made by a principal, in log order
+pub(crate) fn caller_entry_ids(caller: Principal) -> Vec<u64> {
+    crate::AUDIT_BY_CALLER.with(|storage| {
+        storage
+            .borrow()
+            .range(
+                AuditCallerKey {
+                    caller,
+                    entry_id: 0,
+                }..,
+            )
+            .take_while(|(key, _)| key.caller == caller)
+            .map(|(key, _)| key.entry_id)
+            .collect()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::kes;
+
+    #[test]
+    fn unchanged_records_are_not_logged() {
+        record(
+            "run_accrual",
+            EntityKind::Loan,
+            7,
+            Some(&kes(100)),
+            &kes(100),
+        );
+        assert!(entity_entry_ids(EntityKind::Loan, Some(7)).is_empty());
+        assert_eq!(crate::AUDIT_LOG.with(|log| log.borrow().len()), 0);
+    }
+}
@@ -0,0 +1,277 @@
+// Certified data: a hash tree over loans, payments and loan pools whose root
+// hash is the canister's certified data.
+//
+// The tree has one labeled subtree per record type ("loans", "payments",
+// "pools"), each keyed by the big-endian record ID. A leaf is the SHA-256 of
+// the record's Candid encoding. Certified queries return the record with the
+// subnet's certificate and a witness for the record's path, so a client can
+// check that the certificate signs the root hash, that the witness reduces to
+// it, and that the leaf matches the hash of the record it received.
+//
+// A fourth subtree, "http_assets", is keyed by the URL path of the HTTP
+// gateway's per-record routes and holds the SHA-256 of each route's JSON
+// body, the layout HTTP gateways verify responses against (response
+// verification version 1). A pool's body also depends on its loans and
+// positions, so storing either re-certifies it.
+//
+// The tree lives on the heap. It is rebuilt from stable memory on install
+// and upgrade and kept current by the store helpers.
+use std::cell::RefCell;
+use std::collections::BTreeSet;
+
+use candid::{CandidType, Encode};
+use ic_certified_map::{AsHashTree, Hash, HashTree, RbTree};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+
+use crate::models::*;
+use crate::{http, Error};
+
+const LOANS: &[u8] = b"loans";
+const PAYMENTS: &[u8] = b"payments";
+const POOLS: &[u8] = b"pools";
+const HTTP_ASSETS: &[u8] = b"http_assets";
+
+type Tree = RbTree<&'static [u8], RbTree<Vec<u8>, Hash>>;
+
+thread_local! {
+    static TREE: RefCell<Tree> = RefCell::new(empty_tree());
+
+    // Pools whose statistics route is re-certified when the running batch
+    // ends, or None outside a batch
+    static STALE_POOLS: RefCell<Option<BTreeSet<u64>>> = RefCell::new(None);
+}
+
+fn empty_tree() -> Tree {
+    let mut tree = RbTree::new();
+    for label in [LOANS, PAYMENTS, POOLS, HTTP_ASSETS] {
+        tree.insert(label, RbTree::new());
+    }
+    tree
+}
+
+fn record_hash<T: CandidType>(record: &T) -> Hash {
+    Sha256::digest(Encode!(record).unwrap()).into()
+}
+
+fn insert<T: CandidType>(tree: &mut Tree, label: &'static [u8], id: u64, record: &T) {
+    tree.modify(label, |records| {
+        records.insert(id.to_be_bytes().to_vec(), record_hash(record))
+    });
+}
+
+// Certifies the body a route serves, or drops the route while it serves an
+// error
+fn insert_route(tree: &mut Tree, path: String, body: Result<Vec<u8>, Error>) {
+    tree.modify(HTTP_ASSETS, |routes| match body {
+        Ok(body) => routes.insert(path.into_bytes(), Sha256::digest(body).into()),
+        Err(_) => routes.delete(path.as_bytes()),
+    });
+}
+
+fn loan_path(loan_id: u64) -> String {
+    format!("/loans/{}", loan_id)
+}
+
+fn payment_path(payment_id: u64) -> String {
+    format!("/payments/{}", payment_id)
+}
+
+fn pool_path(pool_id: u64) -> String {
+    format!("/pools/{}", pool_id)
+}
+
+// Applies a change to the tree and certifies its new root hash
+fn update(change: impl FnOnce(&mut Tree)) {
+    TREE.with(|tree| {
+        let mut tree = tree.borrow_mut();
+        change(&mut tree);
+        ic_cdk::api::set_certified_data(&tree.root_hash());
+    });
+}
+
+pub(crate) fn certify_loan(loan: &Loan) {
+    update(|tree| {
+        insert(tree, LOANS, loan.id, loan);
+        insert_route(tree, loan_path(loan.id), http::loan_status(loan.id));
+    });
+    certify_pool_statistics(loan.pool_id);
+}
+
+pub(crate) fn certify_payment(payment: &Payment) {
+    update(|tree| {
+        insert(tree, PAYMENTS, payment.id, payment);
+        insert_route(tree, payment_path(payment.id), http::receipt(payment.id));
+    });
+}
+
+pub(crate) fn certify_pool(pool: &LoanPool) {
+    update(|tree| {
+        insert(tree, POOLS, pool.id, pool);
+        insert_route(tree, pool_path(pool.id), http::pool(pool.id));
+    });
+}
+
+// Re-certifies the statistics route of pools whose loans, positions or
+// valuation settings changed, once the running batch ends if there is one
+pub(crate) fn certify_pool_statistics(pool_ids: impl IntoIterator<Item = u64>) {
+    let pool_ids = STALE_POOLS.with(|stale| match stale.borrow_mut().as_mut() {
+        Some(stale) => {
+            stale.extend(pool_ids);
+            Vec::new()
+        }
+        None => pool_ids.into_iter().collect(),
+    });
+    if pool_ids.is_empty() {
+        return;
+    }
+    update(|tree| {
+        for pool_id in pool_ids {
+            insert_route(tree, pool_path(pool_id), http::pool(pool_id));
+        }
+    });
+}
+
+// Runs `work`, which stores many loans, re-certifying each affected pool's
+// statistics once at the end instead of after every loan
+pub(crate) fn batch<R>(work: impl FnOnce() -> R) -> R {
+    STALE_POOLS.with(|stale| *stale.borrow_mut() = Some(BTreeSet::new()));
+    let result = work();
+    let pool_ids = STALE_POOLS.with(|stale| stale.borrow_mut().take().unwrap_or_default());
+    certify_pool_statistics(pool_ids);
+    result
+}
+
+// Rebuilds the tree from every stored loan, payment and pool and certifies
+// its root hash
+pub(crate) fn rebuild() {
+    let mut tree = empty_tree();
+    crate::LOANS_STORAGE.with(|storage| {
+        for (id, loan) in storage.borrow().iter() {
+            insert(&mut tree, LOANS, id, &loan);
+            insert_route(&mut tree, loan_path(id), http::loan_status(id));
+        }
+    });
+    crate::PAYMENTS_STORAGE.with(|storage| {
+        for (id, payment) in storage.borrow().iter() {
+            insert(&mut tree, PAYMENTS, id, &payment);
+            insert_route(&mut tree, payment_path(id), http::receipt(id));
+        }
+    });
+    crate::LOAN_POOLS_STORAGE.with(|storage| {
+        for (id, pool) in storage.borrow().iter() {
+            insert(&mut tree, POOLS, id, &pool);
+            insert_route(&mut tree, pool_path(id), http::pool(id));
+        }
+    });
+    ic_cdk::api::set_certified_data(&tree.root_hash());
+    TREE.with(|cell| *cell.borrow_mut() = tree);
+}
+
+// CBOR encoding of a witness, with the self-describe tag the IC interface
+// specification uses for hash trees
+fn encode_witness(witness: HashTree) -> Vec<u8> {
+    let mut serializer = serde_cbor::Serializer::new(Vec::new());
+    serializer.self_describe().expect("Cannot encode a witness");
+    witness
+        .serialize(&mut serializer)
+        .expect("Cannot encode a witness");
+    serializer.into_inner()
+}
+
+// Encoded witness for one record
+fn witness(label: &'static [u8], id: u64) -> Vec<u8> {
+    TREE.with(|tree| {
+        let tree = tree.borrow();
+        encode_witness(tree.nested_witness(label, |records| records.witness(&id.to_be_bytes())))
+    })
+}
+
+fn certified<T>(label: &'static [u8], id: u64, data: T) -> Certified<T> {
+    Certified {
+        data,
+        certificate: ic_cdk::api::data_certificate(),
+        witness: witness(label, id),
+    }
+}
+
+pub(crate) fn certified_loan(view: LoanView) -> Certified<LoanView> {
+    let id = view.loan.id;
+    certified(LOANS, id, view)
+}
+
+pub(crate) fn certified_payment(view: PaymentView) -> Certified<PaymentView> {
+    let id = view.payment.id;
+    certified(PAYMENTS, id, view)
+}
+
+pub(crate) fn certified_pool(pool: LoanPool) -> Certified<LoanPool> {
+    let id = pool.id;
+    certified(POOLS, id, pool)
+}
+
+// Standard base64 with padding, as header values carry binary data
+fn base64(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut text = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        let group = chunk.iter().enumerate().fold(0u32, |group, (i, byte)| {
+            group | ((*byte as u32) << (16 - 8 * i))
+        });
+        for i in 0..4 {
+            if i <= chunk.len() {
+                text.push(ALPHABET[((group >> (18 - 6 * i)) & 63) as usize] as char);
+            } else {
+                text.push('=');
+            }
+        }
+    }
+    text
+}
+
+// IC-Certificate header proving the body of a certified route, or None when
+// the route is not certified or the call cannot return a certificate
+pub(crate) fn http_certificate_header(path: &str) -> Option<(String, String)> {
+    let certificate = ic_cdk::api::data_certificate()?;
+    let tree = TREE.with(|tree| {
+        let tree = tree.borrow();
+        let certified = tree
+            .get(HTTP_ASSETS)
+            .is_some_and(|routes| routes.get(path.as_bytes()).is_some());
+        certified.then(|| {
+            encode_witness(
+                tree.nested_witness(HTTP_ASSETS, |routes| routes.witness(path.as_bytes())),
+            )
+        })
+    })?;
+    Some((
+        "IC-Certificate".to_string(),
+        format!(
+            "certificate=:{}:, tree=:{}:",
+            base64(&certificate),
+            base64(&tree)
+        ),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn base64_matches_rfc_4648_vectors() {
+        let vectors = [
+            ("", ""),
+            ("f", "Zg=="),
+            ("fo", "Zm8="),
+            ("foo", "Zm9v"),
+            ("foob", "Zm9vYg=="),
+            ("fooba", "Zm9vYmE="),
+            ("foobar", "Zm9vYmFy"),
+        ];
+        for (input, expected) in vectors {
+            assert_eq!(base64(input.as_bytes()), expected);
+        }
+        assert_eq!(base64(&[0xFF, 0xEF]), "/+8=");
+    }
+}
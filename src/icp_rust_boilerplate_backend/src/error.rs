@@ -0,0 +1,182 @@
+use candid::CandidType;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+use crate::amount::{Amount, Currency};
+
+// Kind of record an error refers to
+#[derive(
+    CandidType, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug,
+)]
+pub enum EntityKind {
+    User,
+    Motorcycle,
+    Loan,
+    Installment,
+    Payment,
+    Investor,
+    LoanPool,
+    Order,
+    RecoveryCase,
+    PoolPosition,
+    KycRecord,
+    Trade,
+    LoanParticipation,
+    Configuration,
+}
+
+impl fmt::Display for EntityKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            EntityKind::User => "User",
+            EntityKind::Motorcycle => "Motorcycle",
+            EntityKind::Loan => "Loan",
+            EntityKind::Installment => "Installment",
+            EntityKind::Payment => "Payment",
+            EntityKind::Investor => "Investor",
+            EntityKind::LoanPool => "Loan pool",
+            EntityKind::Order => "Order",
+            EntityKind::RecoveryCase => "Recovery case",
+            EntityKind::PoolPosition => "Pool position",
+            EntityKind::KycRecord => "KYC record",
+            EntityKind::Trade => "Trade",
+            EntityKind::LoanParticipation => "Loan participation",
+            EntityKind::Configuration => "Configuration",
+        };
+        f.write_str(name)
+    }
+}
+
+// Error returned by every endpoint. Clients branch on the variant; the
+// context fields say which record and which input the error is about.
+#[derive(CandidType, Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub enum Error {
+    NotFound {
+        entity: EntityKind,
+        id: u64,
+    },
+    NoRecords {
+        entity: EntityKind,
+    },
+    AlreadyExists {
+        entity: EntityKind,
+        field: String,
+        msg: String,
+    },
+    InvalidPayload {
+        field: String,
+        msg: String,
+    },
+    Unauthorized {
+        msg: String,
+    },
+    InvalidState {
+        entity: EntityKind,
+        id: u64,
+        msg: String,
+    },
+    InvalidTransition {
+        entity: EntityKind,
+        id: u64,
+        from: String,
+        to: String,
+    },
+    InsufficientFunds {
+        entity: EntityKind,
+        id: u64,
+        available: Amount,
+        requested: Amount,
+    },
+    CurrencyMismatch {
+        expected: Currency,
+        found: Currency,
+    },
+    Arithmetic {
+        msg: String,
+    },
+    PaymentFailed {
+        msg: String,
+    },
+}
+
+impl Error {
+    pub(crate) fn not_found(entity: EntityKind, id: u64) -> Self {
+        Error::NotFound { entity, id }
+    }
+
+    pub(crate) fn invalid_payload(field: &str, msg: impl Into<String>) -> Self {
+        Error::InvalidPayload {
+            field: field.to_string(),
+            msg: msg.into(),
+        }
+    }
+
+    pub(crate) fn invalid_state(entity: EntityKind, id: u64, msg: impl Into<String>) -> Self {
+        Error::InvalidState {
+            entity,
+            id,
+            msg: msg.into(),
+        }
+    }
+
+    // Stable machine-readable code, also used as the prefix of the display text
+    pub fn code(&self) -> &'static str {
+        match self {
+            Error::NotFound { .. } => "NOT_FOUND",
+            Error::NoRecords { .. } => "NO_RECORDS",
+            Error::AlreadyExists { .. } => "ALREADY_EXISTS",
+            Error::InvalidPayload { .. } => "INVALID_PAYLOAD",
+            Error::Unauthorized { .. } => "UNAUTHORIZED",
+            Error::InvalidState { .. } => "INVALID_STATE",
+            Error::InvalidTransition { .. } => "INVALID_TRANSITION",
+            Error::InsufficientFunds { .. } => "INSUFFICIENT_FUNDS",
+            Error::CurrencyMismatch { .. } => "CURRENCY_MISMATCH",
+            Error::Arithmetic { .. } => "ARITHMETIC",
+            Error::PaymentFailed { .. } => "PAYMENT_FAILED",
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: ", self.code())?;
+        match self {
+            Error::NotFound { entity, id } => write!(f, "{} with ID {} not found", entity, id),
+            Error::NoRecords { entity } => write!(f, "No {} records found", entity),
+            Error::AlreadyExists { msg, .. }
+            | Error::InvalidPayload { msg, .. }
+            | Error::Unauthorized { msg }
+            | Error::Arithmetic { msg }
+            | Error::PaymentFailed { msg } => f.write_str(msg),
+            Error::InvalidState { entity, id, msg } => {
+                write!(f, "{} with ID {}: {}", entity, id, msg)
+            }
+            Error::InvalidTransition {
+                entity,
+                id,
+                from,
+                to,
+            } => write!(
+                f,
+                "{} with ID {} cannot move from {} to {}",
+                entity, id, from, to
+            ),
+            Error::InsufficientFunds {
+                entity,
+                id,
+                available,
+                requested,
+            } => write!(
+                f,
+                "{} with ID {} has {} available, {} requested",
+                entity, id, available, requested
+            ),
+            Error::CurrencyMismatch { expected, found } => write!(
+                f,
+                "Currency mismatch: expected {}, found {}",
+                expected.code(),
+                found.code()
+            ),
+        }
+    }
+}
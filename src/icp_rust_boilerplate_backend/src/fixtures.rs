@@ -0,0 +1,65 @@
+// Records shared by the unit tests
+use crate::amount::{Amount, Currency};
+use crate::models::*;
+use crate::schedule::{generate_schedule, NANOS_PER_DAY};
+
+pub(crate) const START: u64 = 19_700 * NANOS_PER_DAY;
+
+pub(crate) fn kes(minor_units: u64) -> Amount {
+    Amount::new(minor_units, Currency::Kes)
+}
+
+pub(crate) fn pool(funds: u64) -> LoanPool {
+    LoanPool {
+        id: 1,
+        name: "Nairobi riders".to_string(),
+        total_funds: kes(funds),
+        available_funds: kes(funds),
+        allocated_funds: kes(0),
+        total_shares: funds,
+        platform_fees: kes(0),
+    }
+}
+
+// A defaulted four-month loan with nothing repaid
+pub(crate) fn defaulted_loan(principal: u64) -> (Loan, Vec<Installment>) {
+    let installments = generate_schedule(
+        7,
+        kes(principal),
+        12.0,
+        4,
+        RepaymentFrequency::Monthly,
+        InterestMethod::Flat,
+        START,
+    )
+    .unwrap();
+    let loan = Loan {
+        id: 7,
+        borrower_id: 2,
+        motorcycle_id: 3,
+        principal_amount: kes(principal),
+        interest_rate: 12.0,
+        interest_method: InterestMethod::Flat,
+        frequency: RepaymentFrequency::Monthly,
+        term: 4,
+        installment_amount: installments[0].total_due,
+        total_interest: kes(principal / 25),
+        total_paid: kes(0),
+        principal_paid: kes(0),
+        interest_paid: kes(0),
+        penalties_paid: kes(0),
+        penalties_due: kes(0),
+        interest_accrued: kes(0),
+        days_past_due: 120,
+        last_accrued_on: None,
+        start_date: START,
+        end_date: installments[3].due_date,
+        status: LoanStatus::Defaulted,
+        disbursement_block_index: Some(1),
+        pool_id: None,
+        decision_reason: None,
+        decided_by: None,
+        decided_at: None,
+    };
+    (loan, installments)
+}
@@ -0,0 +1,408 @@
+// Read-only HTTP gateway: JSON over plain HTTP for clients without an agent
+// library.
+//
+//   GET /loans/{id}            status and balances of a loan
+//   GET /loans/{id}/payments   receipts of a loan's payments (status, cursor, limit, order)
+//   GET /payments/{id}         receipt of a payment
+//   GET /pools                 statistics of every pool (currency, cursor, limit, order)
+//   GET /pools/{id}            statistics of a pool
+//
+// Gateway calls are anonymous, so these routes are public. They carry loan
+// balances and statuses but no borrower or investor details. Responses of the
+// per-record routes carry an IC-Certificate header; see certification.rs.
+use serde::Serialize;
+
+use crate::amount::{Amount, Currency};
+use crate::models::*;
+use crate::paging::paginate;
+use crate::schedule::format_timestamp;
+use crate::{certification, index, EntityKind, Error};
+
+// Amount in minor units, with its currency code and display text
+#[derive(Serialize)]
+struct Money {
+    minor_units: u64,
+    currency: &'static str,
+    text: String,
+}
+
+impl From<Amount> for Money {
+    fn from(amount: Amount) -> Self {
+        Money {
+            minor_units: amount.minor_units,
+            currency: amount.currency.code(),
+            text: amount.to_string(),
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct LoanStatusBody {
+    id: u64,
+    status: LoanStatus,
+    principal_amount: Money,
+    total_paid: Money,
+    outstanding_principal: Money,
+    penalties_due: Money,
+    installment_amount: Money,
+    days_past_due: u32,
+    next_due_date: Option<String>,
+    next_amount_due: Option<Money>,
+    end_date: String,
+}
+
+#[derive(Serialize)]
+struct AllocationBody {
+    penalties: Money,
+    interest: Money,
+    principal: Money,
+    remaining_balance: Money,
+}
+
+#[derive(Serialize)]
+struct ReceiptBody {
+    id: u64,
+    loan_id: u64,
+    amount: Money,
+    status: PaymentStatus,
+    date: String,
+    allocation: Option<AllocationBody>,
+    ledger_block_index: Option<u64>,
+    refund_block_index: Option<u64>,
+    failure_code: Option<String>,
+}
+
+impl From<Payment> for ReceiptBody {
+    fn from(payment: Payment) -> Self {
+        ReceiptBody {
+            id: payment.id,
+            loan_id: payment.loan_id,
+            amount: payment.amount.into(),
+            status: payment.status,
+            date: format_timestamp(payment.date),
+            allocation: payment.allocation.map(|allocation| AllocationBody {
+                penalties: allocation.penalties.into(),
+                interest: allocation.interest.into(),
+                principal: allocation.principal.into(),
+                remaining_balance: allocation.remaining_balance.into(),
+            }),
+            ledger_block_index: payment.ledger_block_index,
+            refund_block_index: payment.refund_block_index,
+            failure_code: payment.failure_reason.as_deref().map(failure_code),
+        }
+    }
+}
+
+// Error code a payment's failure reason starts with, as in `Error::code`. The
+// rest of the reason can name other records, so receipts only carry the code.
+fn failure_code(reason: &str) -> String {
+    match reason.split_once(':') {
+        Some((code, _))
+            if !code.is_empty() && code.chars().all(|c| c.is_ascii_uppercase() || c == '_') =>
+        {
+            code.to_string()
+        }
+        _ => "UNKNOWN".to_string(),
+    }
+}
+
+#[derive(Serialize)]
+struct PoolStatisticsBody {
+    id: u64,
+    name: String,
+    total_funds: Money,
+    available_funds: Money,
+    allocated_funds: Money,
+    net_asset_value: Money,
+    total_shares: u64,
+    loans: usize,
+    investors: usize,
+}
+
+// Status code for an error returned by a route
+fn status_code(error: &Error) -> u16 {
+    match error {
+        Error::NotFound { .. } | Error::NoRecords { .. } => 404,
+        Error::InvalidPayload { .. } | Error::CurrencyMismatch { .. } => 400,
+        Error::Unauthorized { .. } => 403,
+        Error::AlreadyExists { .. }
+        | Error::InvalidState { .. }
+        | Error::InvalidTransition { .. }
+        | Error::InsufficientFunds { .. } => 409,
+        Error::Arithmetic { .. } | Error::PaymentFailed { .. } => 500,
+    }
+}
+
+fn json_response(status_code: u16, body: Vec<u8>, head: bool) -> HttpResponse {
+    HttpResponse {
+        status_code,
+        headers: vec![
+            (
+                "Content-Type".to_string(),
+                "application/json; charset=utf-8".to_string(),
+            ),
+            ("Content-Length".to_string(), body.len().to_string()),
+            ("Access-Control-Allow-Origin".to_string(), "*".to_string()),
+        ],
+        body: if head { Vec::new() } else { body },
+    }
+}
+
+// Error body: the error's code, as in `Error::code`, and its message
+fn error_response(status_code: u16, code: &str, message: String, head: bool) -> HttpResponse {
+    let body = serde_json::json!({ "code": code, "message": message });
+    json_response(status_code, body.to_string().into_bytes(), head)
+}
+
+fn to_json<T: Serialize>(body: &T) -> Vec<u8> {
+    serde_json::to_vec(body).expect("Cannot encode a response as JSON")
+}
+
+fn parse_number<T: std::str::FromStr>(field: &str, value: &str) -> Result<T, Error> {
+    value
+        .parse()
+        .map_err(|_| Error::invalid_payload(field, format!("{} is not a valid number", value)))
+}
+
+fn unknown_parameter(name: &str) -> Error {
+    Error::invalid_payload(name, format!("Unknown query parameter {}", name))
+}
+
+// Splits the paging parameters off a query string and returns the rest for
+// the route's filters
+fn page_request(query: &str) -> Result<(PageRequest, Vec<(&str, &str)>), Error> {
+    let mut page = PageRequest::default();
+    let mut filters = Vec::new();
+    for pair in query.split('&').filter(|pair| !pair.is_empty()) {
+        let (name, value) = pair.split_once('=').unwrap_or((pair, ""));
+        match name {
+            "cursor" => page.cursor = Some(parse_number(name, value)?),
+            "limit" => page.limit = Some(parse_number(name, value)?),
+            "order" => {
+                page.order = Some(match value.to_ascii_lowercase().as_str() {
+                    "asc" | "ascending" => SortOrder::Ascending,
+                    "desc" | "descending" => SortOrder::Descending,
+                    _ => return Err(Error::invalid_payload(name, "Order must be asc or desc")),
+                })
+            }
+            _ => filters.push((name, value)),
+        }
+    }
+    Ok((page, filters))
+}
+
+fn no_parameters(query: &str) -> Result<(), Error> {
+    match query.split('&').find(|pair| !pair.is_empty()) {
+        Some(pair) => Err(unknown_parameter(
+            pair.split_once('=').map_or(pair, |(name, _)| name),
+        )),
+        None => Ok(()),
+    }
+}
+
+pub(crate) fn loan_status(loan_id: u64) -> Result<Vec<u8>, Error> {
+    let loan = crate::load_loan(loan_id)?;
+    let next = crate::loan_installments(loan_id)
+        .into_iter()
+        .find(|installment| installment.status != InstallmentStatus::Paid);
+    let next_amount_due = match &next {
+        Some(installment) => {
+            let paid = installment
+                .principal_paid
+                .checked_add(&installment.interest_paid)?;
+            Some(
+                installment
+                    .total_due
+                    .checked_sub(&paid.min(&installment.total_due))?
+                    .into(),
+            )
+        }
+        None => None,
+    };
+    let outstanding_principal = crate::pool::outstanding_principal(&loan)?;
+    Ok(to_json(&LoanStatusBody {
+        id: loan.id,
+        status: loan.status,
+        principal_amount: loan.principal_amount.into(),
+        total_paid: loan.total_paid.into(),
+        outstanding_principal: outstanding_principal.into(),
+        penalties_due: loan.penalties_due.into(),
+        installment_amount: loan.installment_amount.into(),
+        days_past_due: loan.days_past_due,
+        next_due_date: next.map(|installment| format_timestamp(installment.due_date)),
+        next_amount_due,
+        end_date: format_timestamp(loan.end_date),
+    }))
+}
+
+fn loan_receipts(loan_id: u64, query: &str) -> Result<Vec<u8>, Error> {
+    crate::load_loan(loan_id)?;
+    let (page, filters) = page_request(query)?;
+    let mut status = None;
+    for (name, value) in filters {
+        match name {
+            "status" => {
+                status = Some(match value.to_ascii_lowercase().as_str() {
+                    "pending" => PaymentStatus::Pending,
+                    "completed" => PaymentStatus::Completed,
+                    "failed" => PaymentStatus::Failed,
+                    _ => {
+                        return Err(Error::invalid_payload(
+                            name,
+                            "Status must be pending, completed or failed",
+                        ))
+                    }
+                })
+            }
+            _ => return Err(unknown_parameter(name)),
+        }
+    }
+    let receipts = crate::PAYMENTS_STORAGE.with(|storage| {
+        let storage = storage.borrow();
+        let payments = index::loan_payment_ids(loan_id)
+            .into_iter()
+            .filter_map(|payment_id| {
+                storage
+                    .get(&payment_id)
+                    .map(|payment| (payment_id, payment))
+            });
+        paginate(
+            payments,
+            &page,
+            |payment| status.map_or(true, |status| payment.status == status),
+            ReceiptBody::from,
+        )
+    })?;
+    Ok(to_json(&receipts))
+}
+
+pub(crate) fn receipt(payment_id: u64) -> Result<Vec<u8>, Error> {
+    let payment = crate::PAYMENTS_STORAGE
+        .with(|storage| storage.borrow().get(&payment_id))
+        .ok_or_else(|| Error::not_found(EntityKind::Payment, payment_id))?;
+    Ok(to_json(&ReceiptBody::from(payment)))
+}
+
+fn pool_statistics(pool: LoanPool) -> Result<PoolStatisticsBody, Error> {
+    Ok(PoolStatisticsBody {
+        id: pool.id,
+        net_asset_value: crate::pool_nav(&pool)?.into(),
+        loans: crate::pool_loan_ids(pool.id).len(),
+        investors: crate::pool_investor_ids(pool.id).len(),
+        name: pool.name,
+        total_funds: pool.total_funds.into(),
+        available_funds: pool.available_funds.into(),
+        allocated_funds: pool.allocated_funds.into(),
+        total_shares: pool.total_shares,
+    })
+}
+
+fn pools(query: &str) -> Result<Vec<u8>, Error> {
+    let (page, filters) = page_request(query)?;
+    let mut currency = None;
+    for (name, value) in filters {
+        match name {
+            "currency" => {
+                currency = Some(
+                    [Currency::Kes, Currency::Ugx, Currency::Tzs, Currency::Usd]
+                        .into_iter()
+                        .find(|currency| currency.code().eq_ignore_ascii_case(value))
+                        .ok_or_else(|| {
+                            Error::invalid_payload(
+                                name,
+                                format!("{} is not a supported currency", value),
+                            )
+                        })?,
+                )
+            }
+            _ => return Err(unknown_parameter(name)),
+        }
+    }
+    let pools = crate::LOAN_POOLS_STORAGE.with(|storage| {
+        paginate(
+            storage.borrow().iter(),
+            &page,
+            |pool| currency.map_or(true, |currency| pool.total_funds.currency == currency),
+            |pool| pool,
+        )
+    })?;
+    let statistics = Page {
+        items: pools
+            .items
+            .into_iter()
+            .map(pool_statistics)
+            .collect::<Result<Vec<_>, Error>>()?,
+        total: pools.total,
+        next_cursor: pools.next_cursor,
+    };
+    Ok(to_json(&statistics))
+}
+
+pub(crate) fn pool(pool_id: u64) -> Result<Vec<u8>, Error> {
+    let pool = crate::LOAN_POOLS_STORAGE
+        .with(|storage| storage.borrow().get(&pool_id))
+        .ok_or_else(|| Error::not_found(EntityKind::LoanPool, pool_id))?;
+    Ok(to_json(&pool_statistics(pool)?))
+}
+
+// Routes a gateway request. Only GET and HEAD are served.
+pub(crate) fn handle(request: HttpRequest) -> HttpResponse {
+    let head = request.method.eq_ignore_ascii_case("HEAD");
+    if !head && !request.method.eq_ignore_ascii_case("GET") {
+        let mut response = error_response(
+            405,
+            "METHOD_NOT_ALLOWED",
+            format!("Method {} is not allowed", request.method),
+            false,
+        );
+        response
+            .headers
+            .push(("Allow".to_string(), "GET, HEAD".to_string()));
+        return response;
+    }
+
+    let (path, query) = request
+        .url
+        .split_once('?')
+        .unwrap_or((request.url.as_str(), ""));
+    let segments: Vec<&str> = path
+        .split('/')
+        .filter(|segment| !segment.is_empty())
+        .collect();
+    // The per-record routes are certified; listings are not
+    let (result, certified) = match segments.as_slice() {
+        ["loans", id] => (
+            parse_number("id", id)
+                .and_then(|id| no_parameters(query).and_then(|_| loan_status(id))),
+            true,
+        ),
+        ["loans", id, "payments"] => (
+            parse_number("id", id).and_then(|id| loan_receipts(id, query)),
+            false,
+        ),
+        ["payments", id] => (
+            parse_number("id", id).and_then(|id| no_parameters(query).and_then(|_| receipt(id))),
+            true,
+        ),
+        ["pools"] => (pools(query), false),
+        ["pools", id] => (
+            parse_number("id", id).and_then(|id| no_parameters(query).and_then(|_| pool(id))),
+            true,
+        ),
+        _ => {
+            return error_response(404, "NOT_FOUND", format!("No route for {}", path), head);
+        }
+    };
+    match result {
+        Ok(body) => {
+            let mut response = json_response(200, body, head);
+            if certified && !head {
+                response
+                    .headers
+                    .extend(certification::http_certificate_header(path));
+            }
+            response
+        }
+        Err(error) => error_response(status_code(&error), error.code(), error.to_string(), head),
+    }
+}
@@ -0,0 +1,192 @@
+use candid::Principal;
+
+use crate::models::*;
+
+// Longest email the email index can hold, the limit set by RFC 5321
+pub(crate) const MAX_EMAIL_LEN: usize = 254;
+
+fn email_key(email: &str) -> Option<EmailKey> {
+    (email.len() <= MAX_EMAIL_LEN).then(|| EmailKey(email.to_string()))
+}
+
+// Updates the user indexes after `user` was stored over `previous`
+pub(crate) fn index_user(previous: Option<&User>, user: &User) {
+    crate::USERS_BY_EMAIL.with(|storage| {
+        let mut storage = storage.borrow_mut();
+        if let Some(key) = previous.and_then(|previous| email_key(&previous.email)) {
+            storage.remove(&key);
+        }
+        // Only users stored before registration checked the length can have a
+        // longer email; they are left out of the index
+        if let Some(key) = email_key(&user.email) {
+            storage.insert(key, user.id);
+        }
+    });
+    let key = OwnerUserKey {
+        owner: user.owner,
+        user_id: user.id,
+    };
+    crate::USERS_BY_OWNER.with(|storage| storage.borrow_mut().insert(key, ()));
+}
+
+// Updates the investor index after `investor` was stored; an investor's
+// owner never changes
+pub(crate) fn index_investor(investor: &Investor) {
+    let key = OwnerInvestorKey {
+        owner: investor.owner,
+        investor_id: investor.id,
+    };
+    crate::INVESTORS_BY_OWNER.with(|storage| storage.borrow_mut().insert(key, ()));
+}
+
+// Updates the loan indexes after `loan` was stored over `previous`
+pub(crate) fn index_loan(previous: Option<&Loan>, loan: &Loan) {
+    crate::LOANS_BY_STATUS.with(|storage| {
+        let mut storage = storage.borrow_mut();
+        if let Some(previous) = previous {
+            storage.remove(&StatusLoanKey {
+                status: previous.status,
+                loan_id: previous.id,
+            });
+        }
+        storage.insert(
+            StatusLoanKey {
+                status: loan.status,
+                loan_id: loan.id,
+            },
+            (),
+        );
+    });
+    let key = BorrowerLoanKey {
+        borrower_id: loan.borrower_id,
+        loan_id: loan.id,
+    };
+    crate::LOANS_BY_BORROWER.with(|storage| storage.borrow_mut().insert(key, ()));
+}
+
+pub(crate) fn index_payment(payment: &Payment) {
+    let key = LoanPaymentKey {
+        loan_id: payment.loan_id,
+        payment_id: payment.id,
+    };
+    crate::PAYMENTS_BY_LOAN.with(|storage| storage.borrow_mut().insert(key, ()));
+}
+
+// ID of the user registered with `email`
+pub(crate) fn user_by_email(email: &str) -> Option<u64> {
+    let key = email_key(email)?;
+    crate::USERS_BY_EMAIL.with(|storage| storage.borrow().get(&key))
+}
+
+// IDs of the users owned by a principal, oldest first
+pub(crate) fn users_by_owner(owner: Principal) -> Vec<u64> {
+    crate::USERS_BY_OWNER.with(|storage| {
+        storage
+            .borrow()
+            .range(OwnerUserKey { owner, user_id: 0 }..)
+            .take_while(|(key, _)| key.owner == owner)
+            .map(|(key, _)| key.user_id)
+            .collect()
+    })
+}
+
+// ID of the first investor a principal registered
+pub(crate) fn investor_by_owner(owner: Principal) -> Option<u64> {
+    crate::INVESTORS_BY_OWNER.with(|storage| {
+        storage
+            .borrow()
+            .range(
+                OwnerInvestorKey {
+                    owner,
+                    investor_id: 0,
+                }..,
+            )
+            .next()
+            .filter(|(key, _)| key.owner == owner)
+            .map(|(key, _)| key.investor_id)
+    })
+}
+
+// IDs of a borrower's loans, in loan order
+pub(crate) fn borrower_loan_ids(borrower_id: u64) -> Vec<u64> {
+    crate::LOANS_BY_BORROWER.with(|storage| {
+        storage
+            .borrow()
+            .range(
+                BorrowerLoanKey {
+                    borrower_id,
+                    loan_id: 0,
+                }..,
+            )
+            .take_while(|(key, _)| key.borrower_id == borrower_id)
+            .map(|(key, _)| key.loan_id)
+            .collect()
+    })
+}
+
+// IDs of the loans in a status, in loan order
+pub(crate) fn status_loan_ids(status: LoanStatus) -> Vec<u64> {
+    crate::LOANS_BY_STATUS.with(|storage| {
+        storage
+            .borrow()
+            .range(StatusLoanKey { status, loan_id: 0 }..)
+            .take_while(|(key, _)| key.status == status)
+            .map(|(key, _)| key.loan_id)
+            .collect()
+    })
+}
+
+// IDs of the payments made on a loan, in payment order
+pub(crate) fn loan_payment_ids(loan_id: u64) -> Vec<u64> {
+    crate::PAYMENTS_BY_LOAN.with(|storage| {
+        storage
+            .borrow()
+            .range(
+                LoanPaymentKey {
+                    loan_id,
+                    payment_id: 0,
+                }..,
+            )
+            .take_while(|(key, _)| key.loan_id == loan_id)
+            .map(|(key, _)| key.payment_id)
+            .collect()
+    })
+}
+
+// Indexes every stored investor
+pub(crate) fn rebuild_investors() {
+    let investors: Vec<Investor> = crate::INVESTORS_STORAGE.with(|storage| {
+        storage
+            .borrow()
+            .iter()
+            .map(|(_, investor)| investor)
+            .collect()
+    });
+    for investor in &investors {
+        index_investor(investor);
+    }
+}
+
+// Indexes every stored user, loan and payment
+pub(crate) fn rebuild() {
+    let users: Vec<User> = crate::USERS_STORAGE
+        .with(|storage| storage.borrow().iter().map(|(_, user)| user).collect());
+    for user in &users {
+        index_user(None, user);
+    }
+    let loans: Vec<Loan> = crate::LOANS_STORAGE
+        .with(|storage| storage.borrow().iter().map(|(_, loan)| loan).collect());
+    for loan in &loans {
+        index_loan(None, loan);
+    }
+    let payments: Vec<Payment> = crate::PAYMENTS_STORAGE.with(|storage| {
+        storage
+            .borrow()
+            .iter()
+            .map(|(_, payment)| payment)
+            .collect()
+    });
+    for payment in &payments {
+        index_payment(payment);
+    }
+}
@@ -0,0 +1,173 @@
+use crate::models::*;
+use crate::{EntityKind, Error};
+
+const MAX_NATIONAL_ID_LEN: usize = 32;
+const MAX_REASON_LEN: usize = 256;
+
+// Characters of a masked value left visible, from its end
+const VISIBLE_SUFFIX: usize = 4;
+const REDACTED: &str = "[redacted]";
+
+// Events that move a KYC record between states
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub(crate) enum KycEvent {
+    Submit, // The user submitted or resubmitted their details
+    Verify,
+    Reject,
+}
+
+// Allowed transitions: a rejected submission may be corrected and
+// resubmitted; a verified one is final
+fn next_status(status: KycStatus, event: KycEvent) -> Option<KycStatus> {
+    use KycEvent as E;
+    use KycStatus as S;
+    match (status, event) {
+        (S::Unverified | S::Rejected, E::Submit) => Some(S::Pending),
+        (S::Pending, E::Verify) => Some(S::Verified),
+        (S::Pending, E::Reject) => Some(S::Rejected),
+        _ => None,
+    }
+}
+
+fn target_status(event: KycEvent) -> KycStatus {
+    match event {
+        KycEvent::Submit => KycStatus::Pending,
+        KycEvent::Verify => KycStatus::Verified,
+        KycEvent::Reject => KycStatus::Rejected,
+    }
+}
+
+// Moves a KYC record to the state `event` leads to, rejecting events its
+// current state does not allow
+pub(crate) fn apply_event(record: &mut KycRecord, event: KycEvent) -> Result<(), Error> {
+    match next_status(record.status, event) {
+        Some(status) => {
+            record.status = status;
+            Ok(())
+        }
+        None => Err(Error::InvalidTransition {
+            entity: EntityKind::KycRecord,
+            id: record.user_id,
+            from: format!("{:?}", record.status),
+            to: format!("{:?}", target_status(event)),
+        }),
+    }
+}
+
+// Checks a submission and normalizes it: surrounding whitespace is dropped
+// and the national ID and document hash are case-folded
+pub(crate) fn validate_submission(payload: SubmitKycPayload) -> Result<SubmitKycPayload, Error> {
+    let national_id = payload.national_id.trim().to_ascii_uppercase();
+    if national_id.is_empty()
+        || national_id.len() > MAX_NATIONAL_ID_LEN
+        || !national_id
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-')
+    {
+        return Err(Error::invalid_payload(
+            "national_id",
+            format!(
+                "National ID must be 1 to {} letters, digits or dashes",
+                MAX_NATIONAL_ID_LEN
+            ),
+        ));
+    }
+
+    let phone = payload.phone.trim().to_string();
+    let digits = phone.strip_prefix('+').unwrap_or_default();
+    if !(8..=15).contains(&digits.len())
+        || digits.starts_with('0')
+        || !digits.chars().all(|c| c.is_ascii_digit())
+    {
+        return Err(Error::invalid_payload(
+            "phone",
+            "Phone must be in E.164 format, e.g. +254712345678",
+        ));
+    }
+
+    let document_hash = payload.document_hash.trim().to_ascii_lowercase();
+    if document_hash.len() != 64 || !document_hash.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err(Error::invalid_payload(
+            "document_hash",
+            "Document hash must be a hex-encoded SHA-256 digest",
+        ));
+    }
+
+    Ok(SubmitKycPayload {
+        user_id: payload.user_id,
+        national_id,
+        phone,
+        document_hash,
+    })
+}
+
+pub(crate) fn validate_reason(reason: &str) -> Result<(), Error> {
+    if reason.trim().is_empty() {
+        Err(Error::invalid_payload(
+            "reason",
+            "A rejection needs a reason",
+        ))
+    } else if reason.len() > MAX_REASON_LEN {
+        Err(Error::invalid_payload(
+            "reason",
+            format!("Reason is longer than {} bytes", MAX_REASON_LEN),
+        ))
+    } else {
+        Ok(())
+    }
+}
+
+// KYC status of a user; users who never submitted their details are
+// Unverified
+pub(crate) fn status(user_id: u64) -> KycStatus {
+    crate::KYC_STORAGE
+        .with(|storage| storage.borrow().get(&user_id))
+        .map_or(KycStatus::Unverified, |record| record.status)
+}
+
+// Loans are only originated for borrowers whose identity was verified
+pub(crate) fn ensure_verified(user_id: u64) -> Result<(), Error> {
+    match status(user_id) {
+        KycStatus::Verified => Ok(()),
+        status => Err(Error::invalid_state(
+            EntityKind::User,
+            user_id,
+            format!("Borrower KYC is {:?}, expected Verified", status),
+        )),
+    }
+}
+
+// Replaces all but the last few characters of a value with asterisks
+fn mask(value: &str) -> String {
+    let count = value.chars().count();
+    value
+        .chars()
+        .enumerate()
+        .map(|(i, c)| if i + VISIBLE_SUFFIX < count { '*' } else { c })
+        .collect()
+}
+
+// A user record without contact details, for callers that may see the user
+// but not their personal data
+pub(crate) fn redact_user(user: User) -> User {
+    let email = match user.email.split_once('@') {
+        Some((_, domain)) => format!("***@{}", domain),
+        None => REDACTED.to_string(),
+    };
+    User {
+        email,
+        address: REDACTED.to_string(),
+        ..user
+    }
+}
+
+// A KYC record with its identifiers masked and the document hash removed.
+// The status and review details stay visible.
+pub(crate) fn redact_record(record: KycRecord) -> KycRecord {
+    KycRecord {
+        national_id: mask(&record.national_id),
+        phone: mask(&record.phone),
+        document_hash: REDACTED.to_string(),
+        ..record
+    }
+}
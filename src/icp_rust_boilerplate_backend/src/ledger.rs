@@ -0,0 +1,117 @@
+use candid::{Nat, Principal};
+use icrc_ledger_types::icrc1::account::Account;
+use icrc_ledger_types::icrc1::transfer::{Memo, TransferArg, TransferError};
+use icrc_ledger_types::icrc2::transfer_from::{TransferFromArgs, TransferFromError};
+
+use crate::amount::Amount;
+use crate::models::LedgerConfig;
+use crate::Error;
+
+// Ledger configuration, or an error if no ledger canister has been set
+fn configured_ledger() -> Result<(Principal, LedgerConfig), Error> {
+    let config = crate::LEDGER_CONFIG.with(|cell| cell.borrow().get().clone());
+    match config.ledger_canister_id {
+        Some(ledger_canister_id) => Ok((ledger_canister_id, config)),
+        None => Err(Error::PaymentFailed {
+            msg: "Ledger canister is not configured".to_string(),
+        }),
+    }
+}
+
+// Converts an amount in minor units into the ledger's token base units
+fn to_ledger_units(config: &LedgerConfig, amount: &Amount) -> Result<Nat, Error> {
+    if amount.currency != config.currency {
+        return Err(Error::CurrencyMismatch {
+            expected: config.currency,
+            found: amount.currency,
+        });
+    }
+    let shift = (config.token_decimals as u32)
+        .checked_sub(amount.currency.decimals())
+        .ok_or_else(|| Error::PaymentFailed {
+            msg: "Ledger token has fewer decimals than its currency".to_string(),
+        })?;
+    10u128
+        .checked_pow(shift)
+        .and_then(|scale| (amount.minor_units as u128).checked_mul(scale))
+        .map(Nat::from)
+        .ok_or_else(|| Error::Arithmetic {
+            msg: "Amount overflow".to_string(),
+        })
+}
+
+fn block_index_to_u64(block_index: Nat) -> Result<u64, Error> {
+    match block_index.0.to_u64_digits().as_slice() {
+        [] => Ok(0),
+        [index] => Ok(*index),
+        _ => Err(Error::PaymentFailed {
+            msg: "Ledger block index does not fit in 64 bits".to_string(),
+        }),
+    }
+}
+
+// Pulls `amount` from `from` into this canister's account using an ICRC-2
+// allowance the payer granted beforehand. Returns the ledger block index.
+pub(crate) async fn transfer_from(
+    from: Principal,
+    amount: &Amount,
+    memo: u64,
+) -> Result<u64, Error> {
+    transfer_from_to(from, ic_cdk::id(), amount, memo).await
+}
+
+// Moves `amount` from `from` straight to `to` using an ICRC-2 allowance `from`
+// granted this canister. Returns the ledger block index.
+pub(crate) async fn transfer_from_to(
+    from: Principal,
+    to: Principal,
+    amount: &Amount,
+    memo: u64,
+) -> Result<u64, Error> {
+    let (ledger_canister_id, config) = configured_ledger()?;
+    let args = TransferFromArgs {
+        spender_subaccount: None,
+        from: Account::from(from),
+        to: Account::from(to),
+        amount: to_ledger_units(&config, amount)?,
+        fee: None,
+        memo: Some(Memo::from(memo)),
+        created_at_time: None,
+    };
+
+    let (result,): (Result<Nat, TransferFromError>,) =
+        ic_cdk::call(ledger_canister_id, "icrc2_transfer_from", (args,))
+            .await
+            .map_err(|(code, msg)| Error::PaymentFailed {
+                msg: format!("Ledger call failed ({:?}): {}", code, msg),
+            })?;
+    let block_index = result.map_err(|e| Error::PaymentFailed {
+        msg: format!("Ledger rejected transfer: {:?}", e),
+    })?;
+    block_index_to_u64(block_index)
+}
+
+// Sends `amount` from this canister's account to `to` with an ICRC-1
+// transfer. Returns the ledger block index.
+pub(crate) async fn transfer(to: Principal, amount: &Amount, memo: u64) -> Result<u64, Error> {
+    let (ledger_canister_id, config) = configured_ledger()?;
+    let args = TransferArg {
+        from_subaccount: None,
+        to: Account::from(to),
+        fee: None,
+        created_at_time: None,
+        memo: Some(Memo::from(memo)),
+        amount: to_ledger_units(&config, amount)?,
+    };
+
+    let (result,): (Result<Nat, TransferError>,) =
+        ic_cdk::call(ledger_canister_id, "icrc1_transfer", (args,))
+            .await
+            .map_err(|(code, msg)| Error::PaymentFailed {
+                msg: format!("Ledger call failed ({:?}): {}", code, msg),
+            })?;
+    let block_index = result.map_err(|e| Error::PaymentFailed {
+        msg: format!("Ledger rejected transfer: {:?}", e),
+    })?;
+    block_index_to_u64(block_index)
+}
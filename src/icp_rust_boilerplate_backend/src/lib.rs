@@ -1,11 +1,17 @@
 #[macro_use]
 extern crate serde;
-use candid::{Decode, Encode, Principal};
+use candid::{Nat, Principal};
 use ic_cdk::api::caller;
+use ic_cdk_timers::TimerId;
 use ic_stable_structures::memory_manager::{MemoryId, MemoryManager, VirtualMemory};
-use ic_stable_structures::{BoundedStorable, Cell, DefaultMemoryImpl, StableBTreeMap, Storable};
+use ic_stable_structures::{
+    BoundedStorable, Cell, DefaultMemoryImpl, Log, StableBTreeMap, Storable,
+};
+use icrc_ledger_types::icrc::generic_metadata_value::MetadataValue;
+use icrc_ledger_types::icrc1::account::Account;
+use icrc_ledger_types::icrc1::transfer::{TransferArg, TransferError};
 use regex::Regex;
-use std::{borrow::Cow, cell::RefCell};
+use std::{borrow::Cow, cell::RefCell, collections::BTreeSet, time::Duration};
 
 // Memory Management
 type Memory = VirtualMemory<DefaultMemoryImpl>;
@@ -15,17 +21,69 @@ type IdCell = Cell<u64, Memory>;
 mod models;
 use models::*;
 
-// Define an Error enum for handling errors
-#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
-pub enum Error {
-    Success { msg: String },
-    Error { msg: String },
-    NotFound { msg: String },
-    InvalidPayload { msg: String },
-    Unauthorized { msg: String },
-    PaymentFailed { msg: String },
-    PaymentCompleted { msg: String },
-}
+mod amount;
+use amount::*;
+
+mod schedule;
+use schedule::{day_start, format_timestamp, generate_schedule, parse_timestamp, NANOS_PER_DAY};
+
+mod settlement;
+use settlement::{apply_payment, Settlement};
+
+mod migration;
+use migration::*;
+
+mod ledger;
+
+mod accrual;
+use accrual::accrue_loan;
+
+mod access;
+use access::{authenticated_caller, authorize, resolve_caller, Caller, Permission};
+
+mod returns;
+use returns::{distribute_income, Distribution};
+
+mod pool;
+use pool::{
+    check_pool_invariants, credit_position, debit_position, empty_position, funds_loan,
+    net_asset_value, reserve_capital, settle_capital, shares_for_amount,
+};
+
+mod share_token;
+
+mod market;
+
+mod motorcycle;
+use motorcycle::{apply_event, manual_transition, MotorcycleEvent};
+
+mod loan;
+use loan::{apply_event as apply_loan_event, LoanEvent};
+
+mod recovery;
+use recovery::{apply_recovery, ensure_stage};
+
+mod views;
+
+mod error;
+use error::{EntityKind, Error};
+
+mod index;
+
+mod paging;
+use paging::{in_range, paginate};
+
+mod audit;
+
+mod certification;
+
+mod http;
+
+mod kyc;
+use kyc::KycEvent;
+
+#[cfg(test)]
+mod fixtures;
 
 // Thread-local storage
 thread_local! {
@@ -67,16 +125,154 @@ thread_local! {
         StableBTreeMap::init(
             MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(6))))
     );
+
+    static INSTALLMENTS_STORAGE: RefCell<StableBTreeMap<InstallmentKey, Installment, Memory>> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(7))))
+    );
+
+    static LEDGER_CONFIG: RefCell<Cell<LedgerConfig, Memory>> = RefCell::new(
+        Cell::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(8))), LedgerConfig::default())
+            .expect("Cannot create the ledger configuration")
+    );
+
+    static ACCRUAL_CONFIG: RefCell<Cell<AccrualConfig, Memory>> = RefCell::new(
+        Cell::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(9))), AccrualConfig::default())
+            .expect("Cannot create the accrual configuration")
+    );
+
+    static ACCESS_CONFIG: RefCell<Cell<AccessConfig, Memory>> = RefCell::new(
+        Cell::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(10))), AccessConfig::default())
+            .expect("Cannot create the access configuration")
+    );
+
+    static FEE_CONFIG: RefCell<Cell<FeeConfig, Memory>> = RefCell::new(
+        Cell::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(12))), FeeConfig::default())
+            .expect("Cannot create the fee configuration")
+    );
+
+    // Memory 13 held the share token configuration, which selected the one
+    // pool the ICRC-1 endpoints served; it is left unused
+
+    static POOL_POSITIONS_STORAGE: RefCell<StableBTreeMap<PositionKey, PoolPosition, Memory>> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(11))))
+    );
+
+    static ORDERS_STORAGE: RefCell<StableBTreeMap<u64, Order, Memory>> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(14))))
+    );
+
+    static TRADES_STORAGE: RefCell<StableBTreeMap<u64, Trade, Memory>> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(15))))
+    );
+
+    static LOAN_PARTICIPATIONS_STORAGE: RefCell<StableBTreeMap<ParticipationKey, LoanParticipation, Memory>> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(16))))
+    );
+
+    static RECOVERY_CASES_STORAGE: RefCell<StableBTreeMap<u64, RecoveryCase, Memory>> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(17))))
+    );
+
+    static POOL_LOANS_STORAGE: RefCell<StableBTreeMap<PoolLoanKey, (), Memory>> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(19))))
+    );
+
+    static INVESTOR_LOANS_STORAGE: RefCell<StableBTreeMap<InvestorLoanKey, (), Memory>> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(20))))
+    );
+
+    // Secondary indexes, maintained by store_user, store_investor, store_loan
+    // and store_payment
+    static USERS_BY_EMAIL: RefCell<StableBTreeMap<EmailKey, u64, Memory>> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(21))))
+    );
+
+    static USERS_BY_OWNER: RefCell<StableBTreeMap<OwnerUserKey, (), Memory>> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(22))))
+    );
+
+    static LOANS_BY_BORROWER: RefCell<StableBTreeMap<BorrowerLoanKey, (), Memory>> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(23))))
+    );
+
+    static LOANS_BY_STATUS: RefCell<StableBTreeMap<StatusLoanKey, (), Memory>> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(24))))
+    );
+
+    static PAYMENTS_BY_LOAN: RefCell<StableBTreeMap<LoanPaymentKey, (), Memory>> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(25))))
+    );
+
+    // Append-only log of record changes, in an index and a data region
+    static AUDIT_LOG: RefCell<Log<AuditEntry, Memory, Memory>> = RefCell::new(
+        Log::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(26))),
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(27))),
+        )
+        .expect("Cannot create the audit log")
+    );
+
+    // Audit log indexes, maintained by audit::record
+    static AUDIT_BY_ENTITY: RefCell<StableBTreeMap<AuditEntityKey, (), Memory>> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(28))))
+    );
+
+    static AUDIT_BY_CALLER: RefCell<StableBTreeMap<AuditCallerKey, (), Memory>> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(29))))
+    );
+
+    static KYC_STORAGE: RefCell<StableBTreeMap<u64, KycRecord, Memory>> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(30))))
+    );
+
+    static INVESTORS_BY_OWNER: RefCell<StableBTreeMap<OwnerInvestorKey, (), Memory>> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(31))))
+    );
+
+    // Number of pool share transfers, which is the next transfer's block index
+    static SHARE_TRANSFER_COUNTER: RefCell<IdCell> = RefCell::new(
+        IdCell::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(32))), 0)
+            .expect("Cannot create the share transfer counter")
+    );
+
+    // Schema version stable memory was last migrated to
+    static SCHEMA_VERSION: RefCell<Cell<u64, Memory>> = RefCell::new(
+        Cell::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(18))), 0)
+            .expect("Cannot create the schema version")
+    );
+
+    // Loans with a disbursement transfer in flight (heap only, cleared on upgrade)
+    static DISBURSEMENTS_IN_FLIGHT: RefCell<BTreeSet<u64>> = RefCell::new(BTreeSet::new());
+
+    // Periodic accrual timer; timers do not survive upgrades and are re-armed in post_upgrade
+    static ACCRUAL_TIMER: RefCell<Option<TimerId>> = RefCell::new(None);
 }
 
 // Implement Storable for User
 impl Storable for User {
     fn to_bytes(&self) -> std::borrow::Cow<[u8]> {
-        Cow::Owned(Encode!(self).unwrap())
+        Cow::Owned(encode_record(self))
     }
 
     fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
-        Decode!(bytes.as_ref(), Self).unwrap()
+        decode_record(&bytes)
     }
 }
 
@@ -88,11 +284,11 @@ impl BoundedStorable for User {
 // Implement Storable for Motorcycle
 impl Storable for Motorcycle {
     fn to_bytes(&self) -> std::borrow::Cow<[u8]> {
-        Cow::Owned(Encode!(self).unwrap())
+        Cow::Owned(encode_record(self))
     }
 
     fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
-        Decode!(bytes.as_ref(), Self).unwrap()
+        decode_record(&bytes)
     }
 }
 
@@ -104,11 +300,11 @@ impl BoundedStorable for Motorcycle {
 // Implement Storable for Loan
 impl Storable for Loan {
     fn to_bytes(&self) -> std::borrow::Cow<[u8]> {
-        Cow::Owned(Encode!(self).unwrap())
+        Cow::Owned(encode_record(self))
     }
 
     fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
-        Decode!(bytes.as_ref(), Self).unwrap()
+        decode_record(&bytes)
     }
 }
 
@@ -120,11 +316,11 @@ impl BoundedStorable for Loan {
 // Implement Storable for Payment
 impl Storable for Payment {
     fn to_bytes(&self) -> std::borrow::Cow<[u8]> {
-        Cow::Owned(Encode!(self).unwrap())
+        Cow::Owned(encode_record(self))
     }
 
     fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
-        Decode!(bytes.as_ref(), Self).unwrap()
+        decode_record(&bytes)
     }
 }
 
@@ -136,11 +332,11 @@ impl BoundedStorable for Payment {
 // Implement Storable for Investor
 impl Storable for Investor {
     fn to_bytes(&self) -> std::borrow::Cow<[u8]> {
-        Cow::Owned(Encode!(self).unwrap())
+        Cow::Owned(encode_record(self))
     }
 
     fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
-        Decode!(bytes.as_ref(), Self).unwrap()
+        decode_record(&bytes)
     }
 }
 
@@ -152,11 +348,11 @@ impl BoundedStorable for Investor {
 // Implement Storable for LoanPool
 impl Storable for LoanPool {
     fn to_bytes(&self) -> std::borrow::Cow<[u8]> {
-        Cow::Owned(Encode!(self).unwrap())
+        Cow::Owned(encode_record(self))
     }
 
     fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
-        Decode!(bytes.as_ref(), Self).unwrap()
+        decode_record(&bytes)
     }
 }
 
@@ -165,376 +361,3156 @@ impl BoundedStorable for LoanPool {
     const IS_FIXED_SIZE: bool = false;
 }
 
-// Helper Functions
-
-// Generates a unique identifier for objects
-fn generate_uuid() -> u64 {
-    let id = ID_COUNTER.with(|counter| {
-        let current_id = *counter.borrow().get();
-        let _ = counter.borrow_mut().set(current_id + 1);
-        current_id
-    });
+// Implement Storable for Installment
+impl Storable for Installment {
+    fn to_bytes(&self) -> std::borrow::Cow<[u8]> {
+        Cow::Owned(encode_record(self))
+    }
 
-    id
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        decode_record(&bytes)
+    }
 }
 
-// Validate email format
-fn validate_email_format(email: &str) -> Result<(), String> {
-    let email_regex = Regex::new(r"^\S+@\S+\.\S+$").unwrap();
-    if !email_regex.is_match(email) {
-        Err("Invalid email format".to_string())
-    } else {
-        Ok(())
-    }
+impl BoundedStorable for Installment {
+    const MAX_SIZE: u32 = 1024;
+    const IS_FIXED_SIZE: bool = false;
 }
 
-// Validate email uniqueness
-fn validate_email_uniqueness(email: &str) -> Result<(), String> {
-    let email_exists =
-        USERS_STORAGE.with(|storage| storage.borrow().iter().any(|(_, user)| user.email == email));
+// Implement Storable for InstallmentKey (big-endian so keys sort by loan, then number)
+impl Storable for InstallmentKey {
+    fn to_bytes(&self) -> std::borrow::Cow<[u8]> {
+        let mut bytes = Vec::with_capacity(12);
+        bytes.extend_from_slice(&self.loan_id.to_be_bytes());
+        bytes.extend_from_slice(&self.number.to_be_bytes());
+        Cow::Owned(bytes)
+    }
 
-    if email_exists {
-        Err("User with this email already exists".to_string())
-    } else {
-        Ok(())
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        InstallmentKey {
+            loan_id: u64::from_be_bytes(bytes[0..8].try_into().unwrap()),
+            number: u32::from_be_bytes(bytes[8..12].try_into().unwrap()),
+        }
     }
 }
 
-// User Functions
-#[ic_cdk::update]
-fn register_user(payload: RegisterUserPayload) -> Result<User, String> {
-    if payload.name.is_empty() || payload.email.is_empty() || payload.address.is_empty() {
-        return Err("Name, email, and address are required fields".to_string());
+impl BoundedStorable for InstallmentKey {
+    const MAX_SIZE: u32 = 12;
+    const IS_FIXED_SIZE: bool = true;
+}
+
+// Implement Storable for PoolPosition
+impl Storable for PoolPosition {
+    fn to_bytes(&self) -> std::borrow::Cow<[u8]> {
+        Cow::Owned(encode_record(self))
     }
-    validate_email_format(&payload.email)?;
-    validate_email_uniqueness(&payload.email)?;
 
-    let id = generate_uuid();
-    let user = User {
-        id,
-        owner: caller(),
-        name: payload.name,
-        email: payload.email,
-        address: payload.address,
-        role: payload.role,
-    };
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        decode_record(&bytes)
+    }
+}
 
-    USERS_STORAGE.with(|users| {
-        users.borrow_mut().insert(id, user.clone());
-        Ok(user)
-    })
+impl BoundedStorable for PoolPosition {
+    const MAX_SIZE: u32 = 1024;
+    const IS_FIXED_SIZE: bool = false;
 }
 
-#[ic_cdk::update]
-fn update_user(payload: UpdateUserPayload) -> Result<User, String> {
-    if !USERS_STORAGE.with(|storage| storage.borrow().contains_key(&payload.id)) {
-        return Err("User not found".to_string());
+// Implement Storable for PositionKey (big-endian so keys sort by pool, then investor)
+impl Storable for PositionKey {
+    fn to_bytes(&self) -> std::borrow::Cow<[u8]> {
+        let mut bytes = Vec::with_capacity(16);
+        bytes.extend_from_slice(&self.pool_id.to_be_bytes());
+        bytes.extend_from_slice(&self.investor_id.to_be_bytes());
+        Cow::Owned(bytes)
     }
-    validate_email_format(&payload.email)?;
-    let email_exists = USERS_STORAGE.with(|storage| {
-        storage
-            .borrow()
-            .iter()
-            .any(|(_, user)| user.email == payload.email && user.id != payload.id)
-    });
 
-    if email_exists {
-        return Err("User with this email already exists".to_string());
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        PositionKey {
+            pool_id: u64::from_be_bytes(bytes[0..8].try_into().unwrap()),
+            investor_id: u64::from_be_bytes(bytes[8..16].try_into().unwrap()),
+        }
     }
+}
 
-    let user = User {
-        id: payload.id,
-        owner: caller(),
-        name: payload.name,
-        email: payload.email,
-        address: payload.address,
-        role: payload.role,
-    };
-
-    USERS_STORAGE.with(
-        |storage| match storage.borrow_mut().insert(payload.id, user.clone()) {
-            Some(_) => Ok(user),
-            None => Err("User not found".to_string()),
-        },
-    )
+impl BoundedStorable for PositionKey {
+    const MAX_SIZE: u32 = 16;
+    const IS_FIXED_SIZE: bool = true;
 }
 
-#[ic_cdk::query]
-fn get_user(id: u64) -> Result<User, String> {
-    USERS_STORAGE.with(|storage| match storage.borrow().get(&id) {
-        Some(user) => Ok(user.clone()),
-        None => Err(format!("User with ID {} not found", id)),
-    })
+// Implement Storable for LoanParticipation
+impl Storable for LoanParticipation {
+    fn to_bytes(&self) -> std::borrow::Cow<[u8]> {
+        Cow::Owned(encode_record(self))
+    }
+
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        decode_record(&bytes)
+    }
 }
 
-#[ic_cdk::query]
-fn get_all_users() -> Result<Vec<User>, String> {
-    USERS_STORAGE.with(|storage| {
-        let users: Vec<User> = storage
-            .borrow()
-            .iter()
-            .map(|(_, user)| user.clone())
-            .collect();
-        if users.is_empty() {
-            Err("No users found".to_string())
-        } else {
-            Ok(users)
-        }
-    })
+impl BoundedStorable for LoanParticipation {
+    const MAX_SIZE: u32 = 256;
+    const IS_FIXED_SIZE: bool = false;
 }
 
-// Motorcycle Functions
-#[ic_cdk::update]
-fn register_motorcycle(payload: RegisterMotorcyclePayload) -> Result<Motorcycle, String> {
-    if payload.model.is_empty() || payload.manufacturer.is_empty() {
-        return Err("Model and manufacturer are required fields".to_string());
+// Implement Storable for ParticipationKey (big-endian so keys sort by loan, then investor)
+impl Storable for ParticipationKey {
+    fn to_bytes(&self) -> std::borrow::Cow<[u8]> {
+        let mut bytes = Vec::with_capacity(16);
+        bytes.extend_from_slice(&self.loan_id.to_be_bytes());
+        bytes.extend_from_slice(&self.investor_id.to_be_bytes());
+        Cow::Owned(bytes)
     }
 
-    let id = generate_uuid();
-    let motorcycle = Motorcycle {
-        id,
-        model: payload.model,
-        manufacturer: payload.manufacturer,
-        price: payload.price,
-        status: MotorcycleStatus::Available,
-    };
-
-    MOTORCYCLES_STORAGE.with(|motorcycles| {
-        motorcycles.borrow_mut().insert(id, motorcycle.clone());
-        Ok(motorcycle)
-    })
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        ParticipationKey {
+            loan_id: u64::from_be_bytes(bytes[0..8].try_into().unwrap()),
+            investor_id: u64::from_be_bytes(bytes[8..16].try_into().unwrap()),
+        }
+    }
 }
 
-#[ic_cdk::query]
-fn get_motorcycle(id: u64) -> Result<Motorcycle, String> {
-    MOTORCYCLES_STORAGE.with(|storage| match storage.borrow().get(&id) {
-        Some(motorcycle) => Ok(motorcycle.clone()),
-        None => Err(format!("Motorcycle with ID {} not found", id)),
-    })
+impl BoundedStorable for ParticipationKey {
+    const MAX_SIZE: u32 = 16;
+    const IS_FIXED_SIZE: bool = true;
 }
 
-#[ic_cdk::query]
-fn get_all_motorcycles() -> Result<Vec<Motorcycle>, String> {
-    MOTORCYCLES_STORAGE.with(|storage| {
-        let motorcycles: Vec<Motorcycle> = storage
-            .borrow()
-            .iter()
-            .map(|(_, motorcycle)| motorcycle.clone())
-            .collect();
-        if motorcycles.is_empty() {
-            Err("No motorcycles found".to_string())
-        } else {
-            Ok(motorcycles)
+// Implement Storable for PoolLoanKey (big-endian so keys sort by pool, then loan)
+impl Storable for PoolLoanKey {
+    fn to_bytes(&self) -> std::borrow::Cow<[u8]> {
+        let mut bytes = Vec::with_capacity(16);
+        bytes.extend_from_slice(&self.pool_id.to_be_bytes());
+        bytes.extend_from_slice(&self.loan_id.to_be_bytes());
+        Cow::Owned(bytes)
+    }
+
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        PoolLoanKey {
+            pool_id: u64::from_be_bytes(bytes[0..8].try_into().unwrap()),
+            loan_id: u64::from_be_bytes(bytes[8..16].try_into().unwrap()),
         }
-    })
+    }
+}
+impl BoundedStorable for PoolLoanKey {
+    const MAX_SIZE: u32 = 16;
+    const IS_FIXED_SIZE: bool = true;
 }
 
-#[ic_cdk::update]
-fn update_motorcycle_status(id: u64, status: MotorcycleStatus) -> Result<Motorcycle, String> {
-    MOTORCYCLES_STORAGE.with(|storage| {
-        let mut motorcycles = storage.borrow_mut();
-        if let Some(mut motorcycle) = motorcycles.get(&id).cloned() {
-            motorcycle.status = status;
-            motorcycles.insert(id, motorcycle.clone());
-            Ok(motorcycle)
-        } else {
-            Err(format!("Motorcycle with ID {} not found", id))
+// Implement Storable for InvestorLoanKey (big-endian so keys sort by investor, then loan)
+impl Storable for InvestorLoanKey {
+    fn to_bytes(&self) -> std::borrow::Cow<[u8]> {
+        let mut bytes = Vec::with_capacity(16);
+        bytes.extend_from_slice(&self.investor_id.to_be_bytes());
+        bytes.extend_from_slice(&self.loan_id.to_be_bytes());
+        Cow::Owned(bytes)
+    }
+
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        InvestorLoanKey {
+            investor_id: u64::from_be_bytes(bytes[0..8].try_into().unwrap()),
+            loan_id: u64::from_be_bytes(bytes[8..16].try_into().unwrap()),
         }
-    })
+    }
+}
+impl BoundedStorable for InvestorLoanKey {
+    const MAX_SIZE: u32 = 16;
+    const IS_FIXED_SIZE: bool = true;
 }
 
-// Loan Functions
-#[ic_cdk::update]
-fn create_loan(payload: ApplyLoanPayload) -> Result<Loan, String> {
-
-    let id = generate_uuid();
-    let loan = Loan {
-        id,
-        borrower_id: payload.borrower_id,
-        motorcycle_id: payload.motorcycle_id,
-        principal_amount: payload.principal_amount,
-        interest_rate: payload.interest_rate,
-        daily_payment: payload.daily_payment,
-        start_date: payload.start_date,
-        end_date: payload.end_date,
-        status: LoanStatus::Active,
-        total_paid: 0.0,
-    };
+// Implement Storable for EmailKey
+impl Storable for EmailKey {
+    fn to_bytes(&self) -> std::borrow::Cow<[u8]> {
+        Cow::Borrowed(self.0.as_bytes())
+    }
 
-    LOANS_STORAGE.with(|loans| {
-        loans.borrow_mut().insert(id, loan.clone());
-        Ok(loan)
-    })
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        EmailKey(String::from_utf8(bytes.into_owned()).unwrap())
+    }
 }
-
-#[ic_cdk::query]
-fn get_loan(id: u64) -> Result<Loan, String> {
-    LOANS_STORAGE.with(|storage| match storage.borrow().get(&id) {
-        Some(loan) => Ok(loan.clone()),
-        None => Err(format!("Loan with ID {} not found", id)),
-    })
+impl BoundedStorable for EmailKey {
+    const MAX_SIZE: u32 = index::MAX_EMAIL_LEN as u32;
+    const IS_FIXED_SIZE: bool = false;
 }
 
-#[ic_cdk::query]
-fn get_all_loans() -> Result<Vec<Loan>, String> {
-    LOANS_STORAGE.with(|storage| {
-        let loans: Vec<Loan> = storage
-            .borrow()
-            .iter()
-            .map(|(_, loan)| loan.clone())
-            .collect();
-        if loans.is_empty() {
-            Err("No loans found".to_string())
-        } else {
-            Ok(loans)
+// Implement Storable for OwnerUserKey (principal length, principal padded to
+// 29 bytes, then the big-endian user ID)
+impl Storable for OwnerUserKey {
+    fn to_bytes(&self) -> std::borrow::Cow<[u8]> {
+        let principal = self.owner.as_slice();
+        let mut bytes = vec![0; 38];
+        bytes[0] = principal.len() as u8;
+        bytes[1..1 + principal.len()].copy_from_slice(principal);
+        bytes[30..38].copy_from_slice(&self.user_id.to_be_bytes());
+        Cow::Owned(bytes)
+    }
+
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        let len = bytes[0] as usize;
+        OwnerUserKey {
+            owner: Principal::from_slice(&bytes[1..1 + len]),
+            user_id: u64::from_be_bytes(bytes[30..38].try_into().unwrap()),
         }
-    })
+    }
+}
+impl BoundedStorable for OwnerUserKey {
+    const MAX_SIZE: u32 = 38;
+    const IS_FIXED_SIZE: bool = true;
 }
 
-#[ic_cdk::update]
-fn update_loan_status(id: u64, status: LoanStatus) -> Result<Loan, String> {
-    LOANS_STORAGE.with(|storage| {
-        let mut loans = storage.borrow_mut();
-        if let Some(mut loan) = loans.get(&id).cloned() {
-            loan.status = status;
-            loans.insert(id, loan.clone());
-            Ok(loan)
-        } else {
-            Err(format!("Loan with ID {} not found", id))
+// Implement Storable for OwnerInvestorKey (same layout as OwnerUserKey)
+impl Storable for OwnerInvestorKey {
+    fn to_bytes(&self) -> std::borrow::Cow<[u8]> {
+        let principal = self.owner.as_slice();
+        let mut bytes = vec![0; 38];
+        bytes[0] = principal.len() as u8;
+        bytes[1..1 + principal.len()].copy_from_slice(principal);
+        bytes[30..38].copy_from_slice(&self.investor_id.to_be_bytes());
+        Cow::Owned(bytes)
+    }
+
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        let len = bytes[0] as usize;
+        OwnerInvestorKey {
+            owner: Principal::from_slice(&bytes[1..1 + len]),
+            investor_id: u64::from_be_bytes(bytes[30..38].try_into().unwrap()),
+        }
+    }
+}
+impl BoundedStorable for OwnerInvestorKey {
+    const MAX_SIZE: u32 = 38;
+    const IS_FIXED_SIZE: bool = true;
+}
+
+// Implement Storable for BorrowerLoanKey (big-endian so keys sort by borrower, then loan)
+impl Storable for BorrowerLoanKey {
+    fn to_bytes(&self) -> std::borrow::Cow<[u8]> {
+        let mut bytes = Vec::with_capacity(16);
+        bytes.extend_from_slice(&self.borrower_id.to_be_bytes());
+        bytes.extend_from_slice(&self.loan_id.to_be_bytes());
+        Cow::Owned(bytes)
+    }
+
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        BorrowerLoanKey {
+            borrower_id: u64::from_be_bytes(bytes[0..8].try_into().unwrap()),
+            loan_id: u64::from_be_bytes(bytes[8..16].try_into().unwrap()),
+        }
+    }
+}
+impl BoundedStorable for BorrowerLoanKey {
+    const MAX_SIZE: u32 = 16;
+    const IS_FIXED_SIZE: bool = true;
+}
+
+// Implement Storable for StatusLoanKey (status code, then the big-endian loan ID)
+impl Storable for StatusLoanKey {
+    fn to_bytes(&self) -> std::borrow::Cow<[u8]> {
+        let code: u8 = match self.status {
+            LoanStatus::Applied => 0,
+            LoanStatus::UnderReview => 1,
+            LoanStatus::Approved => 2,
+            LoanStatus::Rejected => 3,
+            LoanStatus::Disbursed => 4,
+            LoanStatus::Active => 5,
+            LoanStatus::Completed => 6,
+            LoanStatus::Defaulted => 7,
+            LoanStatus::WrittenOff => 8,
+        };
+        let mut bytes = Vec::with_capacity(9);
+        bytes.push(code);
+        bytes.extend_from_slice(&self.loan_id.to_be_bytes());
+        Cow::Owned(bytes)
+    }
+
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        let status = match bytes[0] {
+            0 => LoanStatus::Applied,
+            1 => LoanStatus::UnderReview,
+            2 => LoanStatus::Approved,
+            3 => LoanStatus::Rejected,
+            4 => LoanStatus::Disbursed,
+            5 => LoanStatus::Active,
+            6 => LoanStatus::Completed,
+            7 => LoanStatus::Defaulted,
+            8 => LoanStatus::WrittenOff,
+            code => ic_cdk::trap(&format!("Unknown loan status code {}", code)),
+        };
+        StatusLoanKey {
+            status,
+            loan_id: u64::from_be_bytes(bytes[1..9].try_into().unwrap()),
+        }
+    }
+}
+impl BoundedStorable for StatusLoanKey {
+    const MAX_SIZE: u32 = 9;
+    const IS_FIXED_SIZE: bool = true;
+}
+
+// Implement Storable for LoanPaymentKey (big-endian so keys sort by loan, then payment)
+impl Storable for LoanPaymentKey {
+    fn to_bytes(&self) -> std::borrow::Cow<[u8]> {
+        let mut bytes = Vec::with_capacity(16);
+        bytes.extend_from_slice(&self.loan_id.to_be_bytes());
+        bytes.extend_from_slice(&self.payment_id.to_be_bytes());
+        Cow::Owned(bytes)
+    }
+
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        LoanPaymentKey {
+            loan_id: u64::from_be_bytes(bytes[0..8].try_into().unwrap()),
+            payment_id: u64::from_be_bytes(bytes[8..16].try_into().unwrap()),
+        }
+    }
+}
+impl BoundedStorable for LoanPaymentKey {
+    const MAX_SIZE: u32 = 16;
+    const IS_FIXED_SIZE: bool = true;
+}
+
+// Implement Storable for AuditEntityKey (entity code, then the big-endian
+// record and entry IDs)
+impl Storable for AuditEntityKey {
+    fn to_bytes(&self) -> std::borrow::Cow<[u8]> {
+        let code: u8 = match self.entity {
+            EntityKind::User => 0,
+            EntityKind::Motorcycle => 1,
+            EntityKind::Loan => 2,
+            EntityKind::Installment => 3,
+            EntityKind::Payment => 4,
+            EntityKind::Investor => 5,
+            EntityKind::LoanPool => 6,
+            EntityKind::Order => 7,
+            EntityKind::RecoveryCase => 8,
+            EntityKind::PoolPosition => 9,
+            EntityKind::KycRecord => 10,
+            EntityKind::Trade => 11,
+            EntityKind::LoanParticipation => 12,
+            EntityKind::Configuration => 13,
+        };
+        let mut bytes = Vec::with_capacity(17);
+        bytes.push(code);
+        bytes.extend_from_slice(&self.entity_id.to_be_bytes());
+        bytes.extend_from_slice(&self.entry_id.to_be_bytes());
+        Cow::Owned(bytes)
+    }
+
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        let entity = match bytes[0] {
+            0 => EntityKind::User,
+            1 => EntityKind::Motorcycle,
+            2 => EntityKind::Loan,
+            3 => EntityKind::Installment,
+            4 => EntityKind::Payment,
+            5 => EntityKind::Investor,
+            6 => EntityKind::LoanPool,
+            7 => EntityKind::Order,
+            8 => EntityKind::RecoveryCase,
+            9 => EntityKind::PoolPosition,
+            10 => EntityKind::KycRecord,
+            11 => EntityKind::Trade,
+            12 => EntityKind::LoanParticipation,
+            13 => EntityKind::Configuration,
+            code => ic_cdk::trap(&format!("Unknown entity code {}", code)),
+        };
+        AuditEntityKey {
+            entity,
+            entity_id: u64::from_be_bytes(bytes[1..9].try_into().unwrap()),
+            entry_id: u64::from_be_bytes(bytes[9..17].try_into().unwrap()),
+        }
+    }
+}
+impl BoundedStorable for AuditEntityKey {
+    const MAX_SIZE: u32 = 17;
+    const IS_FIXED_SIZE: bool = true;
+}
+
+// Implement Storable for AuditCallerKey (principal length, principal padded
+// to 29 bytes, then the big-endian entry ID)
+impl Storable for AuditCallerKey {
+    fn to_bytes(&self) -> std::borrow::Cow<[u8]> {
+        let principal = self.caller.as_slice();
+        let mut bytes = vec![0; 38];
+        bytes[0] = principal.len() as u8;
+        bytes[1..1 + principal.len()].copy_from_slice(principal);
+        bytes[30..38].copy_from_slice(&self.entry_id.to_be_bytes());
+        Cow::Owned(bytes)
+    }
+
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        let len = bytes[0] as usize;
+        AuditCallerKey {
+            caller: Principal::from_slice(&bytes[1..1 + len]),
+            entry_id: u64::from_be_bytes(bytes[30..38].try_into().unwrap()),
+        }
+    }
+}
+impl BoundedStorable for AuditCallerKey {
+    const MAX_SIZE: u32 = 38;
+    const IS_FIXED_SIZE: bool = true;
+}
+
+// Implement Storable for AuditEntry (log entries are unbounded)
+impl Storable for AuditEntry {
+    fn to_bytes(&self) -> std::borrow::Cow<[u8]> {
+        Cow::Owned(encode_record(self))
+    }
+
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        decode_record(&bytes)
+    }
+}
+
+// Implement Storable for Order
+impl Storable for Order {
+    fn to_bytes(&self) -> std::borrow::Cow<[u8]> {
+        Cow::Owned(encode_record(self))
+    }
+
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        decode_record(&bytes)
+    }
+}
+
+impl BoundedStorable for Order {
+    const MAX_SIZE: u32 = 1024;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+// Implement Storable for Trade
+impl Storable for Trade {
+    fn to_bytes(&self) -> std::borrow::Cow<[u8]> {
+        Cow::Owned(encode_record(self))
+    }
+
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        decode_record(&bytes)
+    }
+}
+
+impl BoundedStorable for Trade {
+    const MAX_SIZE: u32 = 1024;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+// Implement Storable for RecoveryCase
+impl Storable for RecoveryCase {
+    fn to_bytes(&self) -> std::borrow::Cow<[u8]> {
+        Cow::Owned(encode_record(self))
+    }
+
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        decode_record(&bytes)
+    }
+}
+
+impl BoundedStorable for RecoveryCase {
+    const MAX_SIZE: u32 = 2048;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+// Implement Storable for KycRecord
+impl Storable for KycRecord {
+    fn to_bytes(&self) -> std::borrow::Cow<[u8]> {
+        Cow::Owned(encode_record(self))
+    }
+
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        decode_record(&bytes)
+    }
+}
+
+impl BoundedStorable for KycRecord {
+    const MAX_SIZE: u32 = 1024;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+// Implement Storable for LedgerConfig
+impl Storable for LedgerConfig {
+    fn to_bytes(&self) -> std::borrow::Cow<[u8]> {
+        Cow::Owned(encode_record(self))
+    }
+
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        decode_record(&bytes)
+    }
+}
+
+// Implement Storable for AccrualConfig
+impl Storable for AccrualConfig {
+    fn to_bytes(&self) -> std::borrow::Cow<[u8]> {
+        Cow::Owned(encode_record(self))
+    }
+
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        decode_record(&bytes)
+    }
+}
+
+// Implement Storable for FeeConfig
+impl Storable for FeeConfig {
+    fn to_bytes(&self) -> std::borrow::Cow<[u8]> {
+        Cow::Owned(encode_record(self))
+    }
+
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        decode_record(&bytes)
+    }
+}
+
+// Implement Storable for AccessConfig
+impl Storable for AccessConfig {
+    fn to_bytes(&self) -> std::borrow::Cow<[u8]> {
+        Cow::Owned(encode_record(self))
+    }
+
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        decode_record(&bytes)
+    }
+}
+
+// Helper Functions
+
+// Longest free text (names, addresses, reasons, error messages) stored on a
+// record
+const MAX_TEXT_LEN: usize = 256;
+
+// Generates a unique identifier for objects
+fn generate_uuid() -> u64 {
+    let id = ID_COUNTER.with(|counter| {
+        let current_id = *counter.borrow().get();
+        let _ = counter.borrow_mut().set(current_id + 1);
+        current_id
+    });
+
+    id
+}
+
+// Collects the stored installments of a loan in installment order
+fn loan_installments(loan_id: u64) -> Vec<Installment> {
+    INSTALLMENTS_STORAGE.with(|storage| {
+        storage
+            .borrow()
+            .range(InstallmentKey { loan_id, number: 0 }..)
+            .take_while(|(key, _)| key.loan_id == loan_id)
+            .map(|(_, installment)| installment)
+            .collect()
+    })
+}
+
+// Stores a loan's installments and records each one that changed against
+// the loan's ID
+fn store_installments(installments: Vec<Installment>, operation: &str) {
+    for installment in installments {
+        let key = InstallmentKey {
+            loan_id: installment.loan_id,
+            number: installment.number,
+        };
+        let previous = INSTALLMENTS_STORAGE.with(|storage| storage.borrow().get(&key));
+        if previous.as_ref() == Some(&installment) {
+            continue;
+        }
+        INSTALLMENTS_STORAGE.with(|storage| storage.borrow_mut().insert(key, installment.clone()));
+        audit::record(
+            operation,
+            EntityKind::Installment,
+            installment.loan_id,
+            previous.as_ref(),
+            &installment,
+        );
+    }
+}
+
+// IDs of the loans a pool currently funds, in loan order
+fn pool_loan_ids(pool_id: u64) -> Vec<u64> {
+    POOL_LOANS_STORAGE.with(|storage| {
+        storage
+            .borrow()
+            .range(
+                PoolLoanKey {
+                    pool_id,
+                    loan_id: 0,
+                }..,
+            )
+            .take_while(|(key, _)| key.pool_id == pool_id)
+            .map(|(key, _)| key.loan_id)
+            .collect()
+    })
+}
+
+// Links a pool-funded loan to its pool while it draws on the pool's capital,
+// and unlinks it once repaid or written off
+fn sync_pool_loan(loan: &Loan) {
+    let Some(pool_id) = loan.pool_id else {
+        return;
+    };
+    let key = PoolLoanKey {
+        pool_id,
+        loan_id: loan.id,
+    };
+    POOL_LOANS_STORAGE.with(|storage| {
+        if funds_loan(pool_id, loan) {
+            storage.borrow_mut().insert(key, ());
+        } else {
+            storage.borrow_mut().remove(&key);
+        }
+    });
+}
+
+// IDs of the investors holding shares in a pool, in investor order
+fn pool_investor_ids(pool_id: u64) -> Vec<u64> {
+    pool_positions(pool_id)
+        .into_iter()
+        .filter(|position| position.shares > 0)
+        .map(|position| position.investor_id)
+        .collect()
+}
+
+// IDs of the loans an investor holds participations in, in loan order
+fn investor_loan_ids(investor_id: u64) -> Vec<u64> {
+    INVESTOR_LOANS_STORAGE.with(|storage| {
+        storage
+            .borrow()
+            .range(
+                InvestorLoanKey {
+                    investor_id,
+                    loan_id: 0,
+                }..,
+            )
+            .take_while(|(key, _)| key.investor_id == investor_id)
+            .map(|(key, _)| key.loan_id)
+            .collect()
+    })
+}
+
+// Loans a pool funds, read from storage except `updated`, which has not been
+// stored yet and may have joined or left the pool
+fn pool_loans(pool_id: u64, updated: Option<&Loan>) -> Vec<Loan> {
+    let mut loans: Vec<Loan> = LOANS_STORAGE.with(|storage| {
+        let storage = storage.borrow();
+        pool_loan_ids(pool_id)
+            .into_iter()
+            .filter(|loan_id| updated.map_or(true, |loan| loan.id != *loan_id))
+            .filter_map(|loan_id| storage.get(&loan_id))
+            .collect()
+    });
+    if let Some(loan) = updated {
+        loans.push(loan.clone());
+    }
+    loans.retain(|loan| funds_loan(pool_id, loan));
+    loans
+}
+
+// Checks a pool's totals against the loans it funds
+fn verify_pool(pool: &LoanPool, updated: Option<&Loan>) -> Result<(), Error> {
+    check_pool_invariants(pool, &pool_loans(pool.id, updated))
+}
+
+// Net asset value of a pool, valuing the loans it funds from storage
+fn pool_nav(pool: &LoanPool) -> Result<Amount, Error> {
+    net_asset_value(pool, &pool_loans(pool.id, None))
+}
+
+// An investor's position in a pool, or an empty one if it never deposited
+fn load_position(pool: &LoanPool, investor_id: u64) -> PoolPosition {
+    let key = PositionKey {
+        pool_id: pool.id,
+        investor_id,
+    };
+    POOL_POSITIONS_STORAGE
+        .with(|storage| storage.borrow().get(&key))
+        .unwrap_or_else(|| empty_position(pool, investor_id))
+}
+
+// Positions held in a pool, in investor order
+fn pool_positions(pool_id: u64) -> Vec<PoolPosition> {
+    POOL_POSITIONS_STORAGE.with(|storage| {
+        storage
+            .borrow()
+            .range(
+                PositionKey {
+                    pool_id,
+                    investor_id: 0,
+                }..,
+            )
+            .take_while(|(key, _)| key.pool_id == pool_id)
+            .map(|(_, position)| position)
+            .collect()
+    })
+}
+
+// Stores a position and records the change against its pool
+fn store_position(position: &PoolPosition, operation: &str) {
+    let key = PositionKey {
+        pool_id: position.pool_id,
+        investor_id: position.investor_id,
+    };
+    let previous =
+        POOL_POSITIONS_STORAGE.with(|storage| storage.borrow_mut().insert(key, position.clone()));
+    // Pool statistics count the investors holding shares
+    let held = |position: &PoolPosition| position.shares > 0;
+    if previous.as_ref().is_some_and(held) != held(position) {
+        certification::certify_pool_statistics([position.pool_id]);
+    }
+    audit::record(
+        operation,
+        EntityKind::PoolPosition,
+        position.pool_id,
+        previous.as_ref(),
+        position,
+    );
+}
+
+// Loans are visible to staff with the ViewLoans permission and to the
+// borrower whose user record is owned by the caller
+fn authorize_loan_access(loan: &Loan) -> Result<(), Error> {
+    let caller = resolve_caller();
+    let is_borrower = caller.user.as_ref().map(|user| user.id) == Some(loan.borrower_id);
+    if is_borrower {
+        Ok(())
+    } else {
+        caller.require(Permission::ViewLoans)
+    }
+}
+
+// Validate email format
+fn validate_email_format(email: &str) -> Result<(), Error> {
+    let email_regex = Regex::new(r"^\S+@\S+\.\S+$").unwrap();
+    if !email_regex.is_match(email) {
+        Err(Error::invalid_payload("email", "Invalid email format"))
+    } else if email.len() > index::MAX_EMAIL_LEN {
+        Err(Error::invalid_payload(
+            "email",
+            format!("Email is longer than {} bytes", index::MAX_EMAIL_LEN),
+        ))
+    } else {
+        Ok(())
+    }
+}
+
+// Validate the length of a free-text field. Free text is bounded so records
+// stay within their stable storage size.
+fn validate_text_len(field: &str, value: &str) -> Result<(), Error> {
+    if value.len() > MAX_TEXT_LEN {
+        Err(Error::invalid_payload(
+            field,
+            format!("Text is longer than {} bytes", MAX_TEXT_LEN),
+        ))
+    } else {
+        Ok(())
+    }
+}
+
+// Cuts error text kept on a record to MAX_TEXT_LEN bytes, at a character
+// boundary
+fn truncate_text(mut text: String) -> String {
+    if text.len() > MAX_TEXT_LEN {
+        let mut end = MAX_TEXT_LEN;
+        while !text.is_char_boundary(end) {
+            end -= 1;
+        }
+        text.truncate(end);
+    }
+    text
+}
+
+// Validate email uniqueness, ignoring the user being updated
+fn validate_email_uniqueness(email: &str, user_id: Option<u64>) -> Result<(), Error> {
+    let email_exists = index::user_by_email(email).is_some_and(|id| Some(id) != user_id);
+
+    if email_exists {
+        Err(Error::AlreadyExists {
+            entity: EntityKind::User,
+            field: "email".to_string(),
+            msg: "User with this email already exists".to_string(),
+        })
+    } else {
+        Ok(())
+    }
+}
+
+// Stores a user, brings its indexes up to date and records the change
+fn store_user(user: &User, operation: &str) {
+    let previous = USERS_STORAGE.with(|storage| storage.borrow_mut().insert(user.id, user.clone()));
+    index::index_user(previous.as_ref(), user);
+    audit::record(
+        operation,
+        EntityKind::User,
+        user.id,
+        previous.as_ref(),
+        user,
+    );
+}
+
+// User Functions
+#[ic_cdk::update]
+fn register_user(payload: RegisterUserPayload) -> Result<User, Error> {
+    let owner = authenticated_caller()?;
+    // Anyone may sign up as a borrower or investor; other roles are assigned by administrators
+    if !matches!(payload.role, UserRole::Borrower | UserRole::Investor) {
+        resolve_caller().require(Permission::ManageUsers)?;
+    }
+    if payload.name.is_empty() || payload.email.is_empty() || payload.address.is_empty() {
+        return Err(Error::invalid_payload(
+            "name",
+            "Name, email, and address are required fields",
+        ));
+    }
+    validate_text_len("name", &payload.name)?;
+    validate_text_len("address", &payload.address)?;
+    validate_email_format(&payload.email)?;
+    validate_email_uniqueness(&payload.email, None)?;
+
+    let id = generate_uuid();
+    let user = User {
+        id,
+        owner,
+        name: payload.name,
+        email: payload.email,
+        address: payload.address,
+        role: payload.role,
+    };
+
+    store_user(&user, "register_user");
+    Ok(user)
+}
+
+// Users may update their own record; administrators may update any record.
+// The record keeps its original owner.
+#[ic_cdk::update]
+fn update_user(payload: UpdateUserPayload) -> Result<User, Error> {
+    let caller = authorize(Permission::UpdateProfile)?;
+    let existing = USERS_STORAGE
+        .with(|storage| storage.borrow().get(&payload.id))
+        .ok_or_else(|| Error::not_found(EntityKind::User, payload.id))?;
+    caller.require_owner(existing.owner, &format!("user {}", existing.id))?;
+    if payload.role != existing.role {
+        caller.require(Permission::ManageUsers)?;
+    }
+    validate_text_len("name", &payload.name)?;
+    validate_text_len("address", &payload.address)?;
+    validate_email_format(&payload.email)?;
+    validate_email_uniqueness(&payload.email, Some(payload.id))?;
+
+    let user = User {
+        id: payload.id,
+        owner: existing.owner,
+        name: payload.name,
+        email: payload.email,
+        address: payload.address,
+        role: payload.role,
+    };
+
+    store_user(&user, "update_user");
+    Ok(user)
+}
+
+// A user record as the caller may see it: contact details are redacted for
+// callers other than its owner without access to personal data
+fn user_view(caller: &Caller, user: User) -> User {
+    if caller.sees_personal_data(user.owner) {
+        user
+    } else {
+        kyc::redact_user(user)
+    }
+}
+
+#[ic_cdk::query]
+fn get_user(id: u64) -> Result<User, Error> {
+    let caller = resolve_caller();
+    if caller.user.as_ref().map(|user| user.id) != Some(id) {
+        caller.require(Permission::ViewUsers)?;
+    }
+    USERS_STORAGE.with(|storage| match storage.borrow().get(&id) {
+        Some(user) => Ok(user_view(&caller, user)),
+        None => Err(Error::not_found(EntityKind::User, id)),
+    })
+}
+
+// The user a principal registered first, visible to that principal and to
+// user administrators
+#[ic_cdk::query]
+fn get_user_by_owner(owner: Principal) -> Result<User, Error> {
+    let caller = resolve_caller();
+    if !caller.owns(owner) {
+        caller.require(Permission::ViewUsers)?;
+    }
+    index::users_by_owner(owner)
+        .first()
+        .and_then(|user_id| USERS_STORAGE.with(|storage| storage.borrow().get(user_id)))
+        .map(|user| user_view(&caller, user))
+        .ok_or(Error::NoRecords {
+            entity: EntityKind::User,
+        })
+}
+
+// Lists users page by page, optionally only those with a given role
+#[ic_cdk::query]
+fn get_all_users(
+    page: Option<PageRequest>,
+    filter: Option<UserFilter>,
+) -> Result<Page<User>, Error> {
+    let caller = authorize(Permission::ViewUsers)?;
+    let filter = filter.unwrap_or_default();
+    USERS_STORAGE.with(|storage| {
+        paginate(
+            storage.borrow().iter(),
+            &page.unwrap_or_default(),
+            |user| filter.role.map_or(true, |role| user.role == role),
+            |user| user_view(&caller, user),
+        )
+    })
+}
+
+// KYC Functions
+fn load_kyc_record(user_id: u64) -> Result<KycRecord, Error> {
+    KYC_STORAGE
+        .with(|storage| storage.borrow().get(&user_id))
+        .ok_or_else(|| Error::not_found(EntityKind::KycRecord, user_id))
+}
+
+// Stores a KYC record. The audit log is readable by every administrator for
+// as long as the canister lives, so it only keeps the redacted record.
+fn store_kyc_record(record: &KycRecord, operation: &str) {
+    let previous =
+        KYC_STORAGE.with(|storage| storage.borrow_mut().insert(record.user_id, record.clone()));
+    audit::record(
+        operation,
+        EntityKind::KycRecord,
+        record.user_id,
+        previous.map(kyc::redact_record).as_ref(),
+        &kyc::redact_record(record.clone()),
+    );
+}
+
+// A KYC record as the caller may see it: identifiers are masked for callers
+// other than its owner without access to personal data
+fn kyc_view(caller: &Caller, record: KycRecord) -> KycView {
+    let owner = USERS_STORAGE
+        .with(|storage| storage.borrow().get(&record.user_id))
+        .map(|user| user.owner);
+    if owner.is_some_and(|owner| caller.sees_personal_data(owner)) {
+        record.into()
+    } else {
+        kyc::redact_record(record).into()
+    }
+}
+
+// Borrowers submit their identity details for verification, or correct and
+// resubmit a rejected submission. Administrators may submit on a borrower's
+// behalf.
+#[ic_cdk::update]
+fn submit_kyc(payload: SubmitKycPayload) -> Result<KycView, Error> {
+    let caller = authorize(Permission::UpdateProfile)?;
+    let user = USERS_STORAGE
+        .with(|storage| storage.borrow().get(&payload.user_id))
+        .ok_or_else(|| Error::not_found(EntityKind::User, payload.user_id))?;
+    caller.require_owner(user.owner, &format!("user {}", user.id))?;
+    if user.role != UserRole::Borrower {
+        return Err(Error::invalid_payload(
+            "user_id",
+            format!("User {} is not a borrower", user.id),
+        ));
+    }
+    let payload = kyc::validate_submission(payload)?;
+
+    let mut record = KYC_STORAGE
+        .with(|storage| storage.borrow().get(&user.id))
+        .unwrap_or_else(|| KycRecord {
+            user_id: user.id,
+            national_id: String::new(),
+            phone: String::new(),
+            document_hash: String::new(),
+            status: KycStatus::Unverified,
+            submitted_at: 0,
+            verified_by: None,
+            reviewed_at: None,
+            rejection_reason: None,
+        });
+    kyc::apply_event(&mut record, KycEvent::Submit)?;
+    record.national_id = payload.national_id;
+    record.phone = payload.phone;
+    record.document_hash = payload.document_hash;
+    record.submitted_at = ic_cdk::api::time();
+    record.verified_by = None;
+    record.reviewed_at = None;
+    record.rejection_reason = None;
+
+    store_kyc_record(&record, "submit_kyc");
+    Ok(record.into())
+}
+
+// Marks a pending submission as verified once the officer has checked the
+// details against the identity document
+#[ic_cdk::update]
+fn verify_kyc(user_id: u64) -> Result<KycView, Error> {
+    let caller = authorize(Permission::VerifyKyc)?;
+    let mut record = load_kyc_record(user_id)?;
+    kyc::apply_event(&mut record, KycEvent::Verify)?;
+    record.verified_by = Some(caller.principal);
+    record.reviewed_at = Some(ic_cdk::api::time());
+    store_kyc_record(&record, "verify_kyc");
+    Ok(record.into())
+}
+
+// Rejects a pending submission with the reason given to the borrower
+#[ic_cdk::update]
+fn reject_kyc(payload: RejectKycPayload) -> Result<KycView, Error> {
+    let caller = authorize(Permission::VerifyKyc)?;
+    kyc::validate_reason(&payload.reason)?;
+    let mut record = load_kyc_record(payload.user_id)?;
+    kyc::apply_event(&mut record, KycEvent::Reject)?;
+    record.verified_by = Some(caller.principal);
+    record.reviewed_at = Some(ic_cdk::api::time());
+    record.rejection_reason = Some(payload.reason);
+    store_kyc_record(&record, "reject_kyc");
+    Ok(record.into())
+}
+
+// KYC status of a user, visible to the user and to user administrators
+#[ic_cdk::query]
+fn get_kyc_status(user_id: u64) -> Result<KycStatus, Error> {
+    let caller = resolve_caller();
+    if caller.user.as_ref().map(|user| user.id) != Some(user_id) {
+        caller.require(Permission::ViewUsers)?;
+    }
+    if !USERS_STORAGE.with(|storage| storage.borrow().contains_key(&user_id)) {
+        return Err(Error::not_found(EntityKind::User, user_id));
+    }
+    Ok(kyc::status(user_id))
+}
+
+#[ic_cdk::query]
+fn get_kyc_record(user_id: u64) -> Result<KycView, Error> {
+    let caller = resolve_caller();
+    if caller.user.as_ref().map(|user| user.id) != Some(user_id) {
+        caller.require(Permission::ViewUsers)?;
+    }
+    load_kyc_record(user_id).map(|record| kyc_view(&caller, record))
+}
+
+// Lists KYC records page by page, optionally only those in a given status;
+// officers use it to find pending submissions
+#[ic_cdk::query]
+fn get_all_kyc_records(
+    page: Option<PageRequest>,
+    filter: Option<KycFilter>,
+) -> Result<Page<KycView>, Error> {
+    let caller = authorize(Permission::VerifyKyc)?;
+    let filter = filter.unwrap_or_default();
+    KYC_STORAGE.with(|storage| {
+        paginate(
+            storage.borrow().iter(),
+            &page.unwrap_or_default(),
+            |record| filter.status.map_or(true, |status| record.status == status),
+            |record| kyc_view(&caller, record),
+        )
+    })
+}
+
+// Motorcycle Functions
+fn store_motorcycle(motorcycle: &Motorcycle, operation: &str) {
+    let previous = MOTORCYCLES_STORAGE.with(|storage| {
+        storage
+            .borrow_mut()
+            .insert(motorcycle.id, motorcycle.clone())
+    });
+    audit::record(
+        operation,
+        EntityKind::Motorcycle,
+        motorcycle.id,
+        previous.as_ref(),
+        motorcycle,
+    );
+}
+
+#[ic_cdk::update]
+fn register_motorcycle(payload: RegisterMotorcyclePayload) -> Result<Motorcycle, Error> {
+    authorize(Permission::ManageMotorcycles)?;
+    if payload.model.is_empty() || payload.manufacturer.is_empty() {
+        return Err(Error::invalid_payload(
+            "model",
+            "Model and manufacturer are required fields",
+        ));
+    }
+    validate_text_len("model", &payload.model)?;
+    validate_text_len("manufacturer", &payload.manufacturer)?;
+    if payload.price.is_zero() {
+        return Err(Error::invalid_payload(
+            "price",
+            "Price must be greater than zero",
+        ));
+    }
+
+    let id = generate_uuid();
+    let motorcycle = Motorcycle {
+        id,
+        model: payload.model,
+        manufacturer: payload.manufacturer,
+        price: payload.price,
+        status: MotorcycleStatus::Available,
+    };
+
+    store_motorcycle(&motorcycle, "register_motorcycle");
+    Ok(motorcycle)
+}
+
+#[ic_cdk::query]
+fn get_motorcycle(id: u64) -> Result<Motorcycle, Error> {
+    MOTORCYCLES_STORAGE.with(|storage| match storage.borrow().get(&id) {
+        Some(motorcycle) => Ok(motorcycle.clone()),
+        None => Err(Error::not_found(EntityKind::Motorcycle, id)),
+    })
+}
+
+// Lists motorcycles page by page, filtered by status, manufacturer and price
+#[ic_cdk::query]
+fn get_all_motorcycles(
+    page: Option<PageRequest>,
+    filter: Option<MotorcycleFilter>,
+) -> Result<Page<Motorcycle>, Error> {
+    let filter = filter.unwrap_or_default();
+    MOTORCYCLES_STORAGE.with(|storage| {
+        paginate(
+            storage.borrow().iter(),
+            &page.unwrap_or_default(),
+            |motorcycle| {
+                filter
+                    .status
+                    .map_or(true, |status| motorcycle.status == status)
+                    && filter.manufacturer.as_ref().map_or(true, |manufacturer| {
+                        motorcycle.manufacturer.eq_ignore_ascii_case(manufacturer)
+                    })
+                    && in_range(
+                        &motorcycle.price,
+                        filter.min_price.as_ref(),
+                        filter.max_price.as_ref(),
+                    )
+            },
+            |motorcycle| motorcycle,
+        )
+    })
+}
+
+#[ic_cdk::update]
+fn update_motorcycle_status(id: u64, status: MotorcycleStatus) -> Result<Motorcycle, Error> {
+    authorize(Permission::ManageMotorcycles)?;
+    let mut motorcycle = MOTORCYCLES_STORAGE
+        .with(|storage| storage.borrow().get(&id))
+        .ok_or_else(|| Error::not_found(EntityKind::Motorcycle, id))?;
+    manual_transition(&mut motorcycle, status)?;
+    store_motorcycle(&motorcycle, "update_motorcycle_status");
+    Ok(motorcycle)
+}
+
+// Loan Functions
+
+// Records a loan application for a borrower whose KYC is verified. Borrowers
+// apply for themselves; loan staff may apply on a borrower's behalf. The
+// repayment schedule is fixed at application; capital and the motorcycle are
+// only reserved on approval.
+#[ic_cdk::update]
+fn create_loan(payload: ApplyLoanPayload) -> Result<Loan, Error> {
+    let caller = resolve_caller();
+    let borrower = USERS_STORAGE
+        .with(|storage| storage.borrow().get(&payload.borrower_id))
+        .ok_or_else(|| Error::not_found(EntityKind::User, payload.borrower_id))?;
+    if caller.user.as_ref().map(|user| user.id) != Some(borrower.id) {
+        caller.require(Permission::ManageLoans)?;
+    }
+    if borrower.role != UserRole::Borrower {
+        return Err(Error::invalid_payload(
+            "borrower_id",
+            format!("User {} is not a borrower", borrower.id),
+        ));
+    }
+    kyc::ensure_verified(borrower.id)?;
+    let motorcycle = MOTORCYCLES_STORAGE
+        .with(|storage| storage.borrow().get(&payload.motorcycle_id))
+        .ok_or_else(|| Error::not_found(EntityKind::Motorcycle, payload.motorcycle_id))?;
+    if motorcycle.status != MotorcycleStatus::Available {
+        return Err(Error::invalid_state(
+            EntityKind::Motorcycle,
+            motorcycle.id,
+            format!("{:?} motorcycles cannot be financed", motorcycle.status),
+        ));
+    }
+    motorcycle
+        .price
+        .ensure_same_currency(&payload.principal_amount)?;
+    if payload.principal_amount.minor_units > motorcycle.price.minor_units {
+        return Err(Error::invalid_payload(
+            "principal_amount",
+            format!(
+                "Principal {} exceeds the motorcycle price {}",
+                payload.principal_amount, motorcycle.price
+            ),
+        ));
+    }
+
+    let start_date = day_start(parse_timestamp(&payload.start_date, "start_date")?);
+    let id = generate_uuid();
+    let schedule = generate_schedule(
+        id,
+        payload.principal_amount,
+        payload.interest_rate,
+        payload.term,
+        payload.frequency,
+        payload.interest_method,
+        start_date,
+    )?;
+    let total_interest = schedule.iter().try_fold(
+        Amount::zero(payload.principal_amount.currency),
+        |total, installment| total.checked_add(&installment.interest_part),
+    )?;
+
+    let loan = Loan {
+        id,
+        borrower_id: payload.borrower_id,
+        motorcycle_id: payload.motorcycle_id,
+        principal_amount: payload.principal_amount,
+        interest_rate: payload.interest_rate,
+        interest_method: payload.interest_method,
+        frequency: payload.frequency,
+        term: payload.term,
+        installment_amount: schedule[0].total_due,
+        total_interest,
+        start_date,
+        end_date: schedule[schedule.len() - 1].due_date,
+        status: LoanStatus::Applied,
+        disbursement_block_index: None,
+        total_paid: Amount::zero(payload.principal_amount.currency),
+        principal_paid: Amount::zero(payload.principal_amount.currency),
+        interest_paid: Amount::zero(payload.principal_amount.currency),
+        penalties_paid: Amount::zero(payload.principal_amount.currency),
+        penalties_due: Amount::zero(payload.principal_amount.currency),
+        interest_accrued: Amount::zero(payload.principal_amount.currency),
+        days_past_due: 0,
+        last_accrued_on: None,
+        pool_id: None,
+        decision_reason: None,
+        decided_by: None,
+        decided_at: None,
+    };
+
+    store_installments(schedule, "create_loan");
+
+    store_loan(&loan, "create_loan");
+    Ok(loan)
+}
+
+fn load_loan(loan_id: u64) -> Result<Loan, Error> {
+    LOANS_STORAGE
+        .with(|storage| storage.borrow().get(&loan_id))
+        .ok_or_else(|| Error::not_found(EntityKind::Loan, loan_id))
+}
+
+// Loans with the given IDs, skipping any that no longer exist
+fn load_loans(loan_ids: impl IntoIterator<Item = u64>) -> Vec<Loan> {
+    LOANS_STORAGE.with(|storage| {
+        let storage = storage.borrow();
+        loan_ids
+            .into_iter()
+            .filter_map(|loan_id| storage.get(&loan_id))
+            .collect()
+    })
+}
+
+// Stores a loan, brings its indexes and pool link up to date and records
+// the change
+fn store_loan(loan: &Loan, operation: &str) {
+    let previous = LOANS_STORAGE.with(|storage| storage.borrow_mut().insert(loan.id, loan.clone()));
+    index::index_loan(previous.as_ref(), loan);
+    sync_pool_loan(loan);
+    certification::certify_loan(loan);
+    audit::record(
+        operation,
+        EntityKind::Loan,
+        loan.id,
+        previous.as_ref(),
+        loan,
+    );
+}
+
+// Marks an application as being reviewed by loan staff
+#[ic_cdk::update]
+fn start_loan_review(loan_id: u64) -> Result<Loan, Error> {
+    authorize(Permission::ManageLoans)?;
+    let mut loan = load_loan(loan_id)?;
+    apply_loan_event(&mut loan, LoanEvent::StartReview)?;
+    store_loan(&loan, "start_loan_review");
+    Ok(loan)
+}
+
+// Approves an application, originating the loan: the motorcycle goes into
+// the loan and, if a pool is given, the principal is reserved from it
+#[ic_cdk::update]
+fn approve_loan(payload: ApproveLoanPayload) -> Result<Loan, Error> {
+    let caller = authorize(Permission::ManageLoans)?;
+    validate_text_len("reason", &payload.reason)?;
+    let mut loan = load_loan(payload.loan_id)?;
+    apply_loan_event(&mut loan, LoanEvent::Approve)?;
+    let mut motorcycle = MOTORCYCLES_STORAGE
+        .with(|storage| storage.borrow().get(&loan.motorcycle_id))
+        .ok_or_else(|| Error::not_found(EntityKind::Motorcycle, loan.motorcycle_id))?;
+    apply_event(&mut motorcycle, MotorcycleEvent::Originated)?;
+
+    // Reserve the principal before anything is stored so a rejected
+    // reservation leaves the application untouched
+    let pool = match payload.pool_id {
+        Some(pool_id) => {
+            let pool = LOAN_POOLS_STORAGE
+                .with(|storage| storage.borrow().get(&pool_id))
+                .ok_or_else(|| Error::not_found(EntityKind::LoanPool, pool_id))?;
+            let pool = reserve_capital(pool, &mut loan)?;
+            verify_pool(&pool, Some(&loan))?;
+            Some(pool)
+        }
+        None => None,
+    };
+    loan.decision_reason = Some(payload.reason);
+    loan.decided_by = Some(caller.principal);
+    loan.decided_at = Some(ic_cdk::api::time());
+
+    if let Some(pool) = pool {
+        store_pool(&pool, "approve_loan");
+    }
+    store_motorcycle(&motorcycle, "approve_loan");
+    store_loan(&loan, "approve_loan");
+    Ok(loan)
+}
+
+// Rejects an application with the reason given to the borrower
+#[ic_cdk::update]
+fn reject_loan(payload: RejectLoanPayload) -> Result<Loan, Error> {
+    let caller = authorize(Permission::ManageLoans)?;
+    if payload.reason.trim().is_empty() {
+        return Err(Error::invalid_payload(
+            "reason",
+            "A rejection needs a reason",
+        ));
+    }
+    validate_text_len("reason", &payload.reason)?;
+    let mut loan = load_loan(payload.loan_id)?;
+    apply_loan_event(&mut loan, LoanEvent::Reject)?;
+    loan.decision_reason = Some(payload.reason);
+    loan.decided_by = Some(caller.principal);
+    loan.decided_at = Some(ic_cdk::api::time());
+    store_loan(&loan, "reject_loan");
+    Ok(loan)
+}
+
+// A loan with the certificate and witness that prove it
+#[ic_cdk::query]
+fn get_loan(id: u64) -> Result<Certified<LoanView>, Error> {
+    let loan = LOANS_STORAGE
+        .with(|storage| storage.borrow().get(&id))
+        .ok_or_else(|| Error::not_found(EntityKind::Loan, id))?;
+    authorize_loan_access(&loan)?;
+    Ok(certification::certified_loan(loan.into()))
+}
+
+// Lists loans page by page, filtered by status, borrower and funding pool.
+// Borrower and status filters are served from their indexes.
+#[ic_cdk::query]
+fn get_all_loans(
+    page: Option<PageRequest>,
+    filter: Option<LoanFilter>,
+) -> Result<Page<LoanView>, Error> {
+    authorize(Permission::ViewLoans)?;
+    let filter = filter.unwrap_or_default();
+    let keep = |loan: &Loan| {
+        filter.status.map_or(true, |status| loan.status == status)
+            && filter
+                .borrower_id
+                .map_or(true, |borrower_id| loan.borrower_id == borrower_id)
+            && filter
+                .pool_id
+                .map_or(true, |pool_id| loan.pool_id == Some(pool_id))
+    };
+    let page = page.unwrap_or_default();
+    let candidates = match (filter.borrower_id, filter.status) {
+        (Some(borrower_id), _) => Some(index::borrower_loan_ids(borrower_id)),
+        (None, Some(status)) => Some(index::status_loan_ids(status)),
+        (None, None) => None,
+    };
+    match candidates {
+        Some(loan_ids) => paginate(
+            load_loans(loan_ids).into_iter().map(|loan| (loan.id, loan)),
+            &page,
+            keep,
+            LoanView::from,
+        ),
+        None => LOANS_STORAGE
+            .with(|storage| paginate(storage.borrow().iter(), &page, keep, LoanView::from)),
+    }
+}
+
+// Loans taken out by a borrower, visible to the borrower and loan staff
+#[ic_cdk::query]
+fn get_loans_by_borrower(borrower_id: u64) -> Result<Vec<LoanView>, Error> {
+    let caller = resolve_caller();
+    if caller.user.as_ref().map(|user| user.id) != Some(borrower_id) {
+        caller.require(Permission::ViewLoans)?;
+    }
+    Ok(load_loans(index::borrower_loan_ids(borrower_id))
+        .into_iter()
+        .map(LoanView::from)
+        .collect())
+}
+
+#[ic_cdk::query]
+fn get_loans_by_status(status: LoanStatus) -> Result<Vec<LoanView>, Error> {
+    authorize(Permission::ViewLoans)?;
+    Ok(load_loans(index::status_loan_ids(status))
+        .into_iter()
+        .map(LoanView::from)
+        .collect())
+}
+
+// Returns the installment plan of a loan in due-date order
+#[ic_cdk::query]
+fn get_loan_schedule(loan_id: u64) -> Result<Vec<InstallmentView>, Error> {
+    let loan = LOANS_STORAGE
+        .with(|storage| storage.borrow().get(&loan_id))
+        .ok_or_else(|| Error::not_found(EntityKind::Loan, loan_id))?;
+    authorize_loan_access(&loan)?;
+
+    Ok(loan_installments(loan_id)
+        .into_iter()
+        .map(InstallmentView::from)
+        .collect())
+}
+
+#[ic_cdk::update]
+fn update_loan_status(id: u64, status: LoanStatus) -> Result<Loan, Error> {
+    authorize(Permission::ManageLoans)?;
+    let mut loan = load_loan(id)?;
+    loan::manual_transition(&mut loan, status)?;
+    store_loan(&loan, "update_loan_status");
+    Ok(loan)
+}
+
+// Sends the loan principal from the canister's ledger account to the
+// borrower's principal and records the ledger block index on the loan
+#[ic_cdk::update]
+async fn disburse_loan(loan_id: u64) -> Result<Loan, Error> {
+    authorize(Permission::ManageLoans)?;
+    let loan = load_loan(loan_id)?;
+    // Check the transition before any funds move
+    apply_loan_event(&mut loan.clone(), LoanEvent::Disburse)?;
+    let borrower = USERS_STORAGE
+        .with(|storage| storage.borrow().get(&loan.borrower_id))
+        .ok_or_else(|| Error::not_found(EntityKind::User, loan.borrower_id))?;
+
+    if !DISBURSEMENTS_IN_FLIGHT.with(|in_flight| in_flight.borrow_mut().insert(loan_id)) {
+        return Err(Error::invalid_state(
+            EntityKind::Loan,
+            loan_id,
+            "Loan is already being disbursed",
+        ));
+    }
+    let result = ledger::transfer(borrower.owner, &loan.principal_amount, loan_id).await;
+    DISBURSEMENTS_IN_FLIGHT.with(|in_flight| in_flight.borrow_mut().remove(&loan_id));
+    let block_index = result?;
+
+    // The principal has left the canister: record its block even if the loan
+    // changed while the transfer was in flight and can no longer move to
+    // Disbursed, so the payout is never lost
+    let mut loan = load_loan(loan_id)?;
+    loan.disbursement_block_index = Some(block_index);
+    let transition = apply_loan_event(&mut loan, LoanEvent::Disburse);
+    store_loan(&loan, "disburse_loan");
+    transition.map(|_| loan)
+}
+
+// Default and Recovery Functions
+
+fn load_recovery_case(loan_id: u64) -> Result<RecoveryCase, Error> {
+    RECOVERY_CASES_STORAGE
+        .with(|storage| storage.borrow().get(&loan_id))
+        .ok_or_else(|| Error::not_found(EntityKind::RecoveryCase, loan_id))
+}
+
+fn store_recovery_case(case: &RecoveryCase, operation: &str) {
+    let previous = RECOVERY_CASES_STORAGE
+        .with(|storage| storage.borrow_mut().insert(case.loan_id, case.clone()));
+    audit::record(
+        operation,
+        EntityKind::RecoveryCase,
+        case.loan_id,
+        previous.as_ref(),
+        case,
+    );
+}
+
+// Opens the recovery of a defaulted loan by notifying the borrower; the
+// motorcycle can be repossessed once the grace period has passed. A notice
+// that has not led to a repossession order yet can be reissued.
+#[ic_cdk::update]
+fn issue_default_notice(payload: DefaultNoticePayload) -> Result<RecoveryCase, Error> {
+    authorize(Permission::ManageLoans)?;
+    let loan = LOANS_STORAGE
+        .with(|storage| storage.borrow().get(&payload.loan_id))
+        .ok_or_else(|| Error::not_found(EntityKind::Loan, payload.loan_id))?;
+    if loan.status != LoanStatus::Defaulted {
+        return Err(Error::invalid_state(
+            EntityKind::Loan,
+            loan.id,
+            format!("{:?} loans cannot be given a default notice", loan.status),
+        ));
+    }
+    if let Some(case) = RECOVERY_CASES_STORAGE.with(|storage| storage.borrow().get(&loan.id)) {
+        ensure_stage(&case, RecoveryStage::NoticeIssued)?;
+    }
+
+    let now = ic_cdk::api::time();
+    let grace_period_ends_at = (payload.grace_period_days as u64)
+        .checked_mul(NANOS_PER_DAY)
+        .and_then(|nanos| now.checked_add(nanos))
+        .ok_or_else(|| Error::invalid_payload("grace_period_days", "Grace period is too long"))?;
+    let case = RecoveryCase {
+        loan_id: loan.id,
+        motorcycle_id: loan.motorcycle_id,
+        stage: RecoveryStage::NoticeIssued,
+        notice_issued_at: now,
+        grace_period_ends_at,
+        agent: None,
+        repossession_ordered_at: None,
+        repossessed_at: None,
+        sale_price: None,
+        sold_at: None,
+        allocation: None,
+        surplus: None,
+        written_off: None,
+        closed_at: None,
+    };
+    store_recovery_case(&case, "issue_default_notice");
+    Ok(case)
+}
+
+// Assigns the repossession of the motorcycle to an agent once the grace
+// period has passed without the loan being reinstated
+#[ic_cdk::update]
+fn order_repossession(payload: RepossessionOrderPayload) -> Result<RecoveryCase, Error> {
+    authorize(Permission::ManageLoans)?;
+    let mut case = load_recovery_case(payload.loan_id)?;
+    ensure_stage(&case, RecoveryStage::NoticeIssued)?;
+    let loan = LOANS_STORAGE
+        .with(|storage| storage.borrow().get(&payload.loan_id))
+        .ok_or_else(|| Error::not_found(EntityKind::Loan, payload.loan_id))?;
+    if loan.status != LoanStatus::Defaulted {
+        return Err(Error::invalid_state(
+            EntityKind::Loan,
+            loan.id,
+            format!("{:?} loans cannot be repossessed", loan.status),
+        ));
+    }
+    let now = ic_cdk::api::time();
+    if now < case.grace_period_ends_at {
+        return Err(Error::invalid_state(
+            EntityKind::RecoveryCase,
+            case.loan_id,
+            format!(
+                "Grace period runs until {}",
+                format_timestamp(case.grace_period_ends_at)
+            ),
+        ));
+    }
+
+    case.agent = Some(payload.agent);
+    case.repossession_ordered_at = Some(now);
+    case.stage = RecoveryStage::RepossessionOrdered;
+    store_recovery_case(&case, "order_repossession");
+    Ok(case)
+}
+
+// Records that the motorcycle has been taken back. Callable by the assigned
+// agent or by loan staff.
+#[ic_cdk::update]
+fn confirm_repossession(loan_id: u64) -> Result<RecoveryCase, Error> {
+    let caller = resolve_caller();
+    let mut case = load_recovery_case(loan_id)?;
+    if case.agent != Some(caller.principal) {
+        caller.require(Permission::ManageLoans)?;
+    }
+    ensure_stage(&case, RecoveryStage::RepossessionOrdered)?;
+    let mut motorcycle = MOTORCYCLES_STORAGE
+        .with(|storage| storage.borrow().get(&case.motorcycle_id))
+        .ok_or_else(|| Error::not_found(EntityKind::Motorcycle, case.motorcycle_id))?;
+
+    apply_event(&mut motorcycle, MotorcycleEvent::Repossessed)?;
+    case.repossessed_at = Some(ic_cdk::api::time());
+    case.stage = RecoveryStage::Repossessed;
+    store_motorcycle(&motorcycle, "confirm_repossession");
+    store_recovery_case(&case, "confirm_repossession");
+    Ok(case)
+}
+
+// Records the price the repossessed motorcycle was sold for
+#[ic_cdk::update]
+fn record_recovery_sale(payload: RecoverySalePayload) -> Result<RecoveryCase, Error> {
+    authorize(Permission::ManageLoans)?;
+    let mut case = load_recovery_case(payload.loan_id)?;
+    ensure_stage(&case, RecoveryStage::Repossessed)?;
+    let loan = LOANS_STORAGE
+        .with(|storage| storage.borrow().get(&payload.loan_id))
+        .ok_or_else(|| Error::not_found(EntityKind::Loan, payload.loan_id))?;
+    loan.principal_amount
+        .ensure_same_currency(&payload.sale_price)?;
+
+    case.sale_price = Some(payload.sale_price);
+    case.sold_at = Some(ic_cdk::api::time());
+    case.stage = RecoveryStage::Sold;
+    store_recovery_case(&case, "record_recovery_sale");
+    Ok(case)
+}
+
+// Closes a recovery: applies the sale proceeds to the loan balance, shares
+// recovered interest and penalties with the funding pool's investors, writes
+// the unpaid principal off against the pool and returns the motorcycle to
+// inventory
+#[ic_cdk::update]
+fn close_recovery(loan_id: u64) -> Result<RecoveryCase, Error> {
+    authorize(Permission::ManageLoans)?;
+    let mut case = load_recovery_case(loan_id)?;
+    ensure_stage(&case, RecoveryStage::Sold)?;
+    let sale_price = case.sale_price.ok_or_else(|| {
+        Error::invalid_state(EntityKind::RecoveryCase, loan_id, "Sale price is missing")
+    })?;
+    let loan = LOANS_STORAGE
+        .with(|storage| storage.borrow().get(&loan_id))
+        .ok_or_else(|| Error::not_found(EntityKind::Loan, loan_id))?;
+    let mut motorcycle = MOTORCYCLES_STORAGE
+        .with(|storage| storage.borrow().get(&case.motorcycle_id))
+        .ok_or_else(|| Error::not_found(EntityKind::Motorcycle, case.motorcycle_id))?;
+    // The motorcycle may already have been refurbished and returned to inventory
+    if motorcycle.status != MotorcycleStatus::Available {
+        apply_event(&mut motorcycle, MotorcycleEvent::Refurbished)?;
+    }
+
+    let now = ic_cdk::api::time();
+    let installments = loan_installments(loan_id);
+    let (settlement, surplus, written_off) =
+        apply_recovery(loan, installments, sale_price, day_start(now))?;
+    let distribution = match settlement.loan.pool_id {
+        Some(pool_id) => Some(settle_pool(pool_id, &settlement)?),
+        None => None,
+    };
+
+    if let Some((distribution, investors)) = distribution {
+        store_distribution(distribution, investors, "close_recovery");
+    }
+    store_installments(settlement.installments, "close_recovery");
+    store_loan(&settlement.loan, "close_recovery");
+    store_motorcycle(&motorcycle, "close_recovery");
+
+    case.allocation = Some(settlement.allocation);
+    case.surplus = Some(surplus);
+    case.written_off = Some(written_off);
+    case.closed_at = Some(now);
+    case.stage = RecoveryStage::Closed;
+    store_recovery_case(&case, "close_recovery");
+    Ok(case)
+}
+
+#[ic_cdk::query]
+fn get_recovery_case(loan_id: u64) -> Result<RecoveryCaseView, Error> {
+    authorize(Permission::ViewLoans)?;
+    load_recovery_case(loan_id).map(RecoveryCaseView::from)
+}
+
+#[ic_cdk::query]
+fn get_recovery_cases() -> Result<Vec<RecoveryCaseView>, Error> {
+    authorize(Permission::ViewLoans)?;
+    Ok(RECOVERY_CASES_STORAGE.with(|storage| {
+        storage
+            .borrow()
+            .iter()
+            .map(|(_, case)| case.into())
+            .collect()
+    }))
+}
+
+// Payment Functions
+
+// Pulls the repayment from the caller through the ledger (ICRC-2
+// `transfer_from`, so the caller must approve this canister first) and then
+// settles it against the loan
+#[ic_cdk::update]
+async fn create_payment(payload: MakePaymentPayload) -> Result<Payment, Error> {
+    let caller = authorize(Permission::MakePayments)?;
+    if payload.amount.is_zero() {
+        return Err(Error::invalid_payload(
+            "amount",
+            "Payment amount must be greater than zero",
+        ));
+    }
+    let borrower = USERS_STORAGE
+        .with(|storage| storage.borrow().get(&payload.borrower_id))
+        .ok_or_else(|| Error::not_found(EntityKind::User, payload.borrower_id))?;
+    caller.require_owner(borrower.owner, &format!("borrower {}", borrower.id))?;
+    let loan = LOANS_STORAGE
+        .with(|storage| storage.borrow().get(&payload.loan_id))
+        .ok_or_else(|| Error::not_found(EntityKind::Loan, payload.loan_id))?;
+    if loan.borrower_id != payload.borrower_id {
+        return Err(Error::Unauthorized {
+            msg: format!(
+                "Loan with ID {} does not belong to borrower {}",
+                loan.id, payload.borrower_id
+            ),
+        });
+    }
+
+    let payer = caller.principal;
+    let now = ic_cdk::api::time();
+    let id = generate_uuid();
+    let mut payment = Payment {
+        id,
+        loan_id: payload.loan_id,
+        borrower_id: payload.borrower_id,
+        amount: payload.amount,
+        status: PaymentStatus::Pending,
+        date: now,
+        allocation: None,
+        failure_reason: None,
+        ledger_block_index: None,
+        refund_block_index: None,
+    };
+
+    // Reject payments the loan cannot accept before any funds move
+    let installments = loan_installments(loan.id);
+    if let Err(error) = apply_payment(loan, installments, payment.amount, day_start(now)) {
+        payment.status = PaymentStatus::Failed;
+        payment.failure_reason = Some(truncate_text(error.to_string()));
+        store_payment(&payment, "create_payment");
+        return Ok(payment);
+    }
+    store_payment(&payment, "create_payment");
+
+    match ledger::transfer_from(payer, &payment.amount, id).await {
+        Ok(block_index) => {
+            payment.ledger_block_index = Some(block_index);
+            match LOANS_STORAGE.with(|storage| storage.borrow().get(&payment.loan_id)) {
+                Some(loan) => settle_payment(
+                    &mut payment,
+                    loan,
+                    day_start(ic_cdk::api::time()),
+                    "create_payment",
+                ),
+                None => {
+                    payment.status = PaymentStatus::Failed;
+                    payment.failure_reason = Some(truncate_text(
+                        Error::not_found(EntityKind::Loan, payment.loan_id).to_string(),
+                    ));
+                }
+            }
+            store_payment(&payment, "create_payment");
+
+            // The loan changed while the transfer was in flight: return the funds
+            if payment.status == PaymentStatus::Failed {
+                match ledger::transfer(payer, &payment.amount, id).await {
+                    Ok(refund_block_index) => payment.refund_block_index = Some(refund_block_index),
+                    Err(e) => {
+                        let reason = payment.failure_reason.take().unwrap_or_default();
+                        payment.failure_reason =
+                            Some(truncate_text(format!("{}; refund failed: {}", reason, e)));
+                    }
+                }
+                store_payment(&payment, "create_payment");
+            }
+        }
+        Err(error) => {
+            payment.status = PaymentStatus::Failed;
+            payment.failure_reason = Some(truncate_text(error.to_string()));
+            store_payment(&payment, "create_payment");
+        }
+    }
+
+    Ok(payment)
+}
+
+fn store_payment(payment: &Payment, operation: &str) {
+    let previous =
+        PAYMENTS_STORAGE.with(|payments| payments.borrow_mut().insert(payment.id, payment.clone()));
+    index::index_payment(payment);
+    certification::certify_payment(payment);
+    audit::record(
+        operation,
+        EntityKind::Payment,
+        payment.id,
+        previous.as_ref(),
+        payment,
+    );
+}
+
+// Applies a pending payment to its loan and moves it to Completed, or to
+// Failed with the reason the loan could not accept it
+fn settle_payment(payment: &mut Payment, loan: Loan, today: u64, operation: &str) {
+    let installments = loan_installments(loan.id);
+    let settlement =
+        apply_payment(loan, installments, payment.amount, today).and_then(|settlement| {
+            let distribution = match settlement.loan.pool_id {
+                Some(pool_id) => Some(settle_pool(pool_id, &settlement)?),
+                None => None,
+            };
+            Ok((settlement, distribution))
+        });
+    match settlement {
+        Ok((settlement, distribution)) => {
+            let loan = settlement.loan;
+            if let Some((distribution, investors)) = distribution {
+                store_distribution(distribution, investors, operation);
+            }
+            if loan.status == LoanStatus::Completed {
+                let motorcycle =
+                    MOTORCYCLES_STORAGE.with(|storage| storage.borrow().get(&loan.motorcycle_id));
+                if let Some(mut motorcycle) = motorcycle {
+                    // A payoff never fails on the motorcycle's bookkeeping
+                    match apply_event(&mut motorcycle, MotorcycleEvent::PaidOff) {
+                        Ok(()) => store_motorcycle(&motorcycle, operation),
+                        Err(error) => audit::record(
+                            operation,
+                            EntityKind::Motorcycle,
+                            motorcycle.id,
+                            None,
+                            &serde_json::json!({
+                                "loan_id": loan.id,
+                                "event": "PaidOff",
+                                "failure_reason": error.to_string(),
+                            }),
+                        ),
+                    }
+                }
+            }
+            store_installments(settlement.installments, operation);
+            store_loan(&loan, operation);
+
+            payment.status = PaymentStatus::Completed;
+            payment.allocation = Some(settlement.allocation);
+        }
+        Err(error) => {
+            payment.status = PaymentStatus::Failed;
+            payment.failure_reason = Some(truncate_text(error.to_string()));
+        }
+    }
+}
+
+// Returns repaid principal to the funding pool, charges the shortfall of a
+// written-off loan to it, and shares the interest and penalties collected
+// among the loan's participation holders and the pool's investors. Also
+// returns the investors with their lifetime returns updated.
+fn settle_pool(
+    pool_id: u64,
+    settlement: &Settlement,
+) -> Result<(Distribution, Vec<Investor>), Error> {
+    let pool = LOAN_POOLS_STORAGE
+        .with(|storage| storage.borrow().get(&pool_id))
+        .ok_or_else(|| Error::not_found(EntityKind::LoanPool, pool_id))?;
+    let pool = settle_capital(pool, &settlement.loan, &settlement.allocation.principal)?;
+    verify_pool(&pool, Some(&settlement.loan))?;
+
+    let income = settlement
+        .allocation
+        .interest
+        .checked_add(&settlement.allocation.penalties)?;
+    let platform_fee_bps = FEE_CONFIG.with(|cell| cell.borrow().get().platform_fee_bps);
+    let distribution = distribute_income(
+        pool,
+        pool_positions(pool_id),
+        &market::loan_participations(settlement.loan.id),
+        &income,
+        platform_fee_bps,
+    )?;
+
+    let mut investors = Vec::new();
+    for (investor_id, returns) in &distribution.returns {
+        let mut investor = INVESTORS_STORAGE
+            .with(|storage| storage.borrow().get(investor_id))
+            .ok_or_else(|| Error::not_found(EntityKind::Investor, *investor_id))?;
+        add_to_totals(&mut investor.returns_earned, returns)?;
+        investors.push(investor);
+    }
+    Ok((distribution, investors))
+}
+
+fn store_distribution(distribution: Distribution, investors: Vec<Investor>, operation: &str) {
+    for investor in &investors {
+        store_investor(investor, operation);
+    }
+    for position in &distribution.positions {
+        store_position(position, operation);
+    }
+    store_pool(&distribution.pool, operation);
+}
+
+// A payment with the certificate and witness that prove it, visible to
+// whoever may see its loan
+#[ic_cdk::query]
+fn get_payment(id: u64) -> Result<Certified<PaymentView>, Error> {
+    let payment = PAYMENTS_STORAGE
+        .with(|storage| storage.borrow().get(&id))
+        .ok_or_else(|| Error::not_found(EntityKind::Payment, id))?;
+    let loan = load_loan(payment.loan_id)?;
+    authorize_loan_access(&loan)?;
+    Ok(certification::certified_payment(payment.into()))
+}
+
+// Lists the payments made on a loan page by page, optionally by status
+#[ic_cdk::query]
+fn get_all_payments_for_loan(
+    loan_id: u64,
+    page: Option<PageRequest>,
+    filter: Option<PaymentFilter>,
+) -> Result<Page<PaymentView>, Error> {
+    let loan = LOANS_STORAGE
+        .with(|storage| storage.borrow().get(&loan_id))
+        .ok_or_else(|| Error::not_found(EntityKind::Loan, loan_id))?;
+    authorize_loan_access(&loan)?;
+    let filter = filter.unwrap_or_default();
+    PAYMENTS_STORAGE.with(|storage| {
+        let storage = storage.borrow();
+        let payments = index::loan_payment_ids(loan_id)
+            .into_iter()
+            .filter_map(|payment_id| {
+                storage
+                    .get(&payment_id)
+                    .map(|payment| (payment_id, payment))
+            });
+        paginate(
+            payments,
+            &page.unwrap_or_default(),
+            |payment| {
+                filter
+                    .status
+                    .map_or(true, |status| payment.status == status)
+            },
+            PaymentView::from,
+        )
+    })
+}
+
+// Investor and Loan Pool Functions
+fn store_investor(investor: &Investor, operation: &str) {
+    let previous = INVESTORS_STORAGE
+        .with(|storage| storage.borrow_mut().insert(investor.id, investor.clone()));
+    index::index_investor(investor);
+    audit::record(
+        operation,
+        EntityKind::Investor,
+        investor.id,
+        previous.as_ref(),
+        investor,
+    );
+}
+
+fn store_pool(pool: &LoanPool, operation: &str) {
+    let previous =
+        LOAN_POOLS_STORAGE.with(|storage| storage.borrow_mut().insert(pool.id, pool.clone()));
+    certification::certify_pool(pool);
+    audit::record(
+        operation,
+        EntityKind::LoanPool,
+        pool.id,
+        previous.as_ref(),
+        pool,
+    );
+}
+
+#[ic_cdk::update]
+fn register_investor(payload: RegisterInvestorPayload) -> Result<Investor, Error> {
+    let owner = authenticated_caller()?;
+    if payload.name.is_empty() || payload.email.is_empty() {
+        return Err(Error::invalid_payload(
+            "name",
+            "Name and email are required fields",
+        ));
+    }
+    validate_text_len("name", &payload.name)?;
+    validate_text_len("email", &payload.email)?;
+
+    let id = generate_uuid();
+    let investor = Investor {
+        id,
+        owner,
+        name: payload.name,
+        email: payload.email,
+        total_invested: Vec::new(),
+        returns_earned: Vec::new(),
+    };
+
+    store_investor(&investor, "register_investor");
+    Ok(investor)
+}
+
+#[ic_cdk::update]
+fn create_loan_pool(payload: CreateLoanPoolPayload) -> Result<LoanPool, Error> {
+    authorize(Permission::ManagePools)?;
+    if payload.name.is_empty() {
+        return Err(Error::invalid_payload("name", "Name is a required field"));
+    }
+    validate_text_len("name", &payload.name)?;
+
+    let id = generate_uuid();
+    let pool = LoanPool {
+        id,
+        name: payload.name,
+        total_funds: Amount::zero(payload.currency),
+        available_funds: Amount::zero(payload.currency),
+        allocated_funds: Amount::zero(payload.currency),
+        total_shares: 0,
+        platform_fees: Amount::zero(payload.currency),
+    };
+
+    store_pool(&pool, "create_loan_pool");
+    Ok(pool)
+}
+
+// Investor deposit into a loan pool, pulled from the investor's account with
+// ICRC-2 `transfer_from`. The deposit mints pool shares at the pool's current
+// value per share.
+#[ic_cdk::update]
+async fn add_funds_to_pool(payload: AddFundsPayload) -> Result<PoolPosition, Error> {
+    let caller = authorize(Permission::Invest)?;
+    if payload.amount.is_zero() {
+        return Err(Error::invalid_payload(
+            "amount",
+            "Deposit amount must be greater than zero",
+        ));
+    }
+    let pool = LOAN_POOLS_STORAGE
+        .with(|storage| storage.borrow().get(&payload.pool_id))
+        .ok_or_else(|| Error::not_found(EntityKind::LoanPool, payload.pool_id))?;
+    pool.total_funds.ensure_same_currency(&payload.amount)?;
+    let investor = INVESTORS_STORAGE
+        .with(|storage| storage.borrow().get(&payload.investor_id))
+        .ok_or_else(|| Error::not_found(EntityKind::Investor, payload.investor_id))?;
+    caller.require_owner(investor.owner, &format!("investor {}", investor.id))?;
+
+    // Check the deposit can be credited before any funds move
+    deposit_credit(payload.pool_id, investor.id, &payload.amount)?;
+
+    let block_index = ledger::transfer_from(caller.principal, &payload.amount, pool.id).await?;
+
+    // Re-read the pool: its value per share may have changed while the
+    // transfer was in flight
+    let error = match deposit_credit(payload.pool_id, investor.id, &payload.amount) {
+        Ok((pool, investor, position)) => {
+            store_investor(&investor, "add_funds_to_pool");
+            store_pool(&pool, "add_funds_to_pool");
+            store_position(&position, "add_funds_to_pool");
+            return Ok(position);
         }
+        Err(error) => error,
+    };
+
+    // The pool or investor changed under the transfer: return the deposit and
+    // record both blocks against the pool
+    let refund = ledger::transfer(caller.principal, &payload.amount, payload.pool_id).await;
+    audit::record(
+        "add_funds_to_pool",
+        EntityKind::PoolPosition,
+        payload.pool_id,
+        None,
+        &serde_json::json!({
+            "investor_id": investor.id,
+            "amount": payload.amount,
+            "ledger_block_index": block_index,
+            "refund_block_index": refund.as_ref().ok(),
+            "failure_reason": error.to_string(),
+        }),
+    );
+    Err(Error::PaymentFailed {
+        msg: match refund {
+            Ok(refund_block_index) => format!(
+                "{}; deposit refunded in ledger block {}",
+                error, refund_block_index
+            ),
+            Err(e) => format!("{}; refund failed: {}", error, e),
+        },
     })
 }
 
-// Payment Functions
+// The pool, investor and position a deposit leaves behind, priced at the
+// pool's current value per share. Nothing is stored.
+fn deposit_credit(
+    pool_id: u64,
+    investor_id: u64,
+    amount: &Amount,
+) -> Result<(LoanPool, Investor, PoolPosition), Error> {
+    let mut pool = LOAN_POOLS_STORAGE
+        .with(|storage| storage.borrow().get(&pool_id))
+        .ok_or_else(|| Error::not_found(EntityKind::LoanPool, pool_id))?;
+    let shares = shares_for_amount(&pool, &pool_nav(&pool)?, amount, false)?;
+    let (investor, position) = credited(&mut pool, investor_id, amount, shares)?;
+    Ok((pool, investor, position))
+}
+
+// Credits capital and shares to a pool, the investor's position in it and the
+// investor's total invested, and checks the pool still balances
+fn credited(
+    pool: &mut LoanPool,
+    investor_id: u64,
+    amount: &Amount,
+    shares: u64,
+) -> Result<(Investor, PoolPosition), Error> {
+    let mut investor = INVESTORS_STORAGE
+        .with(|storage| storage.borrow().get(&investor_id))
+        .ok_or_else(|| Error::not_found(EntityKind::Investor, investor_id))?;
+    let mut position = load_position(pool, investor_id);
+    credit_position(pool, &mut position, amount, shares)?;
+    add_to_totals(&mut investor.total_invested, amount)?;
+    verify_pool(pool, None)?;
+    Ok((investor, position))
+}
+
+// Credits a pool, position and investor as `credited` does, then stores all
+// three
+fn credit_investor(
+    pool: &mut LoanPool,
+    investor_id: u64,
+    amount: &Amount,
+    shares: u64,
+    operation: &str,
+) -> Result<PoolPosition, Error> {
+    let (investor, position) = credited(pool, investor_id, amount, shares)?;
+    store_investor(&investor, operation);
+    store_pool(pool, operation);
+    store_position(&position, operation);
+    Ok(position)
+}
+
+// Pays uncommitted pool capital back to an investor with an ICRC-1 transfer,
+// burning the shares it is worth. Capital lent out to loans cannot be
+// withdrawn until it is repaid.
 #[ic_cdk::update]
-fn create_payment(payload: MakePaymentPayload) -> Result<Payment, String> {
-    if payload.amount <= 0.0 {
-        return Err("Invalid payment amount".to_string());
+async fn withdraw_from_pool(payload: WithdrawFundsPayload) -> Result<PoolPosition, Error> {
+    let caller = authorize(Permission::Invest)?;
+    if payload.amount.is_zero() {
+        return Err(Error::invalid_payload(
+            "amount",
+            "Withdrawal amount must be greater than zero",
+        ));
+    }
+    let mut pool = LOAN_POOLS_STORAGE
+        .with(|storage| storage.borrow().get(&payload.pool_id))
+        .ok_or_else(|| Error::not_found(EntityKind::LoanPool, payload.pool_id))?;
+    let mut investor = INVESTORS_STORAGE
+        .with(|storage| storage.borrow().get(&payload.investor_id))
+        .ok_or_else(|| Error::not_found(EntityKind::Investor, payload.investor_id))?;
+    caller.require_owner(investor.owner, &format!("investor {}", investor.id))?;
+
+    // Debit before the transfer so concurrent withdrawals cannot spend the same funds
+    let mut position = load_position(&pool, investor.id);
+    let nav = pool_nav(&pool)?;
+    let shares = debit_position(&mut pool, &nav, &mut position, &payload.amount)?;
+    sub_from_totals(&mut investor.total_invested, &payload.amount)?;
+    verify_pool(&pool, None)?;
+    store_investor(&investor, "withdraw_from_pool");
+    store_pool(&pool, "withdraw_from_pool");
+    store_position(&position, "withdraw_from_pool");
+
+    if let Err(error) = ledger::transfer(investor.owner, &payload.amount, payload.pool_id).await {
+        // The funds never left: restore the capital and the burned shares
+        let mut pool = LOAN_POOLS_STORAGE
+            .with(|storage| storage.borrow().get(&payload.pool_id))
+            .ok_or_else(|| Error::not_found(EntityKind::LoanPool, payload.pool_id))?;
+        credit_investor(
+            &mut pool,
+            investor.id,
+            &payload.amount,
+            shares,
+            "withdraw_from_pool",
+        )?;
+        return Err(error);
     }
 
-    let id = generate_uuid();
-    let payment = Payment {
-        id,
-        loan_id: payload.loan_id,
-        borrower_id: payload.borrower_id,
-        amount: payload.amount,
-        status: Pending,
-        date: ic_cdk::api::time(),
-    };
+    Ok(position)
+}
 
-    PAYMENTS_STORAGE.with(|payments| {
-        payments.borrow_mut().insert(id, payment.clone());
-        Ok(payment)
+// A loan pool's balances with the certificate and witness that prove them
+#[ic_cdk::query]
+fn get_loan_pool(pool_id: u64) -> Result<Certified<LoanPool>, Error> {
+    let pool = LOAN_POOLS_STORAGE
+        .with(|storage| storage.borrow().get(&pool_id))
+        .ok_or_else(|| Error::not_found(EntityKind::LoanPool, pool_id))?;
+    Ok(certification::certified_pool(pool))
+}
+
+// Current net asset value and share supply of a pool
+#[ic_cdk::query]
+fn get_pool_valuation(pool_id: u64) -> Result<PoolValuation, Error> {
+    let pool = LOAN_POOLS_STORAGE
+        .with(|storage| storage.borrow().get(&pool_id))
+        .ok_or_else(|| Error::not_found(EntityKind::LoanPool, pool_id))?;
+    Ok(PoolValuation {
+        pool_id,
+        net_asset_value: pool_nav(&pool)?,
+        total_shares: pool.total_shares,
     })
 }
 
+// Positions of an investor across all pools
 #[ic_cdk::query]
-fn get_all_payments_for_loan(loan_id: u64) -> Result<Vec<Payment>, String> {
-    PAYMENTS_STORAGE.with(|storage| {
-        let payments: Vec<Payment> = storage
+fn get_investor_positions(investor_id: u64) -> Result<Vec<PoolPosition>, Error> {
+    let caller = resolve_caller();
+    let investor = INVESTORS_STORAGE
+        .with(|storage| storage.borrow().get(&investor_id))
+        .ok_or_else(|| Error::not_found(EntityKind::Investor, investor_id))?;
+    if caller.principal != investor.owner {
+        caller.require(Permission::ManagePools)?;
+    }
+    Ok(POOL_POSITIONS_STORAGE.with(|storage| {
+        storage
             .borrow()
             .iter()
-            .filter(|(_, payment)| payment.loan_id == loan_id)
-            .map(|(_, payment)| payment.clone())
-            .collect();
-        if payments.is_empty() {
-            Err("No payments found for this loan".to_string())
-        } else {
-            Ok(payments)
-        }
+            .filter(|(key, _)| key.investor_id == investor_id)
+            .map(|(_, position)| position)
+            .collect()
+    }))
+}
+
+// Pays the returns accrued on a pool position to the investor with an ICRC-1
+// transfer
+#[ic_cdk::update]
+async fn claim_returns(payload: ClaimReturnsPayload) -> Result<PoolPosition, Error> {
+    let caller = authorize(Permission::Invest)?;
+    let pool = LOAN_POOLS_STORAGE
+        .with(|storage| storage.borrow().get(&payload.pool_id))
+        .ok_or_else(|| Error::not_found(EntityKind::LoanPool, payload.pool_id))?;
+    let investor = INVESTORS_STORAGE
+        .with(|storage| storage.borrow().get(&payload.investor_id))
+        .ok_or_else(|| Error::not_found(EntityKind::Investor, payload.investor_id))?;
+    caller.require_owner(investor.owner, &format!("investor {}", investor.id))?;
+
+    // Move the returns to claimed before the transfer so they cannot be claimed twice
+    let mut position = load_position(&pool, investor.id);
+    let claimed = position.accrued_returns;
+    if claimed.is_zero() {
+        return Err(Error::invalid_payload(
+            "pool_id",
+            format!("No returns to claim from loan pool {}", pool.id),
+        ));
+    }
+    position.claimed_returns = position.claimed_returns.checked_add(&claimed)?;
+    position.accrued_returns = Amount::zero(claimed.currency);
+    store_position(&position, "claim_returns");
+
+    if let Err(error) = ledger::transfer(investor.owner, &claimed, pool.id).await {
+        let mut position = load_position(&pool, investor.id);
+        position.accrued_returns = position.accrued_returns.checked_add(&claimed)?;
+        position.claimed_returns = position.claimed_returns.checked_sub(&claimed)?;
+        store_position(&position, "claim_returns");
+        return Err(error);
+    }
+
+    Ok(position)
+}
+
+// Realized and unrealized returns of an investor, per pool
+#[ic_cdk::query]
+fn get_investor_yield(investor_id: u64) -> Result<Vec<PoolYield>, Error> {
+    Ok(get_investor_positions(investor_id)?
+        .into_iter()
+        .map(|position| PoolYield {
+            pool_id: position.pool_id,
+            shares: position.shares,
+            contributed: position.contributed,
+            realized: position.claimed_returns,
+            unrealized: position.accrued_returns,
+        })
+        .collect())
+}
+
+#[ic_cdk::query]
+fn get_pool_positions(pool_id: u64) -> Result<Vec<PoolPosition>, Error> {
+    authorize(Permission::ManagePools)?;
+    Ok(pool_positions(pool_id))
+}
+
+// IDs of the loans a pool currently funds
+#[ic_cdk::query]
+fn get_pool_loans(pool_id: u64) -> Result<Vec<u64>, Error> {
+    if !LOAN_POOLS_STORAGE.with(|storage| storage.borrow().contains_key(&pool_id)) {
+        return Err(Error::not_found(EntityKind::LoanPool, pool_id));
+    }
+    Ok(pool_loan_ids(pool_id))
+}
+
+// IDs of the investors holding shares in a pool
+#[ic_cdk::query]
+fn get_pool_investors(pool_id: u64) -> Result<Vec<u64>, Error> {
+    if !LOAN_POOLS_STORAGE.with(|storage| storage.borrow().contains_key(&pool_id)) {
+        return Err(Error::not_found(EntityKind::LoanPool, pool_id));
+    }
+    Ok(pool_investor_ids(pool_id))
+}
+
+// Lists loan pools page by page, filtered by currency and available funds
+#[ic_cdk::query]
+fn get_all_loan_pools(
+    page: Option<PageRequest>,
+    filter: Option<LoanPoolFilter>,
+) -> Result<Page<LoanPool>, Error> {
+    let filter = filter.unwrap_or_default();
+    LOAN_POOLS_STORAGE.with(|storage| {
+        paginate(
+            storage.borrow().iter(),
+            &page.unwrap_or_default(),
+            |pool| {
+                filter
+                    .currency
+                    .map_or(true, |currency| pool.total_funds.currency == currency)
+                    && in_range(
+                        &pool.available_funds,
+                        filter.min_available_funds.as_ref(),
+                        None,
+                    )
+            },
+            |pool| pool,
+        )
     })
 }
 
-// Investor and Loan Pool Functions
+// Funds an existing loan from a loan pool by reserving its outstanding
+// principal; repaid principal flows back to the pool
 #[ic_cdk::update]
-fn register_investor(payload: RegisterInvestorPayload) -> Result<Investor, String> {
-    if payload.name.is_empty() || payload.email.is_empty() {
-        return Err("Name and email are required fields".to_string());
+fn allocate_funds_from_pool(pool_id: u64, loan_id: u64) -> Result<LoanPool, Error> {
+    authorize(Permission::ManagePools)?;
+    let pool = LOAN_POOLS_STORAGE
+        .with(|storage| storage.borrow().get(&pool_id))
+        .ok_or_else(|| Error::not_found(EntityKind::LoanPool, pool_id))?;
+    let mut loan = LOANS_STORAGE
+        .with(|storage| storage.borrow().get(&loan_id))
+        .ok_or_else(|| Error::not_found(EntityKind::Loan, loan_id))?;
+    if !matches!(
+        loan.status,
+        LoanStatus::Approved | LoanStatus::Disbursed | LoanStatus::Active
+    ) {
+        return Err(Error::invalid_state(
+            EntityKind::Loan,
+            loan_id,
+            format!("{:?} loans cannot be funded", loan.status),
+        ));
     }
 
-    let id = generate_uuid();
-    let investor = Investor {
-        id,
-        owner: caller(),
-        name: payload.name,
-        email: payload.email,
-        total_invested: ,
-        active_loans: ,
-        returns_earned:
-    };
+    let pool = reserve_capital(pool, &mut loan)?;
+    verify_pool(&pool, Some(&loan))?;
+
+    store_loan(&loan, "allocate_funds_from_pool");
+    store_pool(&pool, "allocate_funds_from_pool");
+    Ok(pool)
+}
+
+// Pool Share Token Functions
+
+// The standard ICRC-1 endpoints serve the shares of every pool, each pool in
+// its own subaccount (see share_token.rs); the pool-scoped endpoints below
+// take the pool ID instead.
+#[ic_cdk::query]
+fn icrc1_name() -> String {
+    share_token::TOKEN_NAME.to_string()
+}
+
+#[ic_cdk::query]
+fn icrc1_symbol() -> String {
+    share_token::TOKEN_SYMBOL.to_string()
+}
+
+#[ic_cdk::query]
+fn icrc1_decimals() -> u8 {
+    share_token::TOKEN_DECIMALS
+}
+
+#[ic_cdk::query]
+fn icrc1_fee() -> Nat {
+    Nat::from(0u64)
+}
+
+#[ic_cdk::query]
+fn icrc1_metadata() -> Vec<(String, MetadataValue)> {
+    share_token::token_metadata()
+}
+
+#[ic_cdk::query]
+fn icrc1_total_supply() -> Nat {
+    share_token::total_supply()
+}
+
+// Shares are minted and burned by this canister on deposit and withdrawal
+#[ic_cdk::query]
+fn icrc1_minting_account() -> Option<Account> {
+    Some(Account::from(ic_cdk::id()))
+}
+
+#[ic_cdk::query]
+fn icrc1_balance_of(account: Account) -> Nat {
+    share_token::icrc1_balance_of(&account)
+}
 
-    INVESTORS_STORAGE.with(|investors| {
-        investors.borrow_mut().insert(id, investor.clone());
-        Ok(investor)
+#[ic_cdk::update]
+fn icrc1_transfer(arg: TransferArg) -> Result<Nat, TransferError> {
+    share_token::icrc1_transfer(arg)
+}
+
+#[ic_cdk::query]
+fn icrc1_supported_standards() -> Vec<SupportedStandard> {
+    vec![SupportedStandard {
+        name: "ICRC-1".to_string(),
+        url: "https://github.com/dfinity/ICRC-1/tree/main/standards/ICRC-1".to_string(),
+    }]
+}
+
+// Account that holds an investor's shares of a pool on the ICRC-1 endpoints
+#[ic_cdk::query]
+fn get_pool_share_account(pool_id: u64, owner: Principal) -> Result<Account, Error> {
+    LOAN_POOLS_STORAGE
+        .with(|storage| storage.borrow().get(&pool_id))
+        .ok_or_else(|| Error::not_found(EntityKind::LoanPool, pool_id))?;
+    Ok(Account {
+        owner,
+        subaccount: Some(share_token::pool_subaccount(pool_id)),
     })
 }
 
+#[ic_cdk::query]
+fn get_pool_share_balance(pool_id: u64, account: Account) -> Result<Nat, Error> {
+    LOAN_POOLS_STORAGE
+        .with(|storage| storage.borrow().get(&pool_id))
+        .ok_or_else(|| Error::not_found(EntityKind::LoanPool, pool_id))?;
+    Ok(share_token::balance_of(pool_id, &account))
+}
+
+// ICRC-1 transfer of the shares of any pool
+#[ic_cdk::update]
+fn transfer_pool_shares(pool_id: u64, arg: TransferArg) -> Result<Nat, TransferError> {
+    share_token::transfer(pool_id, arg, "transfer_pool_shares")
+}
+
+// Secondary Market Functions
+fn store_order(order: &Order, operation: &str) {
+    let previous =
+        ORDERS_STORAGE.with(|storage| storage.borrow_mut().insert(order.id, order.clone()));
+    audit::record(
+        operation,
+        EntityKind::Order,
+        order.id,
+        previous.as_ref(),
+        order,
+    );
+}
+
+fn store_trade(trade: &Trade, operation: &str) {
+    let previous =
+        TRADES_STORAGE.with(|storage| storage.borrow_mut().insert(trade.id, trade.clone()));
+    audit::record(
+        operation,
+        EntityKind::Trade,
+        trade.id,
+        previous.as_ref(),
+        trade,
+    );
+}
+
+// Lists part of an investor's pool shares or loan participation for sale at
+// a price for the whole quantity. The listed quantity is locked until the
+// order is filled, cancelled or expires.
 #[ic_cdk::update]
-fn create_loan_pool(payload: CreateLoanPoolPayload) -> Result<LoanPool, String> {
-    if payload.total_amount <= 0.0 {
-        return Err("Invalid pool amount".to_string());
+fn place_order(payload: PlaceOrderPayload) -> Result<Order, Error> {
+    let caller = authorize(Permission::Invest)?;
+    let seller = INVESTORS_STORAGE
+        .with(|storage| storage.borrow().get(&payload.investor_id))
+        .ok_or_else(|| Error::not_found(EntityKind::Investor, payload.investor_id))?;
+    caller.require_owner(seller.owner, &format!("investor {}", seller.id))?;
+    if payload.quantity == 0 {
+        return Err(Error::invalid_payload(
+            "quantity",
+            "Quantity must be greater than zero",
+        ));
+    }
+    if payload.price.is_zero() {
+        return Err(Error::invalid_payload(
+            "price",
+            "Price must be greater than zero",
+        ));
+    }
+    let currency = market::asset_currency(&payload.asset)?;
+    if payload.price.currency != currency {
+        return Err(Error::CurrencyMismatch {
+            expected: currency,
+            found: payload.price.currency,
+        });
+    }
+    let now = ic_cdk::api::time();
+    let expires_at = payload
+        .expires_at
+        .as_deref()
+        .map(|expires_at| parse_timestamp(expires_at, "expires_at"))
+        .transpose()?;
+    if expires_at.is_some_and(|expires_at| expires_at <= now) {
+        return Err(Error::invalid_payload(
+            "expires_at",
+            "Expiry time must be in the future",
+        ));
     }
 
-    let id = generate_uuid();
-    let pool = LoanPool {
-        id,
-        name: payload.name,
-        total_funds: payload.total_funds,
-        available_funds: 0.0,
-        investor_ids: ,
-        active_loans:, 
+    market::lock(&payload.asset, seller.id, payload.quantity, "place_order")?;
+    let order = Order {
+        id: generate_uuid(),
+        seller_id: seller.id,
+        asset: payload.asset,
+        quantity: payload.quantity,
+        remaining: payload.quantity,
+        price: payload.price,
+        status: OrderStatus::Open,
+        created_at: now,
+        expires_at,
+    };
+    store_order(&order, "place_order");
+    Ok(order)
+}
+
+// Withdraws an open order and unlocks what it still had listed
+#[ic_cdk::update]
+fn cancel_order(order_id: u64) -> Result<Order, Error> {
+    let caller = authorize(Permission::Invest)?;
+    let mut order = ORDERS_STORAGE
+        .with(|storage| storage.borrow().get(&order_id))
+        .ok_or_else(|| Error::not_found(EntityKind::Order, order_id))?;
+    let seller = INVESTORS_STORAGE
+        .with(|storage| storage.borrow().get(&order.seller_id))
+        .ok_or_else(|| Error::not_found(EntityKind::Investor, order.seller_id))?;
+    caller.require_owner(seller.owner, &format!("investor {}", seller.id))?;
+    if order.status != OrderStatus::Open {
+        return Err(Error::invalid_state(
+            EntityKind::Order,
+            order_id,
+            format!("{:?} orders cannot be cancelled", order.status),
+        ));
+    }
+
+    market::unlock(
+        &order.asset,
+        order.seller_id,
+        order.remaining,
+        "cancel_order",
+    )?;
+    order.status = OrderStatus::Cancelled;
+    store_order(&order, "cancel_order");
+    Ok(order)
+}
+
+// Buys `quantity` out of an open order. The price is pulled from the buyer
+// into this canister (ICRC-2 `transfer_from`, so the buyer must approve this
+// canister first); once it arrives, ownership and the entitlement to future
+// income move to the buyer and the price is paid on to the seller. A fill
+// that can no longer settle is refunded to the buyer.
+#[ic_cdk::update]
+async fn fill_order(payload: FillOrderPayload) -> Result<Trade, Error> {
+    let caller = authorize(Permission::Invest)?;
+    let buyer = INVESTORS_STORAGE
+        .with(|storage| storage.borrow().get(&payload.investor_id))
+        .ok_or_else(|| Error::not_found(EntityKind::Investor, payload.investor_id))?;
+    caller.require_owner(buyer.owner, &format!("investor {}", buyer.id))?;
+    let mut order = ORDERS_STORAGE
+        .with(|storage| storage.borrow().get(&payload.order_id))
+        .ok_or_else(|| Error::not_found(EntityKind::Order, payload.order_id))?;
+    if order.status == OrderStatus::Open && market::is_expired(&order, ic_cdk::api::time()) {
+        order = market::expire(order, "fill_order")?;
+    }
+    if order.status != OrderStatus::Open {
+        return Err(Error::invalid_state(
+            EntityKind::Order,
+            order.id,
+            format!("{:?} orders cannot be filled", order.status),
+        ));
+    }
+    if order.seller_id == buyer.id {
+        return Err(Error::invalid_payload(
+            "investor_id",
+            "Investors cannot fill their own orders",
+        ));
+    }
+    if payload.quantity == 0 || payload.quantity > order.remaining {
+        return Err(Error::invalid_payload(
+            "quantity",
+            format!(
+                "Quantity must be between 1 and the {} remaining on order {}",
+                order.remaining, order.id
+            ),
+        ));
+    }
+    let seller = INVESTORS_STORAGE
+        .with(|storage| storage.borrow().get(&order.seller_id))
+        .ok_or_else(|| Error::not_found(EntityKind::Investor, order.seller_id))?;
+    let price = market::fill_price(&order, payload.quantity)?;
+
+    // Take the quantity off the order before the transfer so concurrent fills
+    // cannot buy it twice
+    order.remaining -= payload.quantity;
+    store_order(&order, "fill_order");
+
+    let block_index = match ledger::transfer_from(buyer.owner, &price, order.id).await {
+        Ok(block_index) => block_index,
+        Err(error) => {
+            release_fill(order.id, payload.quantity)?;
+            return Err(error);
+        }
+    };
+
+    // The order and both investors may have changed while the transfer was
+    // in flight: settle against their current state, or return the price
+    let settled = ORDERS_STORAGE
+        .with(|storage| storage.borrow().get(&payload.order_id))
+        .ok_or_else(|| Error::not_found(EntityKind::Order, payload.order_id))
+        .and_then(|order| {
+            market::settle(
+                &order.asset,
+                order.seller_id,
+                buyer.id,
+                payload.quantity,
+                "fill_order",
+            )
+        });
+    if let Err(error) = settled {
+        release_fill(order.id, payload.quantity)?;
+        return Err(
+            match ledger::transfer(buyer.owner, &price, order.id).await {
+                Ok(refund_block_index) => Error::PaymentFailed {
+                    msg: format!(
+                        "{}; price refunded in ledger block {}",
+                        error, refund_block_index
+                    ),
+                },
+                Err(e) => Error::PaymentFailed {
+                    msg: format!("{}; refund failed: {}", error, e),
+                },
+            },
+        );
+    }
+
+    let trade = Trade {
+        id: generate_uuid(),
+        order_id: order.id,
+        asset: order.asset,
+        seller_id: seller.id,
+        buyer_id: buyer.id,
+        quantity: payload.quantity,
+        price,
+        executed_at: ic_cdk::api::time(),
+        ledger_block_index: block_index,
     };
+    store_trade(&trade, "fill_order");
+
+    let mut order = ORDERS_STORAGE
+        .with(|storage| storage.borrow().get(&payload.order_id))
+        .ok_or_else(|| Error::not_found(EntityKind::Order, payload.order_id))?;
+    if order.status == OrderStatus::Open && order.remaining == 0 {
+        order.status = OrderStatus::Filled;
+        store_order(&order, "fill_order");
+    }
+
+    // The trade stands; a payout the ledger refuses stays in this canister
+    // and is recorded against the order for an administrator to settle
+    if let Err(error) = ledger::transfer(seller.owner, &price, trade.id).await {
+        audit::record(
+            "fill_order",
+            EntityKind::Order,
+            trade.order_id,
+            None,
+            &serde_json::json!({
+                "trade_id": trade.id,
+                "seller_id": trade.seller_id,
+                "amount": trade.price,
+                "failure_reason": error.to_string(),
+            }),
+        );
+    }
+    Ok(trade)
+}
+
+// Gives a reserved fill's quantity back to its order, or to the seller once
+// the order was cancelled or expired, which only released what remained
+fn release_fill(order_id: u64, quantity: u64) -> Result<(), Error> {
+    let mut order = ORDERS_STORAGE
+        .with(|storage| storage.borrow().get(&order_id))
+        .ok_or_else(|| Error::not_found(EntityKind::Order, order_id))?;
+    if order.status == OrderStatus::Open {
+        order.remaining += quantity;
+        store_order(&order, "fill_order");
+        Ok(())
+    } else {
+        market::unlock(&order.asset, order.seller_id, quantity, "fill_order")
+    }
+}
 
-    LOAN_POOLS_STORAGE.with(|pools| {
-        pools.borrow_mut().insert(id, pool.clone());
-        Ok(pool)
+// Open, unexpired orders, optionally for one asset only
+#[ic_cdk::query]
+fn get_open_orders(asset: Option<MarketAsset>) -> Vec<OrderView> {
+    let now = ic_cdk::api::time();
+    ORDERS_STORAGE.with(|storage| {
+        storage
+            .borrow()
+            .iter()
+            .map(|(_, order)| order)
+            .filter(|order| order.status == OrderStatus::Open && !market::is_expired(order, now))
+            .filter(|order| asset.map_or(true, |asset| order.asset == asset))
+            .map(OrderView::from)
+            .collect()
     })
 }
 
+// Executed trades in execution order, optionally for one asset only
 #[ic_cdk::query]
-fn get_all_loan_pools() -> Result<Vec<LoanPool>, String> {
-    LOAN_POOLS_STORAGE.with(|storage| {
-        let pools: Vec<LoanPool> = storage
+fn get_trade_history(asset: Option<MarketAsset>) -> Vec<TradeView> {
+    TRADES_STORAGE.with(|storage| {
+        storage
             .borrow()
             .iter()
-            .map(|(_, pool)| pool.clone())
-            .collect();
-        if pools.is_empty() {
-            Err("No loan pools found".to_string())
-        } else {
-            Ok(pools)
-        }
+            .map(|(_, trade)| trade)
+            .filter(|trade| asset.map_or(true, |asset| trade.asset == asset))
+            .map(TradeView::from)
+            .collect()
     })
 }
 
-// Function to allocate funds from a loan pool to a loan
+#[ic_cdk::query]
+fn get_loan_participations(loan_id: u64) -> Result<Vec<LoanParticipation>, Error> {
+    authorize(Permission::ManagePools)?;
+    Ok(market::loan_participations(loan_id))
+}
+
+// Loan participations held by an investor
+#[ic_cdk::query]
+fn get_investor_participations(investor_id: u64) -> Result<Vec<LoanParticipation>, Error> {
+    let caller = resolve_caller();
+    let investor = INVESTORS_STORAGE
+        .with(|storage| storage.borrow().get(&investor_id))
+        .ok_or_else(|| Error::not_found(EntityKind::Investor, investor_id))?;
+    if caller.principal != investor.owner {
+        caller.require(Permission::ManagePools)?;
+    }
+    Ok(LOAN_PARTICIPATIONS_STORAGE.with(|storage| {
+        storage
+            .borrow()
+            .iter()
+            .filter(|(key, participation)| key.investor_id == investor_id && participation.bps > 0)
+            .map(|(_, participation)| participation)
+            .collect()
+    }))
+}
+
+// IDs of the loans an investor holds participations in
+#[ic_cdk::query]
+fn get_investor_loans(investor_id: u64) -> Result<Vec<u64>, Error> {
+    let caller = resolve_caller();
+    let investor = INVESTORS_STORAGE
+        .with(|storage| storage.borrow().get(&investor_id))
+        .ok_or_else(|| Error::not_found(EntityKind::Investor, investor_id))?;
+    if caller.principal != investor.owner {
+        caller.require(Permission::ManagePools)?;
+    }
+    Ok(investor_loan_ids(investor_id))
+}
+
+// Role Administration Functions
 #[ic_cdk::update]
-fn allocate_funds_from_pool(pool_id: u64, loan_id: u64, amount: f64) -> Result<LoanPool, String> {
-    LOAN_POOLS_STORAGE.with(|pools| {
-        let mut pools = pools.borrow_mut();
-        if let Some(mut pool) = pools.get(&pool_id).cloned() {
-            if pool.total_amount - pool.allocated_amount < amount {
-                return Err("Insufficient funds in the pool".to_string());
-            }
+fn grant_role(user_id: u64, role: UserRole) -> Result<User, Error> {
+    authorize(Permission::ManageUsers)?;
+    let mut user = USERS_STORAGE
+        .with(|storage| storage.borrow().get(&user_id))
+        .ok_or_else(|| Error::not_found(EntityKind::User, user_id))?;
+    user.role = role;
+    store_user(&user, "grant_role");
+    Ok(user)
+}
+
+// Revoking returns the user to the default Borrower role
+#[ic_cdk::update]
+fn revoke_role(user_id: u64) -> Result<User, Error> {
+    grant_role(user_id, UserRole::default())
+}
+
+#[ic_cdk::update]
+fn add_administrator(principal: Principal) -> Result<AccessConfig, Error> {
+    authorize(Permission::ManageUsers)?;
+    let mut config = ACCESS_CONFIG.with(|cell| cell.borrow().get().clone());
+    if !config.administrators.contains(&principal) {
+        config.administrators.push(principal);
+    }
+    store_access_config(config.clone(), "add_administrator");
+    Ok(config)
+}
+
+#[ic_cdk::update]
+fn remove_administrator(principal: Principal) -> Result<AccessConfig, Error> {
+    let caller = authorize(Permission::ManageUsers)?;
+    if caller.principal == principal {
+        return Err(Error::invalid_payload(
+            "principal",
+            "Administrators cannot remove themselves",
+        ));
+    }
+    let mut config = ACCESS_CONFIG.with(|cell| cell.borrow().get().clone());
+    config
+        .administrators
+        .retain(|administrator| *administrator != principal);
+    store_access_config(config.clone(), "remove_administrator");
+    Ok(config)
+}
+
+#[ic_cdk::query]
+fn get_access_config() -> Result<AccessConfig, Error> {
+    authorize(Permission::ManageUsers)?;
+    Ok(ACCESS_CONFIG.with(|cell| cell.borrow().get().clone()))
+}
+
+fn store_access_config(config: AccessConfig, operation: &str) {
+    let previous = ACCESS_CONFIG.with(|cell| {
+        cell.borrow_mut()
+            .set(config.clone())
+            .expect("Cannot store the access configuration")
+    });
+    audit::record(
+        operation,
+        EntityKind::Configuration,
+        audit::ACCESS_CONFIG_ID,
+        Some(&previous),
+        &config,
+    );
+}
+
+// Audit Log Functions
+
+// Changes to one record, or to every record of a kind, page by page. Pool
+// positions are filed under their pool's ID, installments and loan
+// participations under their loan's ID, and configurations under the IDs
+// listed in audit.rs.
+#[ic_cdk::query]
+fn get_audit_log_by_entity(
+    entity: EntityKind,
+    entity_id: Option<u64>,
+    page: Option<PageRequest>,
+) -> Result<Page<AuditEntry>, Error> {
+    authorize(Permission::ViewAuditLog)?;
+    paginate(
+        audit::entity_entry_ids(entity, entity_id)
+            .into_iter()
+            .map(|entry_id| (entry_id, entry_id)),
+        &page.unwrap_or_default(),
+        |_| true,
+        audit::load,
+    )
+}
+
+// Changes made by a principal, page by page
+#[ic_cdk::query]
+fn get_audit_log_by_principal(
+    principal: Principal,
+    page: Option<PageRequest>,
+) -> Result<Page<AuditEntry>, Error> {
+    authorize(Permission::ViewAuditLog)?;
+    paginate(
+        audit::caller_entry_ids(principal)
+            .into_iter()
+            .map(|entry_id| (entry_id, entry_id)),
+        &page.unwrap_or_default(),
+        |_| true,
+        audit::load,
+    )
+}
+
+// HTTP Gateway Functions
+
+// Read-only JSON endpoints for plain HTTP clients; see http.rs for the routes
+#[ic_cdk::query]
+fn http_request(request: HttpRequest) -> HttpResponse {
+    http::handle(request)
+}
+
+// Ledger Configuration Functions
+#[ic_cdk::update]
+fn set_ledger_config(config: LedgerConfig) -> Result<LedgerConfig, Error> {
+    authorize(Permission::ManageSettings)?;
+    store_ledger_config(config.clone(), "set_ledger_config");
+    Ok(config)
+}
 
-            LOANS_STORAGE.with(|loans| {
-                let mut loans = loans.borrow_mut();
-                if let Some(mut loan) = loans.get(&loan_id).cloned() {
-                    loan.amount += amount;
-                    loans.insert(loan_id, loan);
-                } else {
-                    return Err(format!("Loan with ID {} not found", loan_id));
+#[ic_cdk::query]
+fn get_ledger_config() -> LedgerConfig {
+    LEDGER_CONFIG.with(|cell| cell.borrow().get().clone())
+}
+
+fn store_ledger_config(config: LedgerConfig, operation: &str) {
+    let previous = LEDGER_CONFIG.with(|cell| {
+        cell.borrow_mut()
+            .set(config.clone())
+            .expect("Cannot store the ledger configuration")
+    });
+    audit::record(
+        operation,
+        EntityKind::Configuration,
+        audit::LEDGER_CONFIG_ID,
+        Some(&previous),
+        &config,
+    );
+}
+
+// Interest Accrual Functions
+
+// Accrues interest, flags overdue installments and defaults loans that are
+// past the configured threshold, for every disbursed or active loan
+fn run_accrual() {
+    market::expire_orders(ic_cdk::api::time(), "run_accrual");
+    let today = day_start(ic_cdk::api::time());
+    let config = ACCRUAL_CONFIG.with(|cell| cell.borrow().get().clone());
+    let active_loans: Vec<Loan> = load_loans(
+        [LoanStatus::Disbursed, LoanStatus::Active]
+            .into_iter()
+            .flat_map(index::status_loan_ids),
+    );
+
+    // Pool statistics are re-certified once, after every loan accrued
+    certification::batch(|| {
+        for loan in active_loans {
+            let loan_id = loan.id;
+            let installments = loan_installments(loan_id);
+            let previous = loan.clone();
+            match accrue_loan(loan, installments, today, &config) {
+                // Loans not started yet or already accrued today are left as
+                // they are
+                Ok((loan, installments)) => {
+                    store_installments(installments, "run_accrual");
+                    if loan != previous {
+                        store_loan(&loan, "run_accrual");
+                    }
                 }
-            });
+                // The loan is left as it was; the failure is kept in the audit
+                // log and the next run retries it
+                Err(error) => audit::record(
+                    "run_accrual",
+                    EntityKind::Loan,
+                    loan_id,
+                    None,
+                    &serde_json::json!({
+                        "accrual_date": today,
+                        "failure_reason": error.to_string(),
+                    }),
+                ),
+            }
+        }
+    });
+}
 
-            pool.allocated_amount += amount;
-            pools.insert(pool_id, pool.clone());
-            Ok(pool)
-        } else {
-            Err(format!("Loan pool with ID {} not found", pool_id))
+// (Re-)arms the periodic accrual job with the configured interval
+fn start_accrual_timer() {
+    let interval_seconds = ACCRUAL_CONFIG.with(|cell| cell.borrow().get().interval_seconds);
+    let timer_id =
+        ic_cdk_timers::set_timer_interval(Duration::from_secs(interval_seconds), run_accrual);
+    if let Some(previous) = ACCRUAL_TIMER.with(|timer| timer.borrow_mut().replace(timer_id)) {
+        ic_cdk_timers::clear_timer(previous);
+    }
+}
+
+fn store_accrual_config(config: AccrualConfig, operation: &str) {
+    let previous = ACCRUAL_CONFIG.with(|cell| {
+        cell.borrow_mut()
+            .set(config.clone())
+            .expect("Cannot store the accrual configuration")
+    });
+    audit::record(
+        operation,
+        EntityKind::Configuration,
+        audit::ACCRUAL_CONFIG_ID,
+        Some(&previous),
+        &config,
+    );
+}
+
+fn validate_accrual_config(config: &AccrualConfig) -> Result<(), Error> {
+    if config.interval_seconds == 0 {
+        return Err(Error::invalid_payload(
+            "interval_seconds",
+            "Interval must be greater than zero",
+        ));
+    }
+    if config.default_threshold_days == 0 {
+        return Err(Error::invalid_payload(
+            "default_threshold_days",
+            "Default threshold must be greater than zero",
+        ));
+    }
+    Ok(())
+}
+
+#[ic_cdk::update]
+fn set_accrual_config(config: AccrualConfig) -> Result<AccrualConfig, Error> {
+    authorize(Permission::ManageSettings)?;
+    validate_accrual_config(&config)?;
+    store_accrual_config(config.clone(), "set_accrual_config");
+    start_accrual_timer();
+    Ok(config)
+}
+
+#[ic_cdk::query]
+fn get_accrual_config() -> AccrualConfig {
+    ACCRUAL_CONFIG.with(|cell| cell.borrow().get().clone())
+}
+
+// Runs the accrual job immediately instead of waiting for the timer
+#[ic_cdk::update]
+fn trigger_accrual() -> Result<(), Error> {
+    authorize(Permission::ManageSettings)?;
+    run_accrual();
+    Ok(())
+}
+
+// Fee Configuration Functions
+fn store_fee_config(config: FeeConfig, operation: &str) {
+    let previous = FEE_CONFIG.with(|cell| {
+        cell.borrow_mut()
+            .set(config.clone())
+            .expect("Cannot store the fee configuration")
+    });
+    audit::record(
+        operation,
+        EntityKind::Configuration,
+        audit::FEE_CONFIG_ID,
+        Some(&previous),
+        &config,
+    );
+}
+
+#[ic_cdk::update]
+fn set_fee_config(config: FeeConfig) -> Result<FeeConfig, Error> {
+    authorize(Permission::ManageSettings)?;
+    if config.platform_fee_bps > 10_000 {
+        return Err(Error::invalid_payload(
+            "platform_fee_bps",
+            "Platform fee cannot exceed 10000 basis points",
+        ));
+    }
+    store_fee_config(config.clone(), "set_fee_config");
+    Ok(config)
+}
+
+#[ic_cdk::query]
+fn get_fee_config() -> FeeConfig {
+    FEE_CONFIG.with(|cell| cell.borrow().get().clone())
+}
+
+// Upgrade Hooks
+fn apply_init_args(args: Option<InitArgs>, operation: &str) {
+    if let Some(args) = args {
+        if let Some(admin) = args.admin {
+            let mut config = ACCESS_CONFIG.with(|cell| cell.borrow().get().clone());
+            if !config.administrators.contains(&admin) {
+                config.administrators.push(admin);
+                store_access_config(config, operation);
+            }
         }
-    })
+        if let Some(ledger) = args.ledger {
+            store_ledger_config(ledger, operation);
+        }
+        if let Some(accrual) = args.accrual {
+            // A zero interval would arm a timer firing on every round, so an
+            // invalid configuration fails the install or upgrade instead
+            if let Err(e) = validate_accrual_config(&accrual) {
+                ic_cdk::trap(&format!("Invalid accrual configuration ({})", e));
+            }
+            store_accrual_config(accrual, operation);
+        }
+        if let Some(fees) = args.fees {
+            store_fee_config(fees, operation);
+        }
+    }
+}
+
+#[ic_cdk::init]
+fn init(args: Option<InitArgs>) {
+    init_schema_version();
+    certification::rebuild();
+    apply_init_args(args, "init");
+    // Without an explicit bootstrap administrator, the installer becomes one
+    if ACCESS_CONFIG.with(|cell| cell.borrow().get().administrators.is_empty()) {
+        store_access_config(
+            AccessConfig {
+                administrators: vec![caller()],
+            },
+            "init",
+        );
+    }
+    start_accrual_timer();
+}
+
+#[ic_cdk::post_upgrade]
+fn post_upgrade(args: Option<InitArgs>) {
+    run_migrations();
+    certification::rebuild();
+    apply_init_args(args, "post_upgrade");
+    start_accrual_timer();
 }
 
 // Exporting the Candid interface
 ic_cdk::export_candid!();
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::{defaulted_loan, pool};
+
+    // Relation maps take any number of links without growing the pool and
+    // investor records they belong to
+    #[test]
+    fn relation_maps_hold_thousands_of_links() {
+        let funding_pool = pool(1_000_000_000);
+        let pool_size = funding_pool.to_bytes().len();
+        LOAN_POOLS_STORAGE.with(|storage| {
+            storage
+                .borrow_mut()
+                .insert(funding_pool.id, funding_pool.clone())
+        });
+
+        let (template, _) = defaulted_loan(100_000);
+        for loan_id in 1..=5_000 {
+            let mut loan = template.clone();
+            loan.id = loan_id;
+            loan.status = LoanStatus::Active;
+            loan.pool_id = Some(if loan_id % 5 == 0 { 2 } else { 1 });
+            LOANS_STORAGE.with(|storage| storage.borrow_mut().insert(loan.id, loan.clone()));
+            sync_pool_loan(&loan);
+
+            let key = InvestorLoanKey {
+                investor_id: 9,
+                loan_id,
+            };
+            INVESTOR_LOANS_STORAGE.with(|storage| storage.borrow_mut().insert(key, ()));
+        }
+
+        let expected: Vec<u64> = (1..=5_000).filter(|id| id % 5 != 0).collect();
+        assert_eq!(pool_loan_ids(1), expected);
+        assert_eq!(pool_loan_ids(2).len(), 1_000);
+        assert_eq!(pool_loans(1, None).len(), 4_000);
+        assert_eq!(investor_loan_ids(9), (1..=5_000).collect::<Vec<u64>>());
+        assert!(investor_loan_ids(10).is_empty());
+
+        // Repaid loans leave the pool's relation map
+        for loan_id in expected.iter().filter(|id| *id % 2 == 0) {
+            let mut loan = LOANS_STORAGE
+                .with(|storage| storage.borrow().get(loan_id))
+                .unwrap();
+            loan.principal_paid = loan.principal_amount;
+            loan.status = LoanStatus::Completed;
+            LOANS_STORAGE.with(|storage| storage.borrow_mut().insert(loan.id, loan.clone()));
+            sync_pool_loan(&loan);
+        }
+        let remaining: Vec<u64> = expected.into_iter().filter(|id| id % 2 != 0).collect();
+        assert_eq!(pool_loan_ids(1), remaining);
+        assert_eq!(pool_loan_ids(2).len(), 1_000);
+
+        let stored = LOAN_POOLS_STORAGE
+            .with(|storage| storage.borrow().get(&1))
+            .unwrap();
+        assert_eq!(stored.to_bytes().len(), pool_size);
+        assert!(pool_size <= LoanPool::MAX_SIZE as usize);
+    }
+}
@@ -0,0 +1,88 @@
+use crate::models::*;
+use crate::{EntityKind, Error};
+
+// Events in a loan's life that move it between states
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub(crate) enum LoanEvent {
+    StartReview, // A lender picked up the application
+    Approve,
+    Reject,
+    Disburse,  // The principal was sent to the borrower
+    Activate,  // Repayment started
+    PayOff,    // The balance was repaid in full
+    Default,   // Payments are too far overdue, or staff declared a default
+    Reinstate, // Staff lifted a default
+    WriteOff,  // Recovery closed with a balance left unpaid
+}
+
+// Allowed transitions: the state a loan moves to when `event` happens in
+// `status`, or None if the event cannot happen in that state
+fn next_status(status: LoanStatus, event: LoanEvent) -> Option<LoanStatus> {
+    use LoanEvent as E;
+    use LoanStatus as S;
+    match (status, event) {
+        (S::Applied, E::StartReview) => Some(S::UnderReview),
+        (S::Applied | S::UnderReview, E::Approve) => Some(S::Approved),
+        (S::Applied | S::UnderReview, E::Reject) => Some(S::Rejected),
+        (S::Approved, E::Disburse) => Some(S::Disbursed),
+        (S::Disbursed, E::Activate) => Some(S::Active),
+        (S::Disbursed | S::Active | S::Defaulted, E::PayOff) => Some(S::Completed),
+        (S::Active, E::Default) => Some(S::Defaulted),
+        (S::Defaulted, E::Reinstate) => Some(S::Active),
+        (S::Defaulted, E::WriteOff) => Some(S::WrittenOff),
+        _ => None,
+    }
+}
+
+// The state an event leads to from any state, for error messages
+fn target_status(event: LoanEvent) -> LoanStatus {
+    match event {
+        LoanEvent::StartReview => LoanStatus::UnderReview,
+        LoanEvent::Approve => LoanStatus::Approved,
+        LoanEvent::Reject => LoanStatus::Rejected,
+        LoanEvent::Disburse => LoanStatus::Disbursed,
+        LoanEvent::Activate | LoanEvent::Reinstate => LoanStatus::Active,
+        LoanEvent::PayOff => LoanStatus::Completed,
+        LoanEvent::Default => LoanStatus::Defaulted,
+        LoanEvent::WriteOff => LoanStatus::WrittenOff,
+    }
+}
+
+fn invalid_transition(loan: &Loan, to: LoanStatus) -> Error {
+    Error::InvalidTransition {
+        entity: EntityKind::Loan,
+        id: loan.id,
+        from: format!("{:?}", loan.status),
+        to: format!("{:?}", to),
+    }
+}
+
+// Moves a loan to the state `event` leads to, rejecting events its current
+// state does not allow
+pub(crate) fn apply_event(loan: &mut Loan, event: LoanEvent) -> Result<(), Error> {
+    match next_status(loan.status, event) {
+        Some(status) => {
+            loan.status = status;
+            Ok(())
+        }
+        None => Err(invalid_transition(loan, target_status(event))),
+    }
+}
+
+// Loans accept repayments once the principal has been disbursed
+pub(crate) fn accepts_payments(loan: &Loan) -> bool {
+    matches!(loan.status, LoanStatus::Disbursed | LoanStatus::Active)
+}
+
+// Status changes requested through `update_loan_status`. Staff may declare
+// or lift a default; every other transition has its own endpoint or follows
+// from payments and accrual.
+pub(crate) fn manual_transition(loan: &mut Loan, status: LoanStatus) -> Result<(), Error> {
+    match status {
+        LoanStatus::Defaulted => apply_event(loan, LoanEvent::Default),
+        LoanStatus::Active if loan.status == LoanStatus::Defaulted => {
+            apply_event(loan, LoanEvent::Reinstate)
+        }
+        _ => Err(invalid_transition(loan, status)),
+    }
+}
@@ -0,0 +1,331 @@
+use crate::amount::{Amount, Currency};
+use crate::models::*;
+use crate::returns::pro_rata_claims;
+use crate::{EntityKind, Error};
+
+fn load_pool(pool_id: u64) -> Result<LoanPool, Error> {
+    crate::LOAN_POOLS_STORAGE
+        .with(|storage| storage.borrow().get(&pool_id))
+        .ok_or_else(|| Error::not_found(EntityKind::LoanPool, pool_id))
+}
+
+fn load_loan(loan_id: u64) -> Result<Loan, Error> {
+    crate::LOANS_STORAGE
+        .with(|storage| storage.borrow().get(&loan_id))
+        .ok_or_else(|| Error::not_found(EntityKind::Loan, loan_id))
+}
+
+fn load_investor(investor_id: u64) -> Result<Investor, Error> {
+    crate::INVESTORS_STORAGE
+        .with(|storage| storage.borrow().get(&investor_id))
+        .ok_or_else(|| Error::not_found(EntityKind::Investor, investor_id))
+}
+
+// Records that an investor holds participations in a loan
+fn link_investor_loan(investor_id: u64, loan_id: u64) {
+    let key = InvestorLoanKey {
+        investor_id,
+        loan_id,
+    };
+    crate::INVESTOR_LOANS_STORAGE.with(|storage| storage.borrow_mut().insert(key, ()));
+}
+
+fn unlink_investor_loan(investor_id: u64, loan_id: u64) {
+    let key = InvestorLoanKey {
+        investor_id,
+        loan_id,
+    };
+    crate::INVESTOR_LOANS_STORAGE.with(|storage| storage.borrow_mut().remove(&key));
+}
+
+// Stores a participation and records the change against its loan
+fn store_participation(participation: &LoanParticipation, operation: &str) {
+    let key = ParticipationKey {
+        loan_id: participation.loan_id,
+        investor_id: participation.investor_id,
+    };
+    let previous = crate::LOAN_PARTICIPATIONS_STORAGE
+        .with(|storage| storage.borrow_mut().insert(key, participation.clone()));
+    crate::audit::record(
+        operation,
+        EntityKind::LoanParticipation,
+        participation.loan_id,
+        previous.as_ref(),
+        participation,
+    );
+}
+
+fn not_enough(asset: &MarketAsset, investor_id: u64, available: u64, requested: u64) -> Error {
+    let unit = match asset {
+        MarketAsset::PoolShares { .. } => "unlocked shares",
+        MarketAsset::LoanParticipation { .. } => "unlocked basis points",
+    };
+    Error::invalid_payload(
+        "quantity",
+        format!(
+            "Investor {} holds {} {}, {} requested",
+            investor_id, available, unit, requested
+        ),
+    )
+}
+
+// Participations in a loan, in investor order
+pub(crate) fn loan_participations(loan_id: u64) -> Vec<LoanParticipation> {
+    crate::LOAN_PARTICIPATIONS_STORAGE.with(|storage| {
+        storage
+            .borrow()
+            .range(
+                ParticipationKey {
+                    loan_id,
+                    investor_id: 0,
+                }..,
+            )
+            .take_while(|(key, _)| key.loan_id == loan_id)
+            .map(|(_, participation)| participation)
+            .collect()
+    })
+}
+
+fn load_participation(loan_id: u64, investor_id: u64) -> LoanParticipation {
+    let key = ParticipationKey {
+        loan_id,
+        investor_id,
+    };
+    crate::LOAN_PARTICIPATIONS_STORAGE
+        .with(|storage| storage.borrow().get(&key))
+        .unwrap_or(LoanParticipation {
+            loan_id,
+            investor_id,
+            bps: 0,
+            locked_bps: 0,
+            drawn_bps: 0,
+        })
+}
+
+// Basis points of a loan's income an investor can still draw from its
+// pro-rata claim as a pool shareholder
+fn claimable_bps(loan: &Loan, investor_id: u64) -> Result<u64, Error> {
+    let pool_id = loan.pool_id.ok_or_else(|| {
+        Error::invalid_state(
+            EntityKind::Loan,
+            loan.id,
+            "Only pool-funded loans have participations",
+        )
+    })?;
+    let pool = load_pool(pool_id)?;
+    if pool.total_shares == 0 {
+        return Ok(0);
+    }
+    let positions = crate::pool_positions(pool_id);
+    let claims = pro_rata_claims(&pool, &positions, &loan_participations(loan.id));
+    let claim = positions
+        .iter()
+        .zip(claims)
+        .find(|(position, _)| position.investor_id == investor_id)
+        .map_or(0, |(_, claim)| claim / pool.total_shares as u128);
+    Ok(claim as u64)
+}
+
+// Currency an asset is priced in: that of its pool or loan
+pub(crate) fn asset_currency(asset: &MarketAsset) -> Result<Currency, Error> {
+    match asset {
+        MarketAsset::PoolShares { pool_id } => Ok(load_pool(*pool_id)?.total_funds.currency),
+        MarketAsset::LoanParticipation { loan_id } => {
+            Ok(load_loan(*loan_id)?.principal_amount.currency)
+        }
+    }
+}
+
+// Sets aside `quantity` of an investor's holding for an order. Locked
+// holdings keep earning but cannot be withdrawn, transferred or listed again.
+pub(crate) fn lock(
+    asset: &MarketAsset,
+    investor_id: u64,
+    quantity: u64,
+    operation: &str,
+) -> Result<(), Error> {
+    match asset {
+        MarketAsset::PoolShares { pool_id } => {
+            let pool = load_pool(*pool_id)?;
+            let mut position = crate::load_position(&pool, investor_id);
+            let unlocked = position.shares - position.locked_shares;
+            if quantity > unlocked {
+                return Err(not_enough(asset, investor_id, unlocked, quantity));
+            }
+            position.locked_shares += quantity;
+            crate::store_position(&position, operation);
+        }
+        MarketAsset::LoanParticipation { loan_id } => {
+            let loan = load_loan(*loan_id)?;
+            if loan.status != LoanStatus::Active {
+                return Err(Error::invalid_state(
+                    EntityKind::Loan,
+                    loan.id,
+                    format!("Participations in {:?} loans cannot be listed", loan.status),
+                ));
+            }
+            // Basis points the investor does not hold outright are drawn
+            // from its pro-rata claim as a pool shareholder
+            let mut participation = load_participation(loan.id, investor_id);
+            let unlocked = (participation.bps - participation.locked_bps) as u64;
+            if quantity > unlocked {
+                let claimable = claimable_bps(&loan, investor_id)?;
+                let drawn = quantity - unlocked;
+                if drawn > claimable {
+                    return Err(not_enough(
+                        asset,
+                        investor_id,
+                        unlocked + claimable,
+                        quantity,
+                    ));
+                }
+                participation.bps += drawn as u32;
+                participation.drawn_bps += drawn as u32;
+                link_investor_loan(investor_id, loan.id);
+            }
+            participation.locked_bps += quantity as u32;
+            store_participation(&participation, operation);
+        }
+    }
+    Ok(())
+}
+
+// Releases `quantity` of an investor's holding from an order
+pub(crate) fn unlock(
+    asset: &MarketAsset,
+    investor_id: u64,
+    quantity: u64,
+    operation: &str,
+) -> Result<(), Error> {
+    match asset {
+        MarketAsset::PoolShares { pool_id } => {
+            let pool = load_pool(*pool_id)?;
+            let mut position = crate::load_position(&pool, investor_id);
+            position.locked_shares = position.locked_shares.saturating_sub(quantity);
+            crate::store_position(&position, operation);
+        }
+        MarketAsset::LoanParticipation { loan_id } => {
+            let quantity = quantity.min(u32::MAX as u64) as u32;
+            let mut participation = load_participation(*loan_id, investor_id);
+            participation.locked_bps = participation.locked_bps.saturating_sub(quantity);
+            // Basis points drawn for the listing go back to the pro-rata claim
+            let returned = quantity
+                .min(participation.drawn_bps)
+                .min(participation.bps - participation.locked_bps);
+            participation.bps -= returned;
+            participation.drawn_bps -= returned;
+            store_participation(&participation, operation);
+            if participation.bps == 0 {
+                unlink_investor_loan(investor_id, *loan_id);
+            }
+        }
+    }
+    Ok(())
+}
+
+// The seller no longer has the quantity of a fill locked for its order
+fn not_listed(seller_id: u64, locked: u64, requested: u64) -> Error {
+    Error::invalid_state(
+        EntityKind::Investor,
+        seller_id,
+        format!("{} locked for sale, {} requested", locked, requested),
+    )
+}
+
+// Hands `quantity` of a locked holding from the seller to the buyer. Pool
+// shares carry their part of the seller's cost basis; participations carry
+// their basis points of the loan's future income. Both sides are checked
+// before anything is stored, so a settlement that fails leaves the holding
+// locked for its order.
+pub(crate) fn settle(
+    asset: &MarketAsset,
+    seller_id: u64,
+    buyer_id: u64,
+    quantity: u64,
+    operation: &str,
+) -> Result<(), Error> {
+    let seller = load_investor(seller_id)?;
+    let buyer = load_investor(buyer_id)?;
+    match asset {
+        MarketAsset::PoolShares { pool_id } => {
+            let pool = load_pool(*pool_id)?;
+            let position = crate::load_position(&pool, seller_id);
+            if quantity > position.locked_shares {
+                return Err(not_listed(seller_id, position.locked_shares, quantity));
+            }
+            unlock(asset, seller_id, quantity, operation)?;
+            crate::share_token::move_shares(*pool_id, seller, buyer, quantity, operation)
+        }
+        MarketAsset::LoanParticipation { loan_id } => {
+            let participation = load_participation(*loan_id, seller_id);
+            let locked = participation.locked_bps.min(participation.bps) as u64;
+            if quantity > locked {
+                return Err(not_listed(seller_id, locked, quantity));
+            }
+            // Sold basis points stay drawn from the seller's pro-rata claim
+            let mut from = participation;
+            let mut to = load_participation(*loan_id, buyer_id);
+            from.locked_bps -= quantity as u32;
+            from.bps -= quantity as u32;
+            to.bps += quantity as u32;
+            store_participation(&from, operation);
+            store_participation(&to, operation);
+
+            link_investor_loan(buyer_id, *loan_id);
+            if from.bps == 0 {
+                unlink_investor_loan(seller_id, *loan_id);
+            }
+            Ok(())
+        }
+    }
+}
+
+// Price of `quantity` out of an order, pro rata to its asking price for the
+// whole quantity and rounded up in the seller's favour
+pub(crate) fn fill_price(order: &Order, quantity: u64) -> Result<Amount, Error> {
+    let numerator = order.price.minor_units as u128 * quantity as u128;
+    let denominator = order.quantity as u128;
+    let price = (numerator + denominator - 1) / denominator;
+    u64::try_from(price)
+        .map(|minor_units| Amount::new(minor_units, order.price.currency))
+        .map_err(|_| Error::Arithmetic {
+            msg: "Amount overflow".to_string(),
+        })
+}
+
+pub(crate) fn is_expired(order: &Order, now: u64) -> bool {
+    order.expires_at.is_some_and(|expires_at| expires_at <= now)
+}
+
+// Marks an open order as expired and releases what it still had listed
+pub(crate) fn expire(mut order: Order, operation: &str) -> Result<Order, Error> {
+    unlock(&order.asset, order.seller_id, order.remaining, operation)?;
+    order.status = OrderStatus::Expired;
+    crate::store_order(&order, operation);
+    Ok(order)
+}
+
+// Expires every open order whose expiry time has passed
+pub(crate) fn expire_orders(now: u64, operation: &str) {
+    let expired: Vec<Order> = crate::ORDERS_STORAGE.with(|storage| {
+        storage
+            .borrow()
+            .iter()
+            .map(|(_, order)| order)
+            .filter(|order| order.status == OrderStatus::Open && is_expired(order, now))
+            .collect()
+    });
+    for order in expired {
+        let order_id = order.id;
+        // The order stays open and is retried on the next run
+        if let Err(error) = expire(order, operation) {
+            crate::audit::record(
+                operation,
+                EntityKind::Order,
+                order_id,
+                None,
+                &serde_json::json!({ "failure_reason": error.to_string() }),
+            );
+        }
+    }
+}
@@ -0,0 +1,1223 @@
+// Stable-memory schema versioning and migration.
+//
+// Every record is stored in an envelope: a marker byte, the schema version
+// the record was written at as a big-endian u16, then its Candid encoding in
+// that version's layout. Candid encodings start with "DIDL", so bytes without
+// the marker were written before envelopes existed and count as version 0.
+// Decoding reads the version and hands records older than the current one to
+// `Versioned::upgrade`. Whole-memory rewrites are listed in `MIGRATIONS` and
+// run once each from `post_upgrade`, tracked by the schema version cell.
+//
+// Records written before monetary fields became `Amount` stored them as `f64`
+// major units, dates were kept as free-form strings before they became
+// nanosecond timestamps, and later layouts gained payment-tracking fields. The legacy
+// shapes below are generic over the money representation and treat fields
+// added since the first release as optional, so one shape decodes every
+// unversioned layout.
+use candid::{CandidType, Decode, Encode, Principal};
+use ic_stable_structures::{BoundedStorable, Cell, StableBTreeMap};
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+
+use crate::amount::{Amount, Currency};
+use crate::models::*;
+use crate::schedule::parse_timestamp;
+use crate::Memory;
+
+// Currency assumed for amounts recorded before currency tagging existed
+pub(crate) const LEGACY_CURRENCY: Currency = Currency::Kes;
+
+// Monetary representation used by a legacy record layout
+pub(crate) trait LegacyMoney: CandidType + DeserializeOwned {
+    fn into_amount(self) -> Amount;
+}
+
+impl LegacyMoney for f64 {
+    fn into_amount(self) -> Amount {
+        Amount::from_major(self, LEGACY_CURRENCY)
+    }
+}
+
+impl LegacyMoney for Amount {
+    fn into_amount(self) -> Amount {
+        self
+    }
+}
+
+const ENVELOPE_MARKER: u8 = 0xFE;
+
+// A record type persisted in stable memory
+pub(crate) trait Versioned: CandidType + DeserializeOwned {
+    // Name used when a stored record cannot be decoded
+    const ENTITY: &'static str;
+    // Schema version this build writes. Bump it whenever the layout changes
+    // and teach `upgrade` to read the previous version.
+    const VERSION: u16;
+
+    // Decodes a record written at an older schema version. Unversioned
+    // records of types without a legacy layout decode as the current struct.
+    fn upgrade(version: u16, body: &[u8]) -> Result<Self, String> {
+        match version {
+            0 => Decode!(body, Self).map_err(|e| e.to_string()),
+            _ => Err(format!("no upgrade path from schema version {}", version)),
+        }
+    }
+}
+
+// Encodes a record in the current layout, inside a versioned envelope
+pub(crate) fn encode_record<T: Versioned>(record: &T) -> Vec<u8> {
+    let mut bytes = vec![ENVELOPE_MARKER];
+    bytes.extend_from_slice(&T::VERSION.to_be_bytes());
+    bytes.extend(Encode!(record).unwrap());
+    bytes
+}
+
+// Schema version and Candid body of a stored record
+fn open_envelope(bytes: &[u8]) -> (u16, &[u8]) {
+    match bytes {
+        [ENVELOPE_MARKER, high, low, body @ ..] => (u16::from_be_bytes([*high, *low]), body),
+        _ => (0, bytes),
+    }
+}
+
+// Decodes a stored record of any schema version up to the current one
+fn try_decode_record<T: Versioned>(bytes: &[u8]) -> Result<T, String> {
+    let (version, body) = open_envelope(bytes);
+    let record = if version == T::VERSION {
+        Decode!(body, T).map_err(|e| e.to_string())
+    } else if version > T::VERSION {
+        Err(format!(
+            "written at schema version {}, newer than this build's {}",
+            version,
+            T::VERSION
+        ))
+    } else {
+        T::upgrade(version, body)
+    };
+    record.map_err(|e| format!("schema version {}: {}", version, e))
+}
+
+// Decodes a stored record, trapping when it cannot be read so the call or
+// upgrade that touched it is rolled back
+pub(crate) fn decode_record<T: Versioned>(bytes: &[u8]) -> T {
+    try_decode_record(bytes)
+        .unwrap_or_else(|e| ic_cdk::trap(&format!("Cannot decode stored {} ({})", T::ENTITY, e)))
+}
+
+fn amount_or_zero<M: LegacyMoney>(value: Option<M>, currency: Currency) -> Amount {
+    value
+        .map(LegacyMoney::into_amount)
+        .unwrap_or_else(|| Amount::zero(currency))
+}
+
+// Converts a date stored as text. Dates were never validated on the way in,
+// so one the current parser rejects fails the record's decoding instead of
+// being replaced by a made-up date.
+fn legacy_timestamp(text: &str, field: &str) -> Result<u64, String> {
+    text.parse::<u64>()
+        .or_else(|_| parse_timestamp(text, field))
+        .map_err(|_| format!("{} '{}' is not a date", field, text))
+}
+
+#[derive(CandidType, Deserialize)]
+pub(crate) struct LegacyMotorcycle<M> {
+    id: u64,
+    model: String,
+    manufacturer: String,
+    price: M,
+    status: MotorcycleStatus,
+}
+
+impl<M: LegacyMoney> From<LegacyMotorcycle<M>> for Motorcycle {
+    fn from(legacy: LegacyMotorcycle<M>) -> Self {
+        Motorcycle {
+            id: legacy.id,
+            model: legacy.model,
+            manufacturer: legacy.manufacturer,
+            price: legacy.price.into_amount(),
+            status: legacy.status,
+        }
+    }
+}
+
+// The original loan layout carried `daily_payment` instead of a schedule
+#[derive(CandidType, Deserialize)]
+pub(crate) struct LegacyLoan<M> {
+    id: u64,
+    borrower_id: u64,
+    motorcycle_id: u64,
+    principal_amount: M,
+    interest_rate: f64,
+    interest_method: Option<InterestMethod>,
+    frequency: Option<RepaymentFrequency>,
+    term: Option<u32>,
+    installment_amount: Option<M>,
+    daily_payment: Option<M>,
+    total_interest: Option<M>,
+    total_paid: M,
+    principal_paid: Option<M>,
+    interest_paid: Option<M>,
+    penalties_paid: Option<M>,
+    penalties_due: Option<M>,
+    interest_accrued: Option<M>,
+    days_past_due: Option<u32>,
+    last_accrued_on: Option<String>,
+    start_date: String,
+    end_date: String,
+    status: LoanStatus,
+    disbursement_block_index: Option<u64>,
+    pool_id: Option<u64>,
+    decision_reason: Option<String>,
+    decided_by: Option<Principal>,
+    decided_at: Option<u64>,
+}
+
+impl<M: LegacyMoney> TryFrom<LegacyLoan<M>> for Loan {
+    type Error = String;
+
+    fn try_from(legacy: LegacyLoan<M>) -> Result<Self, String> {
+        let principal_amount = legacy.principal_amount.into_amount();
+        let currency = principal_amount.currency;
+        Ok(Loan {
+            id: legacy.id,
+            borrower_id: legacy.borrower_id,
+            motorcycle_id: legacy.motorcycle_id,
+            principal_amount,
+            interest_rate: legacy.interest_rate,
+            interest_method: legacy.interest_method.unwrap_or_default(),
+            frequency: legacy.frequency.unwrap_or_default(),
+            term: legacy.term.unwrap_or_default(),
+            installment_amount: amount_or_zero(
+                legacy.installment_amount.or(legacy.daily_payment),
+                currency,
+            ),
+            total_interest: amount_or_zero(legacy.total_interest, currency),
+            total_paid: legacy.total_paid.into_amount(),
+            principal_paid: amount_or_zero(legacy.principal_paid, currency),
+            interest_paid: amount_or_zero(legacy.interest_paid, currency),
+            penalties_paid: amount_or_zero(legacy.penalties_paid, currency),
+            penalties_due: amount_or_zero(legacy.penalties_due, currency),
+            interest_accrued: amount_or_zero(legacy.interest_accrued, currency),
+            days_past_due: legacy.days_past_due.unwrap_or_default(),
+            last_accrued_on: legacy
+                .last_accrued_on
+                .map(|text| legacy_timestamp(&text, "last_accrued_on"))
+                .transpose()?,
+            start_date: legacy_timestamp(&legacy.start_date, "start_date")?,
+            end_date: legacy_timestamp(&legacy.end_date, "end_date")?,
+            status: legacy.status,
+            disbursement_block_index: legacy.disbursement_block_index,
+            pool_id: legacy.pool_id,
+            decision_reason: legacy.decision_reason,
+            decided_by: legacy.decided_by,
+            decided_at: legacy.decided_at,
+        })
+    }
+}
+
+#[derive(CandidType, Deserialize)]
+pub(crate) struct LegacyInstallment<M> {
+    loan_id: u64,
+    number: u32,
+    due_date: String,
+    principal_part: M,
+    interest_part: M,
+    total_due: M,
+    closing_balance: M,
+    principal_paid: Option<M>,
+    interest_paid: Option<M>,
+    status: Option<InstallmentStatus>,
+}
+
+impl<M: LegacyMoney> TryFrom<LegacyInstallment<M>> for Installment {
+    type Error = String;
+
+    fn try_from(legacy: LegacyInstallment<M>) -> Result<Self, String> {
+        let principal_part = legacy.principal_part.into_amount();
+        let currency = principal_part.currency;
+        Ok(Installment {
+            loan_id: legacy.loan_id,
+            number: legacy.number,
+            due_date: legacy_timestamp(&legacy.due_date, "due_date")?,
+            principal_part,
+            interest_part: legacy.interest_part.into_amount(),
+            total_due: legacy.total_due.into_amount(),
+            closing_balance: legacy.closing_balance.into_amount(),
+            principal_paid: amount_or_zero(legacy.principal_paid, currency),
+            interest_paid: amount_or_zero(legacy.interest_paid, currency),
+            status: legacy.status.unwrap_or_default(),
+        })
+    }
+}
+
+#[derive(CandidType, Deserialize)]
+pub(crate) struct LegacyPayment<M> {
+    id: u64,
+    loan_id: u64,
+    borrower_id: u64,
+    amount: M,
+    date: String,
+    status: PaymentStatus,
+    allocation: Option<PaymentAllocation>,
+    failure_reason: Option<String>,
+    ledger_block_index: Option<u64>,
+    refund_block_index: Option<u64>,
+}
+
+impl<M: LegacyMoney> TryFrom<LegacyPayment<M>> for Payment {
+    type Error = String;
+
+    fn try_from(legacy: LegacyPayment<M>) -> Result<Self, String> {
+        Ok(Payment {
+            id: legacy.id,
+            loan_id: legacy.loan_id,
+            borrower_id: legacy.borrower_id,
+            amount: legacy.amount.into_amount(),
+            date: legacy_timestamp(&legacy.date, "date")?,
+            status: legacy.status,
+            allocation: legacy.allocation,
+            failure_reason: legacy.failure_reason,
+            ledger_block_index: legacy.ledger_block_index,
+            refund_block_index: legacy.refund_block_index,
+        })
+    }
+}
+
+// Investors with one total in a single currency, as stored up to schema
+// version 2
+#[derive(CandidType, Deserialize)]
+pub(crate) struct LegacyInvestor<M> {
+    id: u64,
+    owner: Principal,
+    name: String,
+    email: String,
+    total_invested: M,
+    returns_earned: M,
+}
+
+impl<M: LegacyMoney> From<LegacyInvestor<M>> for Investor {
+    fn from(legacy: LegacyInvestor<M>) -> Self {
+        Investor {
+            id: legacy.id,
+            owner: legacy.owner,
+            name: legacy.name,
+            email: legacy.email,
+            total_invested: legacy_totals(legacy.total_invested),
+            returns_earned: legacy_totals(legacy.returns_earned),
+        }
+    }
+}
+
+// A single-currency total as a list of per-currency totals
+fn legacy_totals<M: LegacyMoney>(total: M) -> Vec<Amount> {
+    let total = total.into_amount();
+    if total.is_zero() {
+        Vec::new()
+    } else {
+        vec![total]
+    }
+}
+
+#[derive(CandidType, Deserialize)]
+pub(crate) struct LegacyLoanPool<M> {
+    id: u64,
+    name: String,
+    total_funds: M,
+    available_funds: M,
+    allocated_funds: Option<M>,
+    total_shares: Option<u64>,
+    platform_fees: Option<M>,
+}
+
+impl<M: LegacyMoney> From<LegacyLoanPool<M>> for LoanPool {
+    fn from(legacy: LegacyLoanPool<M>) -> Self {
+        let total_funds = legacy.total_funds.into_amount();
+        let available_funds = legacy.available_funds.into_amount();
+        // Pools stored before capital reservation: whatever is not available is lent out
+        let allocated_funds = match legacy.allocated_funds {
+            Some(allocated_funds) => allocated_funds.into_amount(),
+            None => total_funds
+                .checked_sub(&available_funds)
+                .unwrap_or(Amount::zero(total_funds.currency)),
+        };
+        // Capital deposited before positions existed is held as unowned shares
+        // at one share per minor unit, so new depositors do not capture it
+        let total_shares = legacy.total_shares.unwrap_or(total_funds.minor_units);
+        LoanPool {
+            id: legacy.id,
+            name: legacy.name,
+            total_funds,
+            available_funds,
+            allocated_funds,
+            total_shares,
+            platform_fees: amount_or_zero(legacy.platform_fees, total_funds.currency),
+        }
+    }
+}
+
+// Pool positions stored before returns were tracked or shares could be listed
+#[derive(CandidType, Deserialize)]
+pub(crate) struct LegacyPoolPosition {
+    pool_id: u64,
+    investor_id: u64,
+    shares: u64,
+    locked_shares: Option<u64>,
+    contributed: Amount,
+    accrued_returns: Option<Amount>,
+    claimed_returns: Option<Amount>,
+}
+
+impl From<LegacyPoolPosition> for PoolPosition {
+    fn from(legacy: LegacyPoolPosition) -> Self {
+        let currency = legacy.contributed.currency;
+        PoolPosition {
+            pool_id: legacy.pool_id,
+            investor_id: legacy.investor_id,
+            shares: legacy.shares,
+            locked_shares: legacy.locked_shares.unwrap_or_default(),
+            contributed: legacy.contributed,
+            accrued_returns: amount_or_zero(legacy.accrued_returns, currency),
+            claimed_returns: amount_or_zero(legacy.claimed_returns, currency),
+        }
+    }
+}
+
+// Participation layout before drawn basis points were tracked
+#[derive(CandidType, Deserialize)]
+pub(crate) struct LegacyLoanParticipation {
+    loan_id: u64,
+    investor_id: u64,
+    bps: u32,
+    locked_bps: u32,
+}
+
+impl From<LegacyLoanParticipation> for LoanParticipation {
+    fn from(legacy: LegacyLoanParticipation) -> Self {
+        LoanParticipation {
+            loan_id: legacy.loan_id,
+            investor_id: legacy.investor_id,
+            bps: legacy.bps,
+            locked_bps: legacy.locked_bps,
+            drawn_bps: 0,
+        }
+    }
+}
+
+// Decodes an unversioned record, first as the current struct and then
+// through its legacy shape, trying the `Amount` layouts before the original
+// floating point one. A record that decodes in a legacy shape but cannot be
+// converted reports why rather than falling through to the next shape.
+fn decode_unversioned<T, A, F>(bytes: &[u8]) -> Result<T, String>
+where
+    T: CandidType + DeserializeOwned,
+    A: CandidType + DeserializeOwned + TryInto<T>,
+    F: CandidType + DeserializeOwned + TryInto<T>,
+    A::Error: ToString,
+    F::Error: ToString,
+{
+    if let Ok(record) = Decode!(bytes, T) {
+        return Ok(record);
+    }
+    if let Ok(legacy) = Decode!(bytes, A) {
+        return legacy.try_into().map_err(|e| e.to_string());
+    }
+    Decode!(bytes, F)
+        .map_err(|e| e.to_string())?
+        .try_into()
+        .map_err(|e| e.to_string())
+}
+
+// Upgrades from an unversioned layout, or reports a version with no upgrade path
+fn upgrade_unversioned<T, A, F>(version: u16, body: &[u8]) -> Result<T, String>
+where
+    T: CandidType + DeserializeOwned,
+    A: CandidType + DeserializeOwned + TryInto<T>,
+    F: CandidType + DeserializeOwned + TryInto<T>,
+    A::Error: ToString,
+    F::Error: ToString,
+{
+    match version {
+        0 => decode_unversioned::<T, A, F>(body),
+        _ => Err(format!("no upgrade path from schema version {}", version)),
+    }
+}
+
+impl Versioned for User {
+    const ENTITY: &'static str = "user";
+    const VERSION: u16 = 1;
+}
+
+impl Versioned for Motorcycle {
+    const ENTITY: &'static str = "motorcycle";
+    const VERSION: u16 = 1;
+
+    fn upgrade(version: u16, body: &[u8]) -> Result<Self, String> {
+        upgrade_unversioned::<_, LegacyMotorcycle<Amount>, LegacyMotorcycle<f64>>(version, body)
+    }
+}
+
+impl Versioned for Loan {
+    const ENTITY: &'static str = "loan";
+    const VERSION: u16 = 1;
+
+    fn upgrade(version: u16, body: &[u8]) -> Result<Self, String> {
+        upgrade_unversioned::<_, LegacyLoan<Amount>, LegacyLoan<f64>>(version, body)
+    }
+}
+
+impl Versioned for Installment {
+    const ENTITY: &'static str = "installment";
+    const VERSION: u16 = 1;
+
+    fn upgrade(version: u16, body: &[u8]) -> Result<Self, String> {
+        upgrade_unversioned::<_, LegacyInstallment<Amount>, LegacyInstallment<f64>>(version, body)
+    }
+}
+
+impl Versioned for Payment {
+    const ENTITY: &'static str = "payment";
+    const VERSION: u16 = 1;
+
+    fn upgrade(version: u16, body: &[u8]) -> Result<Self, String> {
+        upgrade_unversioned::<_, LegacyPayment<Amount>, LegacyPayment<f64>>(version, body)
+    }
+}
+
+impl Versioned for Investor {
+    const ENTITY: &'static str = "investor";
+    const VERSION: u16 = 3;
+
+    fn upgrade(version: u16, body: &[u8]) -> Result<Self, String> {
+        match version {
+            // Versions 1 and 2 kept single-currency totals. Version 1 also
+            // listed the investor's loans, now kept in the investor-loan
+            // relation map; Candid skips the extra field.
+            1 | 2 => Decode!(body, LegacyInvestor<Amount>)
+                .map(Into::into)
+                .map_err(|e| e.to_string()),
+            _ => {
+                upgrade_unversioned::<_, LegacyInvestor<Amount>, LegacyInvestor<f64>>(version, body)
+            }
+        }
+    }
+}
+
+impl Versioned for LoanPool {
+    const ENTITY: &'static str = "loan pool";
+    const VERSION: u16 = 2;
+
+    fn upgrade(version: u16, body: &[u8]) -> Result<Self, String> {
+        match version {
+            // Version 1 also listed the pool's investors and loans, now kept
+            // in relation maps; Candid skips the extra fields
+            1 => Decode!(body, Self).map_err(|e| e.to_string()),
+            _ => {
+                upgrade_unversioned::<_, LegacyLoanPool<Amount>, LegacyLoanPool<f64>>(version, body)
+            }
+        }
+    }
+}
+
+impl Versioned for PoolPosition {
+    const ENTITY: &'static str = "pool position";
+    const VERSION: u16 = 1;
+
+    fn upgrade(version: u16, body: &[u8]) -> Result<Self, String> {
+        // Positions never held `f64` amounts, so there is one legacy shape
+        match version {
+            0 => Decode!(body, Self)
+                .or_else(|_| Decode!(body, LegacyPoolPosition).map(Into::into))
+                .map_err(|e| e.to_string()),
+            _ => Err(format!("no upgrade path from schema version {}", version)),
+        }
+    }
+}
+
+impl Versioned for LoanParticipation {
+    const ENTITY: &'static str = "loan participation";
+    const VERSION: u16 = 2;
+
+    fn upgrade(version: u16, body: &[u8]) -> Result<Self, String> {
+        // Drawn basis points were added in version 2; the
+        // `derive_drawn_participations` migration fills them in
+        match version {
+            0 | 1 => Decode!(body, LegacyLoanParticipation)
+                .map(Into::into)
+                .map_err(|e| e.to_string()),
+            _ => Err(format!("no upgrade path from schema version {}", version)),
+        }
+    }
+}
+
+impl Versioned for Order {
+    const ENTITY: &'static str = "order";
+    const VERSION: u16 = 1;
+}
+
+impl Versioned for Trade {
+    const ENTITY: &'static str = "trade";
+    const VERSION: u16 = 1;
+}
+
+impl Versioned for RecoveryCase {
+    const ENTITY: &'static str = "recovery case";
+    const VERSION: u16 = 1;
+}
+
+impl Versioned for KycRecord {
+    const ENTITY: &'static str = "KYC record";
+    const VERSION: u16 = 1;
+}
+
+impl Versioned for AuditEntry {
+    const ENTITY: &'static str = "audit entry";
+    const VERSION: u16 = 1;
+}
+
+impl Versioned for LedgerConfig {
+    const ENTITY: &'static str = "ledger configuration";
+    const VERSION: u16 = 1;
+}
+
+impl Versioned for AccrualConfig {
+    const ENTITY: &'static str = "accrual configuration";
+    const VERSION: u16 = 1;
+}
+
+impl Versioned for FeeConfig {
+    const ENTITY: &'static str = "fee configuration";
+    const VERSION: u16 = 1;
+}
+
+impl Versioned for AccessConfig {
+    const ENTITY: &'static str = "access configuration";
+    const VERSION: u16 = 1;
+}
+
+// Re-inserts every entry so records decoded through a legacy shape are
+// persisted in the current layout.
+fn rewrite_all<K, V>(map: &mut StableBTreeMap<K, V, Memory>)
+where
+    K: BoundedStorable + Ord + Clone,
+    V: BoundedStorable,
+{
+    let entries: Vec<(K, V)> = map.iter().collect();
+    for (key, value) in entries {
+        map.insert(key, value);
+    }
+}
+
+// Re-stores a cell's value in the current layout
+fn rewrite_cell<T: Versioned + ic_stable_structures::Storable + Clone>(cell: &mut Cell<T, Memory>) {
+    let value = cell.get().clone();
+    if cell.set(value).is_err() {
+        ic_cdk::trap(&format!("Cannot rewrite the stored {}", T::ENTITY));
+    }
+}
+
+// Rewrites every record and configuration in the current layout
+fn rewrite_all_records() {
+    crate::USERS_STORAGE.with(|storage| rewrite_all(&mut storage.borrow_mut()));
+    crate::MOTORCYCLES_STORAGE.with(|storage| rewrite_all(&mut storage.borrow_mut()));
+    crate::LOANS_STORAGE.with(|storage| rewrite_all(&mut storage.borrow_mut()));
+    crate::INSTALLMENTS_STORAGE.with(|storage| rewrite_all(&mut storage.borrow_mut()));
+    crate::PAYMENTS_STORAGE.with(|storage| rewrite_all(&mut storage.borrow_mut()));
+    crate::INVESTORS_STORAGE.with(|storage| rewrite_all(&mut storage.borrow_mut()));
+    crate::LOAN_POOLS_STORAGE.with(|storage| rewrite_all(&mut storage.borrow_mut()));
+    crate::POOL_POSITIONS_STORAGE.with(|storage| rewrite_all(&mut storage.borrow_mut()));
+    crate::LOAN_PARTICIPATIONS_STORAGE.with(|storage| rewrite_all(&mut storage.borrow_mut()));
+    crate::ORDERS_STORAGE.with(|storage| rewrite_all(&mut storage.borrow_mut()));
+    crate::TRADES_STORAGE.with(|storage| rewrite_all(&mut storage.borrow_mut()));
+    crate::RECOVERY_CASES_STORAGE.with(|storage| rewrite_all(&mut storage.borrow_mut()));
+    crate::LEDGER_CONFIG.with(|cell| rewrite_cell(&mut cell.borrow_mut()));
+    crate::ACCRUAL_CONFIG.with(|cell| rewrite_cell(&mut cell.borrow_mut()));
+    crate::FEE_CONFIG.with(|cell| rewrite_cell(&mut cell.borrow_mut()));
+    crate::ACCESS_CONFIG.with(|cell| rewrite_cell(&mut cell.borrow_mut()));
+}
+
+// Fills the relation maps that replaced the ID lists on pools and investors
+// from the records they summarized, then drops the lists from stored pools
+// and investors. Pool investors are read from pool positions directly.
+fn build_relation_maps() {
+    let loans: Vec<Loan> = crate::LOANS_STORAGE
+        .with(|storage| storage.borrow().iter().map(|(_, loan)| loan).collect());
+    for loan in &loans {
+        crate::sync_pool_loan(loan);
+    }
+    let holdings: Vec<InvestorLoanKey> = crate::LOAN_PARTICIPATIONS_STORAGE.with(|storage| {
+        storage
+            .borrow()
+            .iter()
+            .filter(|(_, participation)| participation.bps > 0)
+            .map(|(key, _)| InvestorLoanKey {
+                investor_id: key.investor_id,
+                loan_id: key.loan_id,
+            })
+            .collect()
+    });
+    crate::INVESTOR_LOANS_STORAGE.with(|storage| {
+        let mut storage = storage.borrow_mut();
+        for key in holdings {
+            storage.insert(key, ());
+        }
+    });
+    crate::LOAN_POOLS_STORAGE.with(|storage| rewrite_all(&mut storage.borrow_mut()));
+    crate::INVESTORS_STORAGE.with(|storage| rewrite_all(&mut storage.borrow_mut()));
+}
+
+// A loan's first listing used to split all of its income among the pool's
+// investors at that moment. Those seeded basis points become drawn from the
+// holders' pro-rata claims: what each holder bought or sold since is taken
+// from the trade history, and the rest of its participation was seeded.
+fn derive_drawn_participations() {
+    let trades: Vec<Trade> = crate::TRADES_STORAGE
+        .with(|storage| storage.borrow().iter().map(|(_, trade)| trade).collect());
+    crate::LOAN_PARTICIPATIONS_STORAGE.with(|storage| {
+        let mut storage = storage.borrow_mut();
+        let participations: Vec<(ParticipationKey, LoanParticipation)> = storage.iter().collect();
+        for (key, mut participation) in participations {
+            let asset = MarketAsset::LoanParticipation {
+                loan_id: participation.loan_id,
+            };
+            let (mut bought, mut sold) = (0u64, 0u64);
+            for trade in trades.iter().filter(|trade| trade.asset == asset) {
+                if trade.buyer_id == participation.investor_id {
+                    bought += trade.quantity;
+                }
+                if trade.seller_id == participation.investor_id {
+                    sold += trade.quantity;
+                }
+            }
+            let seeded = (participation.bps as u64 + sold).saturating_sub(bought);
+            participation.drawn_bps = seeded.min(u32::MAX as u64) as u32;
+            storage.insert(key, participation);
+        }
+    });
+}
+
+// A rewrite of stable memory that brings it to `version`
+struct Migration {
+    version: u64,
+    run: fn(),
+}
+
+// Every migration, in version order. Append new ones; never reorder or
+// remove a released entry.
+const MIGRATIONS: &[Migration] = &[
+    // Store every record in a versioned envelope
+    Migration {
+        version: 1,
+        run: rewrite_all_records,
+    },
+    // Move pool and investor ID lists into relation maps
+    Migration {
+        version: 2,
+        run: build_relation_maps,
+    },
+    // Build the user, loan and payment secondary indexes
+    Migration {
+        version: 3,
+        run: crate::index::rebuild,
+    },
+    // Build the owner principal → investor index
+    Migration {
+        version: 4,
+        run: crate::index::rebuild_investors,
+    },
+    // Record participations seeded at a loan's first listing as drawn
+    Migration {
+        version: 5,
+        run: derive_drawn_participations,
+    },
+];
+
+// Schema version of stable memory written by this build
+pub(crate) fn latest_schema_version() -> u64 {
+    MIGRATIONS.last().map_or(0, |migration| migration.version)
+}
+
+fn store_schema_version(version: u64) {
+    crate::SCHEMA_VERSION.with(|cell| {
+        cell.borrow_mut()
+            .set(version)
+            .expect("Cannot store the schema version")
+    });
+}
+
+// Marks freshly installed stable memory as already at the latest version
+pub(crate) fn init_schema_version() {
+    store_schema_version(latest_schema_version());
+}
+
+// Runs, in order, every migration newer than the schema version stable
+// memory was left at by the previous build
+pub(crate) fn run_migrations() {
+    let current = crate::SCHEMA_VERSION.with(|cell| *cell.borrow().get());
+    if current > latest_schema_version() {
+        ic_cdk::trap(&format!(
+            "Stable memory is at schema version {}, newer than this build's {}",
+            current,
+            latest_schema_version()
+        ));
+    }
+    for migration in MIGRATIONS
+        .iter()
+        .filter(|migration| migration.version > current)
+    {
+        (migration.run)();
+        store_schema_version(migration.version);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::{defaulted_loan, kes, pool};
+
+    // Layouts written by earlier releases, field for field
+
+    // The original release: `f64` major units, text dates and ID lists
+    #[derive(CandidType)]
+    struct OriginalMotorcycle {
+        id: u64,
+        model: String,
+        manufacturer: String,
+        price: f64,
+        status: MotorcycleStatus,
+    }
+
+    #[derive(CandidType)]
+    struct OriginalLoan {
+        id: u64,
+        borrower_id: u64,
+        motorcycle_id: u64,
+        principal_amount: f64,
+        interest_rate: f64,
+        daily_payment: f64,
+        total_paid: f64,
+        start_date: String,
+        end_date: String,
+        status: LoanStatus,
+    }
+
+    #[derive(CandidType)]
+    struct OriginalPayment {
+        id: u64,
+        loan_id: u64,
+        borrower_id: u64,
+        amount: f64,
+        date: String,
+        status: PaymentStatus,
+    }
+
+    #[derive(CandidType)]
+    struct OriginalInvestor {
+        id: u64,
+        owner: Principal,
+        name: String,
+        email: String,
+        total_invested: f64,
+        active_loans: Vec<u64>,
+        returns_earned: f64,
+    }
+
+    #[derive(CandidType)]
+    struct OriginalLoanPool {
+        id: u64,
+        name: String,
+        total_funds: f64,
+        available_funds: f64,
+        investor_ids: Vec<u64>,
+        active_loans: Vec<u64>,
+    }
+
+    // Unversioned `Amount` layouts, before capital reservation and returns
+    #[derive(CandidType)]
+    struct AmountInstallment {
+        loan_id: u64,
+        number: u32,
+        due_date: String,
+        principal_part: Amount,
+        interest_part: Amount,
+        total_due: Amount,
+        closing_balance: Amount,
+    }
+
+    #[derive(CandidType)]
+    struct AmountLoanPool {
+        id: u64,
+        name: String,
+        total_funds: Amount,
+        available_funds: Amount,
+    }
+
+    #[derive(CandidType)]
+    struct AmountPoolPosition {
+        pool_id: u64,
+        investor_id: u64,
+        shares: u64,
+        contributed: Amount,
+    }
+
+    // Schema version 1 envelopes, before the ID lists moved to relation maps
+    #[derive(CandidType)]
+    struct InvestorV1 {
+        id: u64,
+        owner: Principal,
+        name: String,
+        email: String,
+        total_invested: Amount,
+        active_loans: Vec<u64>,
+        returns_earned: Amount,
+    }
+
+    #[derive(CandidType)]
+    struct LoanPoolV1 {
+        id: u64,
+        name: String,
+        total_funds: Amount,
+        available_funds: Amount,
+        allocated_funds: Amount,
+        total_shares: u64,
+        platform_fees: Amount,
+        investor_ids: Vec<u64>,
+        active_loans: Vec<u64>,
+    }
+
+    // Schema version 2: investor totals in a single currency
+    #[derive(CandidType)]
+    struct InvestorV2 {
+        id: u64,
+        owner: Principal,
+        name: String,
+        email: String,
+        total_invested: Amount,
+        returns_earned: Amount,
+    }
+
+    fn unversioned<T: CandidType>(record: &T) -> Vec<u8> {
+        Encode!(record).unwrap()
+    }
+
+    fn envelope<T: CandidType>(version: u16, record: &T) -> Vec<u8> {
+        let mut bytes = vec![ENVELOPE_MARKER];
+        bytes.extend_from_slice(&version.to_be_bytes());
+        bytes.extend(Encode!(record).unwrap());
+        bytes
+    }
+
+    fn date(text: &str) -> u64 {
+        parse_timestamp(text, "date").unwrap()
+    }
+
+    #[test]
+    fn original_motorcycle_converts_f64_price() {
+        let bytes = unversioned(&OriginalMotorcycle {
+            id: 3,
+            model: "Boxer BM 150".to_string(),
+            manufacturer: "Bajaj".to_string(),
+            price: 185_000.5,
+            status: MotorcycleStatus::InLoan,
+        });
+        let motorcycle: Motorcycle = try_decode_record(&bytes).unwrap();
+        assert_eq!(motorcycle.id, 3);
+        assert_eq!(motorcycle.model, "Boxer BM 150");
+        assert_eq!(motorcycle.price, kes(18_500_050));
+        assert_eq!(motorcycle.status, MotorcycleStatus::InLoan);
+    }
+
+    #[test]
+    fn original_loan_converts_amounts_and_dates() {
+        let bytes = unversioned(&OriginalLoan {
+            id: 7,
+            borrower_id: 2,
+            motorcycle_id: 3,
+            principal_amount: 100_000.0,
+            interest_rate: 12.0,
+            daily_payment: 350.0,
+            total_paid: 700.25,
+            start_date: "2024-01-15".to_string(),
+            end_date: "2024-12-31T00:00:00Z".to_string(),
+            status: LoanStatus::Active,
+        });
+        let loan: Loan = try_decode_record(&bytes).unwrap();
+        assert_eq!(loan.id, 7);
+        assert_eq!(loan.borrower_id, 2);
+        assert_eq!(loan.principal_amount, kes(10_000_000));
+        assert_eq!(loan.installment_amount, kes(35_000));
+        assert_eq!(loan.total_paid, kes(70_025));
+        assert_eq!(loan.principal_paid, kes(0));
+        assert_eq!(loan.penalties_due, kes(0));
+        assert_eq!(loan.interest_method, InterestMethod::default());
+        assert_eq!(loan.term, 0);
+        assert_eq!(loan.start_date, date("2024-01-15"));
+        assert_eq!(loan.end_date, date("2024-12-31"));
+        assert_eq!(loan.last_accrued_on, None);
+        assert_eq!(loan.status, LoanStatus::Active);
+        assert_eq!(loan.pool_id, None);
+    }
+
+    #[test]
+    fn unparseable_legacy_dates_fail_decoding() {
+        let mut payment = OriginalPayment {
+            id: 11,
+            loan_id: 7,
+            borrower_id: 2,
+            amount: 350.0,
+            date: "last Tuesday".to_string(),
+            status: PaymentStatus::Completed,
+        };
+        let error = try_decode_record::<Payment>(&unversioned(&payment)).unwrap_err();
+        assert_eq!(error, "schema version 0: date 'last Tuesday' is not a date");
+
+        payment.date = "2024-02-01".to_string();
+        let payment: Payment = try_decode_record(&unversioned(&payment)).unwrap();
+        assert_eq!(payment.date, date("2024-02-01"));
+        assert_eq!(payment.amount, kes(35_000));
+        assert_eq!(payment.status, PaymentStatus::Completed);
+        assert!(payment.allocation.is_none());
+        assert_eq!(payment.ledger_block_index, None);
+    }
+
+    #[test]
+    fn original_investor_and_pool_drop_id_lists() {
+        let owner = Principal::anonymous();
+        let bytes = unversioned(&OriginalInvestor {
+            id: 9,
+            owner,
+            name: "Wanjiru".to_string(),
+            email: "wanjiru@example.com".to_string(),
+            total_invested: 50_000.0,
+            active_loans: vec![7, 8],
+            returns_earned: 1_250.75,
+        });
+        let investor: Investor = try_decode_record(&bytes).unwrap();
+        assert_eq!(investor.owner, owner);
+        assert_eq!(investor.total_invested, vec![kes(5_000_000)]);
+        assert_eq!(investor.returns_earned, vec![kes(125_075)]);
+
+        let bytes = unversioned(&OriginalLoanPool {
+            id: 1,
+            name: "Nairobi riders".to_string(),
+            total_funds: 500_000.0,
+            available_funds: 350_000.0,
+            investor_ids: vec![9],
+            active_loans: vec![7, 8],
+        });
+        let pool: LoanPool = try_decode_record(&bytes).unwrap();
+        assert_eq!(pool.total_funds, kes(50_000_000));
+        assert_eq!(pool.available_funds, kes(35_000_000));
+        // Capital not available was lent out, and is held as unowned shares
+        assert_eq!(pool.allocated_funds, kes(15_000_000));
+        assert_eq!(pool.total_shares, 50_000_000);
+        assert_eq!(pool.platform_fees, kes(0));
+    }
+
+    #[test]
+    fn unversioned_amount_layouts_fill_later_fields() {
+        let bytes = unversioned(&AmountInstallment {
+            loan_id: 7,
+            number: 2,
+            due_date: "2024-03-15".to_string(),
+            principal_part: kes(2_500_000),
+            interest_part: kes(100_000),
+            total_due: kes(2_600_000),
+            closing_balance: kes(5_000_000),
+        });
+        let installment: Installment = try_decode_record(&bytes).unwrap();
+        assert_eq!(installment.due_date, date("2024-03-15"));
+        assert_eq!(installment.total_due, kes(2_600_000));
+        assert_eq!(installment.principal_paid, kes(0));
+        assert_eq!(installment.status, InstallmentStatus::default());
+
+        let bytes = unversioned(&AmountLoanPool {
+            id: 2,
+            name: "Kampala riders".to_string(),
+            total_funds: Amount::new(900_000, Currency::Ugx),
+            available_funds: Amount::new(600_000, Currency::Ugx),
+        });
+        let pool: LoanPool = try_decode_record(&bytes).unwrap();
+        assert_eq!(pool.allocated_funds, Amount::new(300_000, Currency::Ugx));
+        assert_eq!(pool.total_shares, 900_000);
+        assert_eq!(pool.platform_fees, Amount::zero(Currency::Ugx));
+
+        let bytes = unversioned(&AmountPoolPosition {
+            pool_id: 1,
+            investor_id: 9,
+            shares: 40_000,
+            contributed: kes(40_000),
+        });
+        let position: PoolPosition = try_decode_record(&bytes).unwrap();
+        assert_eq!(position.shares, 40_000);
+        assert_eq!(position.locked_shares, 0);
+        assert_eq!(position.accrued_returns, kes(0));
+        assert_eq!(position.claimed_returns, kes(0));
+    }
+
+    #[test]
+    fn version_one_envelopes_skip_id_lists() {
+        let bytes = envelope(
+            1,
+            &InvestorV1 {
+                id: 9,
+                owner: Principal::anonymous(),
+                name: "Wanjiru".to_string(),
+                email: "wanjiru@example.com".to_string(),
+                total_invested: kes(5_000_000),
+                active_loans: vec![7],
+                returns_earned: kes(125_075),
+            },
+        );
+        let investor: Investor = try_decode_record(&bytes).unwrap();
+        assert_eq!(investor.id, 9);
+        assert_eq!(investor.returns_earned, vec![kes(125_075)]);
+
+        let bytes = envelope(
+            1,
+            &LoanPoolV1 {
+                id: 1,
+                name: "Nairobi riders".to_string(),
+                total_funds: kes(500_000),
+                available_funds: kes(400_000),
+                allocated_funds: kes(100_000),
+                total_shares: 480_000,
+                platform_fees: kes(1_200),
+                investor_ids: vec![9],
+                active_loans: vec![7],
+            },
+        );
+        let pool: LoanPool = try_decode_record(&bytes).unwrap();
+        assert_eq!(pool.allocated_funds, kes(100_000));
+        assert_eq!(pool.total_shares, 480_000);
+        assert_eq!(pool.platform_fees, kes(1_200));
+    }
+
+    #[test]
+    fn version_two_investor_keeps_totals_per_currency() {
+        let bytes = envelope(
+            2,
+            &InvestorV2 {
+                id: 9,
+                owner: Principal::anonymous(),
+                name: "Wanjiru".to_string(),
+                email: "wanjiru@example.com".to_string(),
+                total_invested: Amount::new(750_000, Currency::Ugx),
+                returns_earned: Amount::zero(Currency::Kes),
+            },
+        );
+        let investor: Investor = try_decode_record(&bytes).unwrap();
+        assert_eq!(
+            investor.total_invested,
+            vec![Amount::new(750_000, Currency::Ugx)]
+        );
+        assert!(investor.returns_earned.is_empty());
+
+        let decoded: Investor = try_decode_record(&encode_record(&investor)).unwrap();
+        assert_eq!(decoded.total_invested, investor.total_invested);
+    }
+
+    #[test]
+    fn version_one_participation_has_nothing_drawn() {
+        let bytes = envelope(
+            1,
+            &LegacyLoanParticipation {
+                loan_id: 7,
+                investor_id: 2,
+                bps: 2_500,
+                locked_bps: 500,
+            },
+        );
+        let participation: LoanParticipation = try_decode_record(&bytes).unwrap();
+        assert_eq!(participation.bps, 2_500);
+        assert_eq!(participation.locked_bps, 500);
+        assert_eq!(participation.drawn_bps, 0);
+    }
+
+    #[test]
+    fn current_records_round_trip() {
+        let (loan, installments) = defaulted_loan(100_000);
+        let decoded: Loan = try_decode_record(&encode_record(&loan)).unwrap();
+        assert_eq!(decoded.principal_amount, loan.principal_amount);
+        assert_eq!(decoded.end_date, loan.end_date);
+        assert_eq!(decoded.status, loan.status);
+
+        let decoded: Installment = try_decode_record(&encode_record(&installments[3])).unwrap();
+        assert_eq!(decoded.due_date, installments[3].due_date);
+        assert_eq!(decoded.closing_balance, installments[3].closing_balance);
+
+        let decoded: LoanPool = try_decode_record(&encode_record(&pool(250_000))).unwrap();
+        assert_eq!(decoded.total_funds, kes(250_000));
+        assert_eq!(decoded.total_shares, 250_000);
+    }
+
+    #[test]
+    fn newer_and_corrupt_records_are_rejected() {
+        let (loan, _) = defaulted_loan(100_000);
+        let error = try_decode_record::<Loan>(&envelope(Loan::VERSION + 1, &loan)).unwrap_err();
+        assert!(error.contains("newer than this build's"), "{}", error);
+        assert!(try_decode_record::<Loan>(b"not a record").is_err());
+        assert!(try_decode_record::<Loan>(&unversioned(&kes(100))).is_err());
+    }
+
+    #[test]
+    fn migrations_bring_memory_to_the_latest_version() {
+        let versions: Vec<u64> = MIGRATIONS
+            .iter()
+            .map(|migration| migration.version)
+            .collect();
+        assert_eq!(versions, (1..=latest_schema_version()).collect::<Vec<_>>());
+        assert_eq!(latest_schema_version(), 5);
+
+        let (mut loan, _) = defaulted_loan(100_000);
+        loan.pool_id = Some(1);
+        crate::LOANS_STORAGE.with(|storage| storage.borrow_mut().insert(loan.id, loan.clone()));
+        crate::LOAN_POOLS_STORAGE.with(|storage| storage.borrow_mut().insert(1, pool(250_000)));
+        let owner = Principal::from_slice(&[7; 29]);
+        let investor = Investor {
+            id: 2,
+            owner,
+            name: "Amina".to_string(),
+            email: "amina@example.com".to_string(),
+            total_invested: Vec::new(),
+            returns_earned: Vec::new(),
+        };
+        crate::INVESTORS_STORAGE.with(|storage| storage.borrow_mut().insert(2, investor));
+        let key = ParticipationKey {
+            loan_id: loan.id,
+            investor_id: 2,
+        };
+        let seeded = LoanParticipation {
+            loan_id: loan.id,
+            investor_id: 2,
+            bps: 2_500,
+            locked_bps: 0,
+            drawn_bps: 0,
+        };
+        crate::LOAN_PARTICIPATIONS_STORAGE.with(|storage| storage.borrow_mut().insert(key, seeded));
+        for migration in MIGRATIONS {
+            (migration.run)();
+        }
+        assert_eq!(crate::pool_loan_ids(1), vec![loan.id]);
+        assert_eq!(
+            crate::index::borrower_loan_ids(loan.borrower_id),
+            vec![loan.id]
+        );
+        assert_eq!(
+            crate::index::status_loan_ids(LoanStatus::Defaulted),
+            vec![loan.id]
+        );
+        let stored = crate::LOAN_POOLS_STORAGE
+            .with(|storage| storage.borrow().get(&1))
+            .unwrap();
+        assert_eq!(stored.total_funds, kes(250_000));
+        assert_eq!(crate::index::investor_by_owner(owner), Some(2));
+        let participation = crate::LOAN_PARTICIPATIONS_STORAGE
+            .with(|storage| storage.borrow().get(&key))
+            .unwrap();
+        assert_eq!(participation.drawn_bps, 2_500);
+        assert_eq!(
+            crate::index::investor_by_owner(Principal::anonymous()),
+            None
+        );
+    }
+}
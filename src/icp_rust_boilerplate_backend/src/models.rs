@@ -1,6 +1,9 @@
 use candid::{CandidType, Principal};
 use serde::{Deserialize, Serialize};
 
+use crate::amount::{Amount, Currency};
+use crate::error::EntityKind;
+
 // User Role Types Enum
 #[derive(CandidType, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Hash, Default, Debug)]
 pub enum UserRole {
@@ -22,13 +25,37 @@ pub struct User {
     pub(crate) role: UserRole,
 }
 
-// Struct representing a Motorcycle
+// Borrower KYC Record, one per user. Timestamps are nanoseconds since the
+// epoch.
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct KycRecord {
+    pub(crate) user_id: u64,
+    pub(crate) national_id: String,
+    pub(crate) phone: String,         // E.164, e.g. +254712345678
+    pub(crate) document_hash: String, // Hex SHA-256 of the identity document
+    pub(crate) status: KycStatus,
+    pub(crate) submitted_at: u64,
+    pub(crate) verified_by: Option<Principal>, // Officer who verified or rejected the submission
+    pub(crate) reviewed_at: Option<u64>,
+    pub(crate) rejection_reason: Option<String>,
+}
+
+// KYC Status Enum. Users without a KYC record are Unverified.
+#[derive(CandidType, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Hash, Default, Debug)]
+pub enum KycStatus {
+    #[default]
+    Unverified,
+    Pending,
+    Verified,
+    Rejected,
+}
+
 #[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
 pub struct Motorcycle {
     pub(crate) id: u64,
     pub(crate) model: String,
     pub(crate) manufacturer: String,
-    pub(crate) price: f64,
+    pub(crate) price: Amount,
     pub(crate) status: MotorcycleStatus, // Available, In Loan, etc.
 }
 
@@ -43,27 +70,111 @@ pub enum MotorcycleStatus {
 }
 
 // Loan Struct
-#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+#[derive(CandidType, Serialize, Deserialize, Clone, PartialEq, Debug)]
 pub struct Loan {
     pub(crate) id: u64,
     pub(crate) borrower_id: u64,
     pub(crate) motorcycle_id: u64,
-    pub(crate) principal_amount: f64,
-    pub(crate) interest_rate: f64,
-    pub(crate) daily_payment: f64,
-    pub(crate) total_paid: f64,
-    pub(crate) start_date: String,
-    pub(crate) end_date: String,
+    pub(crate) principal_amount: Amount,
+    pub(crate) interest_rate: f64, // Annual rate in percent
+    pub(crate) interest_method: InterestMethod,
+    pub(crate) frequency: RepaymentFrequency,
+    pub(crate) term: u32, // Number of installments
+    pub(crate) installment_amount: Amount,
+    pub(crate) total_interest: Amount,
+    pub(crate) total_paid: Amount,
+    pub(crate) principal_paid: Amount,
+    pub(crate) interest_paid: Amount,
+    pub(crate) penalties_paid: Amount,
+    pub(crate) penalties_due: Amount,
+    pub(crate) interest_accrued: Amount,
+    pub(crate) days_past_due: u32,
+    pub(crate) last_accrued_on: Option<u64>, // Dates are nanosecond timestamps of the day's start
+    pub(crate) start_date: u64,
+    pub(crate) end_date: u64,
     pub(crate) status: LoanStatus,
+    pub(crate) disbursement_block_index: Option<u64>,
+    pub(crate) pool_id: Option<u64>, // Loan pool that funds the principal, if any
+    pub(crate) decision_reason: Option<String>, // Why the application was approved or rejected
+    pub(crate) decided_by: Option<Principal>,
+    pub(crate) decided_at: Option<u64>, // Nanoseconds since the epoch
 }
 
 // Loan Status Enum
-#[derive(CandidType, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Hash, Default, Debug)]
+#[derive(
+    CandidType,
+    Serialize,
+    Deserialize,
+    Clone,
+    Copy,
+    PartialEq,
+    Eq,
+    PartialOrd,
+    Ord,
+    Hash,
+    Default,
+    Debug,
+)]
 pub enum LoanStatus {
     #[default]
+    Applied,
+    UnderReview,
+    Approved,
+    Rejected,
+    Disbursed, // Principal sent, repayment not started yet
     Active,
     Completed,
     Defaulted,
+    WrittenOff, // Closed after recovery with a balance left unpaid
+}
+
+// Repayment Frequency Enum
+#[derive(CandidType, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Hash, Default, Debug)]
+pub enum RepaymentFrequency {
+    #[default]
+    Daily,
+    Weekly,
+    Monthly,
+}
+
+// Interest Method Enum
+#[derive(CandidType, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Hash, Default, Debug)]
+pub enum InterestMethod {
+    #[default]
+    Flat,
+    ReducingBalance,
+}
+
+// Installment Struct (one row of a loan's amortization schedule)
+#[derive(CandidType, Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub struct Installment {
+    pub(crate) loan_id: u64,
+    pub(crate) number: u32,
+    pub(crate) due_date: u64, // Nanosecond timestamp of the day's start
+    pub(crate) principal_part: Amount,
+    pub(crate) interest_part: Amount,
+    pub(crate) total_due: Amount,
+    pub(crate) closing_balance: Amount,
+    pub(crate) principal_paid: Amount,
+    pub(crate) interest_paid: Amount,
+    pub(crate) status: InstallmentStatus,
+}
+
+// Installment Status Enum
+#[derive(CandidType, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Hash, Default, Debug)]
+pub enum InstallmentStatus {
+    #[default]
+    Pending,
+    PartiallyPaid,
+    Paid,
+    Overdue,
+}
+
+// Installment Storage Key (loan ID, installment number)
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
+pub struct InstallmentKey {
+    pub(crate) loan_id: u64,
+    pub(crate) number: u32,
 }
 
 // Payment Struct
@@ -72,9 +183,22 @@ pub struct Payment {
     pub(crate) id: u64,
     pub(crate) loan_id: u64,
     pub(crate) borrower_id: u64,
-    pub(crate) amount: f64,
-    pub(crate) date: String,
+    pub(crate) amount: Amount,
+    pub(crate) date: u64, // Nanoseconds since the epoch
     pub(crate) status: PaymentStatus,
+    pub(crate) allocation: Option<PaymentAllocation>,
+    pub(crate) failure_reason: Option<String>,
+    pub(crate) ledger_block_index: Option<u64>,
+    pub(crate) refund_block_index: Option<u64>,
+}
+
+// How a settled payment was split across the loan balance
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct PaymentAllocation {
+    pub(crate) penalties: Amount,
+    pub(crate) interest: Amount,
+    pub(crate) principal: Amount,
+    pub(crate) remaining_balance: Amount,
 }
 
 // Payment Status Enum
@@ -86,6 +210,37 @@ pub enum PaymentStatus {
     Failed,
 }
 
+// Recovery Case Struct: the default-to-write-off workflow of one defaulted
+// loan. Timestamps are nanoseconds since the epoch.
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct RecoveryCase {
+    pub(crate) loan_id: u64,
+    pub(crate) motorcycle_id: u64,
+    pub(crate) stage: RecoveryStage,
+    pub(crate) notice_issued_at: u64,
+    pub(crate) grace_period_ends_at: u64,
+    pub(crate) agent: Option<Principal>, // Repossession agent
+    pub(crate) repossession_ordered_at: Option<u64>,
+    pub(crate) repossessed_at: Option<u64>,
+    pub(crate) sale_price: Option<Amount>,
+    pub(crate) sold_at: Option<u64>,
+    pub(crate) allocation: Option<PaymentAllocation>, // How the sale proceeds were applied
+    pub(crate) surplus: Option<Amount>,               // Proceeds beyond the outstanding balance
+    pub(crate) written_off: Option<Amount>,           // Principal charged to the funding pool
+    pub(crate) closed_at: Option<u64>,
+}
+
+// Recovery Stage Enum
+#[derive(CandidType, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Hash, Default, Debug)]
+pub enum RecoveryStage {
+    #[default]
+    NoticeIssued,
+    RepossessionOrdered,
+    Repossessed,
+    Sold,
+    Closed,
+}
+
 // Investor Struct
 #[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
 pub struct Investor {
@@ -93,9 +248,8 @@ pub struct Investor {
     pub(crate) owner: Principal,
     pub(crate) name: String,
     pub(crate) email: String,
-    pub(crate) total_invested: f64,
-    pub(crate) active_loans: Vec<u64>,
-    pub(crate) returns_earned: f64,
+    pub(crate) total_invested: Vec<Amount>, // One total per currency invested in
+    pub(crate) returns_earned: Vec<Amount>, // One total per currency earned in
 }
 
 // Loan Pool Struct
@@ -103,14 +257,409 @@ pub struct Investor {
 pub struct LoanPool {
     pub(crate) id: u64,
     pub(crate) name: String,
-    pub(crate) total_funds: f64,
-    pub(crate) available_funds: f64,
-    pub(crate) investor_ids: Vec<u64>,
-    pub(crate) active_loans: Vec<u64>,
+    pub(crate) total_funds: Amount,
+    pub(crate) available_funds: Amount, // Capital not committed to loans
+    pub(crate) allocated_funds: Amount, // Outstanding principal of the pool's loans
+    pub(crate) total_shares: u64,
+    pub(crate) platform_fees: Amount, // Platform's cut of the pool's interest income
+}
+
+// An investor's stake in a loan pool
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct PoolPosition {
+    pub(crate) pool_id: u64,
+    pub(crate) investor_id: u64,
+    pub(crate) shares: u64,
+    pub(crate) locked_shares: u64, // Shares listed for sale on the secondary market
+    pub(crate) contributed: Amount, // Deposits net of withdrawals
+    pub(crate) accrued_returns: Amount, // Distributed income not claimed yet
+    pub(crate) claimed_returns: Amount,
+}
+
+// An investor's entitlement to the interest and penalties of one loan, in
+// basis points of the loan's income
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct LoanParticipation {
+    pub(crate) loan_id: u64,
+    pub(crate) investor_id: u64,
+    pub(crate) bps: u32,
+    pub(crate) locked_bps: u32, // Basis points listed for sale on the secondary market
+    pub(crate) drawn_bps: u32,  // Basis points taken out of the investor's pro-rata claim
+}
+
+// Loan Participation Storage Key (loan ID, investor ID)
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
+pub struct ParticipationKey {
+    pub(crate) loan_id: u64,
+    pub(crate) investor_id: u64,
+}
+
+// Exposure traded on the secondary market: pool shares, or basis points of a
+// loan's income
+#[derive(CandidType, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum MarketAsset {
+    PoolShares { pool_id: u64 },
+    LoanParticipation { loan_id: u64 },
+}
+
+// Secondary Market Order Struct
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct Order {
+    pub(crate) id: u64,
+    pub(crate) seller_id: u64, // Investor ID
+    pub(crate) asset: MarketAsset,
+    pub(crate) quantity: u64,
+    pub(crate) remaining: u64,
+    pub(crate) price: Amount, // Asking price for the whole quantity
+    pub(crate) status: OrderStatus,
+    pub(crate) created_at: u64, // Nanoseconds since the epoch
+    pub(crate) expires_at: Option<u64>,
+}
+
+// Order Status Enum
+#[derive(CandidType, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Hash, Default, Debug)]
+pub enum OrderStatus {
+    #[default]
+    Open,
+    Filled,
+    Cancelled,
+    Expired,
+}
+
+// A (partial) fill of a secondary market order
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct Trade {
+    pub(crate) id: u64,
+    pub(crate) order_id: u64,
+    pub(crate) asset: MarketAsset,
+    pub(crate) seller_id: u64,
+    pub(crate) buyer_id: u64,
+    pub(crate) quantity: u64,
+    pub(crate) price: Amount,
+    pub(crate) executed_at: u64,
+    pub(crate) ledger_block_index: u64,
+}
+
+// Realized (claimed) and unrealized (accrued) returns of one pool position
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct PoolYield {
+    pub(crate) pool_id: u64,
+    pub(crate) shares: u64,
+    pub(crate) contributed: Amount,
+    pub(crate) realized: Amount,
+    pub(crate) unrealized: Amount,
+}
+
+// Pool Position Storage Key (pool ID, investor ID)
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
+pub struct PositionKey {
+    pub(crate) pool_id: u64,
+    pub(crate) investor_id: u64,
+}
+
+// Pool-Loan Relation Key (pool ID, loan ID): loans a pool currently funds
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
+pub struct PoolLoanKey {
+    pub(crate) pool_id: u64,
+    pub(crate) loan_id: u64,
+}
+
+// Investor-Loan Relation Key (investor ID, loan ID): loans an investor holds
+// participations in
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
+pub struct InvestorLoanKey {
+    pub(crate) investor_id: u64,
+    pub(crate) loan_id: u64,
+}
+
+// Email Index Key: the email a user registered with
+#[derive(Clone, PartialEq, Eq, PartialOrd, Ord, Debug)]
+pub struct EmailKey(pub(crate) String);
+
+// Owner Index Key (owner principal, user ID)
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
+pub struct OwnerUserKey {
+    pub(crate) owner: Principal,
+    pub(crate) user_id: u64,
+}
+
+// Investor Owner Index Key (owner principal, investor ID)
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
+pub struct OwnerInvestorKey {
+    pub(crate) owner: Principal,
+    pub(crate) investor_id: u64,
+}
+
+// Borrower Index Key (borrower ID, loan ID)
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
+pub struct BorrowerLoanKey {
+    pub(crate) borrower_id: u64,
+    pub(crate) loan_id: u64,
+}
+
+// Loan Status Index Key (status, loan ID)
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
+pub struct StatusLoanKey {
+    pub(crate) status: LoanStatus,
+    pub(crate) loan_id: u64,
+}
+
+// Payment Index Key (loan ID, payment ID)
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
+pub struct LoanPaymentKey {
+    pub(crate) loan_id: u64,
+    pub(crate) payment_id: u64,
+}
+
+// Audit Index Key (entity kind, record ID, audit entry ID): changes to a record
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
+pub struct AuditEntityKey {
+    pub(crate) entity: EntityKind,
+    pub(crate) entity_id: u64,
+    pub(crate) entry_id: u64,
+}
+
+// Audit Index Key (caller principal, audit entry ID): changes made by a principal
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
+pub struct AuditCallerKey {
+    pub(crate) caller: Principal,
+    pub(crate) entry_id: u64,
+}
+
+// Ledger Configuration Struct
+#[derive(CandidType, Serialize, Deserialize, Clone, Default, Debug)]
+pub struct LedgerConfig {
+    pub(crate) ledger_canister_id: Option<Principal>,
+    pub(crate) currency: Currency,
+    pub(crate) token_decimals: u8,
+}
+
+// Interest Accrual Configuration Struct
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct AccrualConfig {
+    pub(crate) interval_seconds: u64,
+    pub(crate) penalty_rate_bps: u32, // Daily penalty on overdue amounts, in basis points
+    pub(crate) default_threshold_days: u32,
+}
+
+impl Default for AccrualConfig {
+    fn default() -> Self {
+        AccrualConfig {
+            interval_seconds: 60 * 60,
+            penalty_rate_bps: 10,
+            default_threshold_days: 30,
+        }
+    }
+}
+
+// Net asset value and share supply of a loan pool
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct PoolValuation {
+    pub(crate) pool_id: u64,
+    pub(crate) net_asset_value: Amount,
+    pub(crate) total_shares: u64,
+}
+
+// ICRC-1 Supported Standard Record
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct SupportedStandard {
+    pub(crate) name: String,
+    pub(crate) url: String,
+}
+
+// Fee Configuration Struct
+#[derive(CandidType, Serialize, Deserialize, Clone, Default, Debug)]
+pub struct FeeConfig {
+    pub(crate) platform_fee_bps: u32, // Platform's cut of pool interest income, in basis points
+}
+
+// Access Control Configuration Struct
+#[derive(CandidType, Serialize, Deserialize, Clone, Default, Debug)]
+pub struct AccessConfig {
+    pub(crate) administrators: Vec<Principal>,
+}
+
+// Audit Log Entry: one stored record change, with the record before and
+// after the change encoded as JSON
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct AuditEntry {
+    pub(crate) id: u64, // Position in the audit log
+    pub(crate) caller: Principal,
+    pub(crate) timestamp: u64,
+    pub(crate) operation: String, // Endpoint or job that made the change
+    pub(crate) entity: EntityKind,
+    pub(crate) entity_id: u64,         // Pool ID for pool positions
+    pub(crate) before: Option<String>, // None when the change created the record
+    pub(crate) after: String,
+}
+
+// Query Views: records as stored, with their timestamps also formatted as
+// RFC 3339 text
+
+// Loan View
+#[derive(CandidType, Serialize, Clone, Debug)]
+pub struct LoanView {
+    pub(crate) loan: Loan,
+    pub(crate) start_date: String,
+    pub(crate) end_date: String,
+    pub(crate) last_accrued_on: Option<String>,
+    pub(crate) decided_at: Option<String>,
+}
+
+// Installment View
+#[derive(CandidType, Serialize, Clone, Debug)]
+pub struct InstallmentView {
+    pub(crate) installment: Installment,
+    pub(crate) due_date: String,
+}
+
+// Payment View
+#[derive(CandidType, Serialize, Clone, Debug)]
+pub struct PaymentView {
+    pub(crate) payment: Payment,
+    pub(crate) date: String,
+}
+
+// Recovery Case View
+#[derive(CandidType, Serialize, Clone, Debug)]
+pub struct RecoveryCaseView {
+    pub(crate) case: RecoveryCase,
+    pub(crate) notice_issued_at: String,
+    pub(crate) grace_period_ends_at: String,
+    pub(crate) repossession_ordered_at: Option<String>,
+    pub(crate) repossessed_at: Option<String>,
+    pub(crate) sold_at: Option<String>,
+    pub(crate) closed_at: Option<String>,
+}
+
+// KYC Record View
+#[derive(CandidType, Serialize, Clone, Debug)]
+pub struct KycView {
+    pub(crate) record: KycRecord,
+    pub(crate) submitted_at: String,
+    pub(crate) reviewed_at: Option<String>,
+}
+
+// Order View
+#[derive(CandidType, Serialize, Clone, Debug)]
+pub struct OrderView {
+    pub(crate) order: Order,
+    pub(crate) created_at: String,
+    pub(crate) expires_at: Option<String>,
+}
+
+// Trade View
+#[derive(CandidType, Serialize, Clone, Debug)]
+pub struct TradeView {
+    pub(crate) trade: Trade,
+    pub(crate) executed_at: String,
+}
+
+// A record with the proof that the subnet certified it: the certificate
+// over the canister's certified data (only present in query calls) and the
+// CBOR-encoded hash tree witness for the record's path
+#[derive(CandidType, Serialize, Clone, Debug)]
+pub struct Certified<T> {
+    pub(crate) data: T,
+    pub(crate) certificate: Option<Vec<u8>>,
+    pub(crate) witness: Vec<u8>,
+}
+
+// HTTP Gateway Request, as the HTTP gateway protocol defines it
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct HttpRequest {
+    pub(crate) method: String,
+    pub(crate) url: String, // Path and query string
+    pub(crate) headers: Vec<(String, String)>,
+    pub(crate) body: Vec<u8>,
+    pub(crate) certificate_version: Option<u16>,
+}
+
+// HTTP Gateway Response
+#[derive(CandidType, Serialize, Clone, Debug)]
+pub struct HttpResponse {
+    pub(crate) status_code: u16,
+    pub(crate) headers: Vec<(String, String)>,
+    pub(crate) body: Vec<u8>,
+}
+
+// A page of a listing, in the requested order
+#[derive(CandidType, Serialize, Clone, Debug)]
+pub struct Page<T> {
+    pub(crate) items: Vec<T>,
+    pub(crate) total: u64, // Records matching the filter across all pages
+    pub(crate) next_cursor: Option<u64>, // Pass back as `cursor` for the next page
 }
 
 // Payloads
 
+// Sort Order Enum (by ID, which follows creation order)
+#[derive(CandidType, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default, Debug)]
+pub enum SortOrder {
+    #[default]
+    Ascending,
+    Descending,
+}
+
+// Page Request Payload
+#[derive(CandidType, Deserialize, Clone, Debug, Default)]
+pub struct PageRequest {
+    pub(crate) cursor: Option<u64>, // ID of the last record of the previous page
+    pub(crate) limit: Option<u32>,
+    pub(crate) order: Option<SortOrder>,
+}
+
+// User Filter Payload
+#[derive(CandidType, Deserialize, Clone, Debug, Default)]
+pub struct UserFilter {
+    pub(crate) role: Option<UserRole>,
+}
+
+// KYC Record Filter Payload
+#[derive(CandidType, Deserialize, Clone, Debug, Default)]
+pub struct KycFilter {
+    pub(crate) status: Option<KycStatus>,
+}
+
+// Motorcycle Filter Payload
+#[derive(CandidType, Deserialize, Clone, Debug, Default)]
+pub struct MotorcycleFilter {
+    pub(crate) status: Option<MotorcycleStatus>,
+    pub(crate) manufacturer: Option<String>, // Case-insensitive exact match
+    pub(crate) min_price: Option<Amount>,
+    pub(crate) max_price: Option<Amount>,
+}
+
+// Loan Filter Payload
+#[derive(CandidType, Deserialize, Clone, Debug, Default)]
+pub struct LoanFilter {
+    pub(crate) status: Option<LoanStatus>,
+    pub(crate) borrower_id: Option<u64>,
+    pub(crate) pool_id: Option<u64>,
+}
+
+// Loan Pool Filter Payload
+#[derive(CandidType, Deserialize, Clone, Debug, Default)]
+pub struct LoanPoolFilter {
+    pub(crate) currency: Option<Currency>,
+    pub(crate) min_available_funds: Option<Amount>,
+}
+
+// Payment Filter Payload
+#[derive(CandidType, Deserialize, Clone, Debug, Default)]
+pub struct PaymentFilter {
+    pub(crate) status: Option<PaymentStatus>,
+}
+
+// Canister Init Arguments
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct InitArgs {
+    pub(crate) admin: Option<Principal>, // Bootstrap administrator, defaults to the installer
+    pub(crate) ledger: Option<LedgerConfig>,
+    pub(crate) accrual: Option<AccrualConfig>,
+    pub(crate) fees: Option<FeeConfig>,
+}
+
 // Register User Payload
 #[derive(CandidType, Deserialize, Clone, Debug)]
 pub struct RegisterUserPayload {
@@ -130,12 +679,28 @@ pub struct UpdateUserPayload {
     pub(crate) role: UserRole,
 }
 
+// Submit KYC Payload
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct SubmitKycPayload {
+    pub(crate) user_id: u64,
+    pub(crate) national_id: String,
+    pub(crate) phone: String,
+    pub(crate) document_hash: String,
+}
+
+// Reject KYC Submission Payload
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct RejectKycPayload {
+    pub(crate) user_id: u64,
+    pub(crate) reason: String,
+}
+
 // Motorcycle Registration Payload
 #[derive(CandidType, Deserialize, Clone, Debug)]
 pub struct RegisterMotorcyclePayload {
     pub(crate) model: String,
     pub(crate) manufacturer: String,
-    pub(crate) price: f64,
+    pub(crate) price: Amount,
 }
 
 // Loan Application Payload
@@ -143,11 +708,27 @@ pub struct RegisterMotorcyclePayload {
 pub struct ApplyLoanPayload {
     pub(crate) borrower_id: u64,
     pub(crate) motorcycle_id: u64,
-    pub(crate) principal_amount: f64,
+    pub(crate) principal_amount: Amount,
     pub(crate) interest_rate: f64,
-    pub(crate) daily_payment: f64,
-    pub(crate) start_date: String,
-    pub(crate) end_date: String,
+    pub(crate) interest_method: InterestMethod,
+    pub(crate) frequency: RepaymentFrequency,
+    pub(crate) term: u32,
+    pub(crate) start_date: String, // YYYY-MM-DD or RFC 3339
+}
+
+// Approve Loan Application Payload
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct ApproveLoanPayload {
+    pub(crate) loan_id: u64,
+    pub(crate) reason: String,
+    pub(crate) pool_id: Option<u64>, // Reserve the principal from this loan pool
+}
+
+// Reject Loan Application Payload
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct RejectLoanPayload {
+    pub(crate) loan_id: u64,
+    pub(crate) reason: String,
 }
 
 // Make Payment Payload
@@ -155,7 +736,7 @@ pub struct ApplyLoanPayload {
 pub struct MakePaymentPayload {
     pub(crate) loan_id: u64,
     pub(crate) borrower_id: u64,
-    pub(crate) amount: f64,
+    pub(crate) amount: Amount,
 }
 
 // Investor Registration Payload
@@ -169,7 +750,7 @@ pub struct RegisterInvestorPayload {
 #[derive(CandidType, Deserialize, Clone, Debug)]
 pub struct CreateLoanPoolPayload {
     pub(crate) name: String,
-    pub(crate) initial_funds: f64,
+    pub(crate) currency: Currency, // Capital is added through investor deposits
 }
 
 // Add Funds to Loan Pool Payload
@@ -177,5 +758,59 @@ pub struct CreateLoanPoolPayload {
 pub struct AddFundsPayload {
     pub(crate) pool_id: u64,
     pub(crate) investor_id: u64,
-    pub(crate) amount: f64,
+    pub(crate) amount: Amount,
+}
+
+// Claim Pool Returns Payload
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct ClaimReturnsPayload {
+    pub(crate) pool_id: u64,
+    pub(crate) investor_id: u64,
+}
+
+// Withdraw Funds from Loan Pool Payload
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct WithdrawFundsPayload {
+    pub(crate) pool_id: u64,
+    pub(crate) investor_id: u64,
+    pub(crate) amount: Amount,
+}
+
+// Place Secondary Market Order Payload
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct PlaceOrderPayload {
+    pub(crate) investor_id: u64,
+    pub(crate) asset: MarketAsset,
+    pub(crate) quantity: u64, // Shares, or basis points of the loan's income
+    pub(crate) price: Amount,
+    pub(crate) expires_at: Option<String>, // YYYY-MM-DD or RFC 3339
+}
+
+// Fill Secondary Market Order Payload
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct FillOrderPayload {
+    pub(crate) order_id: u64,
+    pub(crate) investor_id: u64, // Buyer
+    pub(crate) quantity: u64,
+}
+
+// Default Notice Payload
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct DefaultNoticePayload {
+    pub(crate) loan_id: u64,
+    pub(crate) grace_period_days: u32,
+}
+
+// Repossession Order Payload
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct RepossessionOrderPayload {
+    pub(crate) loan_id: u64,
+    pub(crate) agent: Principal,
+}
+
+// Recovery Sale Payload
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct RecoverySalePayload {
+    pub(crate) loan_id: u64,
+    pub(crate) sale_price: Amount,
 }
@@ -0,0 +1,76 @@
+use crate::models::*;
+use crate::{EntityKind, Error};
+
+// Loan events that move a motorcycle between states
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub(crate) enum MotorcycleEvent {
+    Originated,  // A loan financing the motorcycle was created
+    PaidOff,     // The loan was repaid in full
+    Repossessed, // The motorcycle was taken back after a default
+    Refurbished, // A repossessed motorcycle is back in inventory
+}
+
+// Allowed transitions: the state a motorcycle moves to when `event` happens
+// in `status`, or None if the event cannot happen in that state
+fn next_status(status: MotorcycleStatus, event: MotorcycleEvent) -> Option<MotorcycleStatus> {
+    match (status, event) {
+        (MotorcycleStatus::Available, MotorcycleEvent::Originated) => {
+            Some(MotorcycleStatus::InLoan)
+        }
+        (MotorcycleStatus::InLoan, MotorcycleEvent::PaidOff) => Some(MotorcycleStatus::FullyOwned),
+        (MotorcycleStatus::InLoan, MotorcycleEvent::Repossessed) => {
+            Some(MotorcycleStatus::Repossessed)
+        }
+        (MotorcycleStatus::Repossessed, MotorcycleEvent::Refurbished) => {
+            Some(MotorcycleStatus::Available)
+        }
+        _ => None,
+    }
+}
+
+// The state an event leads to from any state, for error messages
+fn target_status(event: MotorcycleEvent) -> MotorcycleStatus {
+    match event {
+        MotorcycleEvent::Originated => MotorcycleStatus::InLoan,
+        MotorcycleEvent::PaidOff => MotorcycleStatus::FullyOwned,
+        MotorcycleEvent::Repossessed => MotorcycleStatus::Repossessed,
+        MotorcycleEvent::Refurbished => MotorcycleStatus::Available,
+    }
+}
+
+fn invalid_transition(motorcycle: &Motorcycle, to: MotorcycleStatus) -> Error {
+    Error::InvalidTransition {
+        entity: EntityKind::Motorcycle,
+        id: motorcycle.id,
+        from: format!("{:?}", motorcycle.status),
+        to: format!("{:?}", to),
+    }
+}
+
+// Moves a motorcycle to the state `event` leads to, rejecting events its
+// current state does not allow
+pub(crate) fn apply_event(
+    motorcycle: &mut Motorcycle,
+    event: MotorcycleEvent,
+) -> Result<(), Error> {
+    match next_status(motorcycle.status, event) {
+        Some(status) => {
+            motorcycle.status = status;
+            Ok(())
+        }
+        None => Err(invalid_transition(motorcycle, target_status(event))),
+    }
+}
+
+// Status changes requested by staff. Only returning a refurbished motorcycle
+// to inventory is manual; every other transition follows a loan event.
+pub(crate) fn manual_transition(
+    motorcycle: &mut Motorcycle,
+    status: MotorcycleStatus,
+) -> Result<(), Error> {
+    if status == MotorcycleStatus::Available {
+        apply_event(motorcycle, MotorcycleEvent::Refurbished)
+    } else {
+        Err(invalid_transition(motorcycle, status))
+    }
+}
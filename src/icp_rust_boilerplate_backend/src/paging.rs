@@ -0,0 +1,84 @@
+use std::collections::VecDeque;
+
+use crate::amount::Amount;
+use crate::models::*;
+use crate::Error;
+
+// Page size when a request does not set one, and the largest allowed
+pub(crate) const DEFAULT_PAGE_LIMIT: u32 = 50;
+pub(crate) const MAX_PAGE_LIMIT: u32 = 500;
+
+// Pages through `records`, which must come in ascending ID order. Every
+// record is checked against `keep` so the page carries the total number of
+// matches; only the records on the page are turned into views.
+pub(crate) fn paginate<T, V>(
+    records: impl Iterator<Item = (u64, T)>,
+    request: &PageRequest,
+    keep: impl Fn(&T) -> bool,
+    view: impl Fn(T) -> V,
+) -> Result<Page<V>, Error> {
+    let limit = request.limit.unwrap_or(DEFAULT_PAGE_LIMIT);
+    if limit == 0 || limit > MAX_PAGE_LIMIT {
+        return Err(Error::invalid_payload(
+            "limit",
+            format!("Page limit must be between 1 and {}", MAX_PAGE_LIMIT),
+        ));
+    }
+    let limit = limit as usize;
+    let order = request.order.unwrap_or_default();
+
+    // One record past the page tells whether another page follows. Walking
+    // in ascending order, a descending page is the last records before the
+    // cursor, so older ones are dropped from the front as newer ones arrive.
+    let mut total = 0;
+    let mut window = VecDeque::with_capacity(limit + 1);
+    for (id, record) in records {
+        if !keep(&record) {
+            continue;
+        }
+        total += 1;
+        match order {
+            SortOrder::Ascending => {
+                if request.cursor.map_or(true, |cursor| id > cursor) && window.len() <= limit {
+                    window.push_back((id, record));
+                }
+            }
+            SortOrder::Descending => {
+                if request.cursor.map_or(true, |cursor| id < cursor) {
+                    window.push_back((id, record));
+                    if window.len() > limit + 1 {
+                        window.pop_front();
+                    }
+                }
+            }
+        }
+    }
+
+    let mut items: Vec<(u64, T)> = match order {
+        SortOrder::Ascending => window.into_iter().collect(),
+        SortOrder::Descending => window.into_iter().rev().collect(),
+    };
+    let next_cursor = if items.len() > limit {
+        items.truncate(limit);
+        items.last().map(|(id, _)| *id)
+    } else {
+        None
+    };
+    Ok(Page {
+        items: items.into_iter().map(|(_, record)| view(record)).collect(),
+        total,
+        next_cursor,
+    })
+}
+
+// Whether `price` lies within the optional bounds. Bounds in another
+// currency never match.
+pub(crate) fn in_range(price: &Amount, min: Option<&Amount>, max: Option<&Amount>) -> bool {
+    let above_min = min.map_or(true, |min| {
+        min.currency == price.currency && price.minor_units >= min.minor_units
+    });
+    let below_max = max.map_or(true, |max| {
+        max.currency == price.currency && price.minor_units <= max.minor_units
+    });
+    above_min && below_max
+}
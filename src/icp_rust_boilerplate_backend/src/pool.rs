@@ -0,0 +1,359 @@
+use crate::amount::Amount;
+use crate::models::*;
+use crate::{EntityKind, Error};
+
+// Position of an investor that holds nothing in the pool yet
+pub(crate) fn empty_position(pool: &LoanPool, investor_id: u64) -> PoolPosition {
+    PoolPosition {
+        pool_id: pool.id,
+        investor_id,
+        shares: 0,
+        locked_shares: 0,
+        contributed: Amount::zero(pool.total_funds.currency),
+        accrued_returns: Amount::zero(pool.total_funds.currency),
+        claimed_returns: Amount::zero(pool.total_funds.currency),
+    }
+}
+
+// Principal of a loan that has not been repaid yet
+pub(crate) fn outstanding_principal(loan: &Loan) -> Result<Amount, Error> {
+    loan.principal_amount.checked_sub(&loan.principal_paid)
+}
+
+// Checks that a pool's totals reconcile: every unit of capital is either
+// available or allocated, and the allocated capital is exactly the
+// outstanding principal of the pool's loans.
+pub(crate) fn check_pool_invariants(pool: &LoanPool, loans: &[Loan]) -> Result<(), Error> {
+    let capital = pool.available_funds.checked_add(&pool.allocated_funds)?;
+    if capital != pool.total_funds {
+        return Err(Error::invalid_state(
+            EntityKind::LoanPool,
+            pool.id,
+            format!(
+                "Available {} and allocated {} do not add up to total {}",
+                pool.available_funds, pool.allocated_funds, pool.total_funds
+            ),
+        ));
+    }
+    let lent = loans
+        .iter()
+        .try_fold(Amount::zero(pool.total_funds.currency), |total, loan| {
+            total.checked_add(&outstanding_principal(loan)?)
+        })?;
+    if lent != pool.allocated_funds {
+        return Err(Error::invalid_state(
+            EntityKind::LoanPool,
+            pool.id,
+            format!(
+                "Allocated {} does not match outstanding principal {} of its loans",
+                pool.allocated_funds, lent
+            ),
+        ));
+    }
+    Ok(())
+}
+
+// Moves the outstanding principal of `loan` from the pool's available funds
+// to its allocated funds and links the loan to the pool
+pub(crate) fn reserve_capital(mut pool: LoanPool, loan: &mut Loan) -> Result<LoanPool, Error> {
+    if let Some(pool_id) = loan.pool_id {
+        return Err(Error::invalid_state(
+            EntityKind::Loan,
+            loan.id,
+            format!("Loan is already funded by loan pool {}", pool_id),
+        ));
+    }
+    let amount = outstanding_principal(loan)?;
+    pool.available_funds.ensure_same_currency(&amount)?;
+    if amount.minor_units > pool.available_funds.minor_units {
+        return Err(Error::InsufficientFunds {
+            entity: EntityKind::LoanPool,
+            id: pool.id,
+            available: pool.available_funds,
+            requested: amount,
+        });
+    }
+
+    pool.available_funds = pool.available_funds.checked_sub(&amount)?;
+    pool.allocated_funds = pool.allocated_funds.checked_add(&amount)?;
+    loan.pool_id = Some(pool.id);
+    Ok(pool)
+}
+
+// Returns repaid principal to the pool's available funds
+fn release_capital(mut pool: LoanPool, principal_repaid: &Amount) -> Result<LoanPool, Error> {
+    pool.allocated_funds = pool.allocated_funds.checked_sub(principal_repaid)?;
+    pool.available_funds = pool.available_funds.checked_add(principal_repaid)?;
+    Ok(pool)
+}
+
+// Charges the principal a loan still owes to the pool's capital
+fn write_off(mut pool: LoanPool, loan: &Loan) -> Result<LoanPool, Error> {
+    let shortfall = outstanding_principal(loan)?;
+    pool.allocated_funds = pool.allocated_funds.checked_sub(&shortfall)?;
+    pool.total_funds = pool.total_funds.checked_sub(&shortfall)?;
+    Ok(pool)
+}
+
+// Applies a settled loan to the pool that funds it: repaid principal returns
+// to the available funds and, once the loan is written off, the principal it
+// still owes is charged to the pool's capital. The pool reconciles with
+// `check_pool_invariants` only after both steps.
+pub(crate) fn settle_capital(
+    pool: LoanPool,
+    loan: &Loan,
+    principal_repaid: &Amount,
+) -> Result<LoanPool, Error> {
+    let pool = release_capital(pool, principal_repaid)?;
+    if loan.status == LoanStatus::WrittenOff {
+        write_off(pool, loan)
+    } else {
+        Ok(pool)
+    }
+}
+
+// Whether `loan` still draws on the pool's capital: it was funded by the
+// pool, owes principal and has not been written off
+pub(crate) fn funds_loan(pool_id: u64, loan: &Loan) -> bool {
+    loan.pool_id == Some(pool_id)
+        && loan.status != LoanStatus::WrittenOff
+        && outstanding_principal(loan).is_ok_and(|principal| !principal.is_zero())
+}
+
+// Net asset value of a pool: its capital less the outstanding principal of
+// defaulted loans, which is treated as impaired. Interest is left out: once
+// collected it is paid to investors as returns and never joins the capital,
+// so pricing shares on it would pay it out twice.
+pub(crate) fn net_asset_value(pool: &LoanPool, loans: &[Loan]) -> Result<Amount, Error> {
+    let mut nav = pool.total_funds;
+    for loan in loans
+        .iter()
+        .filter(|loan| loan.status == LoanStatus::Defaulted)
+    {
+        let impaired = outstanding_principal(loan)?;
+        nav = nav.checked_sub(&impaired.min(&nav))?;
+    }
+    Ok(nav)
+}
+
+// Shares worth `amount` at the pool's net asset value per share. The first
+// deposit into an empty pool mints one share per minor unit.
+pub(crate) fn shares_for_amount(
+    pool: &LoanPool,
+    nav: &Amount,
+    amount: &Amount,
+    round_up: bool,
+) -> Result<u64, Error> {
+    if pool.total_shares == 0 || nav.is_zero() {
+        return Ok(amount.minor_units);
+    }
+    let numerator = amount.minor_units as u128 * pool.total_shares as u128;
+    let denominator = nav.minor_units as u128;
+    let shares = if round_up {
+        (numerator + denominator - 1) / denominator
+    } else {
+        numerator / denominator
+    };
+    u64::try_from(shares).map_err(|_| Error::Arithmetic {
+        msg: "Share amount overflow".to_string(),
+    })
+}
+
+// Value of `shares` at the pool's net asset value, rounded down
+pub(crate) fn share_value(pool: &LoanPool, nav: &Amount, shares: u64) -> Result<Amount, Error> {
+    if pool.total_shares == 0 {
+        return Ok(Amount::zero(nav.currency));
+    }
+    let value = shares as u128 * nav.minor_units as u128 / pool.total_shares as u128;
+    u64::try_from(value)
+        .map(|minor_units| Amount::new(minor_units, nav.currency))
+        .map_err(|_| Error::Arithmetic {
+            msg: "Amount overflow".to_string(),
+        })
+}
+
+// Adds deposited capital to the pool and `shares` to the investor's position
+pub(crate) fn credit_position(
+    pool: &mut LoanPool,
+    position: &mut PoolPosition,
+    amount: &Amount,
+    shares: u64,
+) -> Result<(), Error> {
+    pool.total_funds = pool.total_funds.checked_add(amount)?;
+    pool.available_funds = pool.available_funds.checked_add(amount)?;
+    pool.total_shares = pool
+        .total_shares
+        .checked_add(shares)
+        .ok_or_else(|| Error::Arithmetic {
+            msg: "Share amount overflow".to_string(),
+        })?;
+    position.shares += shares;
+    position.contributed = position.contributed.checked_add(amount)?;
+    Ok(())
+}
+
+// Removes `amount` of uncommitted capital from the pool and burns the shares
+// it is worth from the investor's position. Returns the shares burned.
+pub(crate) fn debit_position(
+    pool: &mut LoanPool,
+    nav: &Amount,
+    position: &mut PoolPosition,
+    amount: &Amount,
+) -> Result<u64, Error> {
+    pool.available_funds.ensure_same_currency(amount)?;
+    if amount.minor_units > pool.available_funds.minor_units {
+        return Err(Error::InsufficientFunds {
+            entity: EntityKind::LoanPool,
+            id: pool.id,
+            available: pool.available_funds,
+            requested: *amount,
+        });
+    }
+    let shares = shares_for_amount(pool, nav, amount, true)?;
+    let unlocked = position.shares - position.locked_shares;
+    if shares > unlocked {
+        return Err(Error::InsufficientFunds {
+            entity: EntityKind::Investor,
+            id: position.investor_id,
+            available: share_value(pool, nav, unlocked)?,
+            requested: *amount,
+        });
+    }
+
+    pool.total_funds = pool.total_funds.checked_sub(amount)?;
+    pool.available_funds = pool.available_funds.checked_sub(amount)?;
+    pool.total_shares -= shares;
+    position.shares -= shares;
+    position.contributed = position
+        .contributed
+        .checked_sub(&position.contributed.min(amount))?;
+    Ok(shares)
+}
+
+// Moves `shares` between two positions in the same pool, together with the
+// matching part of the sender's contribution. Returns already accrued stay
+// with the sender.
+pub(crate) fn transfer_shares(
+    from: &mut PoolPosition,
+    to: &mut PoolPosition,
+    shares: u64,
+) -> Result<Amount, Error> {
+    let unlocked = from.shares - from.locked_shares;
+    if shares > unlocked {
+        return Err(Error::invalid_payload(
+            "amount",
+            format!(
+                "Investor {} holds {} unlocked shares, {} requested",
+                from.investor_id, unlocked, shares
+            ),
+        ));
+    }
+    let contributed = if shares == 0 {
+        0
+    } else {
+        (from.contributed.minor_units as u128 * shares as u128 / from.shares as u128) as u64
+    };
+    let contributed = Amount::new(contributed, from.contributed.currency);
+
+    from.shares -= shares;
+    from.contributed = from.contributed.checked_sub(&contributed)?;
+    to.shares = to
+        .shares
+        .checked_add(shares)
+        .ok_or_else(|| Error::Arithmetic {
+            msg: "Share amount overflow".to_string(),
+        })?;
+    to.contributed = to.contributed.checked_add(&contributed)?;
+    Ok(contributed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::accrual::accrue_loan;
+    use crate::fixtures::{defaulted_loan, kes, pool, START};
+    use crate::recovery::apply_recovery;
+    use crate::returns::distribute_income;
+    use crate::schedule::NANOS_PER_DAY;
+    use crate::settlement::apply_payment;
+
+    #[test]
+    fn recovery_with_shortfall_reconciles_pool() {
+        let (mut loan, installments) = defaulted_loan(100_000);
+        let pool = reserve_capital(pool(250_000), &mut loan).unwrap();
+        check_pool_invariants(&pool, &[loan.clone()]).unwrap();
+
+        // The sale covers the interest and part of the principal
+        let today = START + 200 * NANOS_PER_DAY;
+        let (settlement, surplus, written_off) =
+            apply_recovery(loan, installments, kes(60_000), today).unwrap();
+        assert_eq!(settlement.loan.status, LoanStatus::WrittenOff);
+        assert!(surplus.is_zero());
+        assert_eq!(written_off, kes(44_000));
+
+        let pool =
+            settle_capital(pool, &settlement.loan, &settlement.allocation.principal).unwrap();
+        assert!(!funds_loan(pool.id, &settlement.loan));
+        check_pool_invariants(&pool, &[]).unwrap();
+        assert_eq!(pool.allocated_funds, kes(0));
+        assert_eq!(pool.available_funds, kes(206_000));
+        assert_eq!(pool.total_funds, kes(206_000));
+    }
+
+    #[test]
+    fn repayment_releases_capital_without_write_off() {
+        let (mut loan, _) = defaulted_loan(100_000);
+        let pool = reserve_capital(pool(250_000), &mut loan).unwrap();
+        loan.status = LoanStatus::Active;
+        loan.principal_paid = kes(25_000);
+
+        let pool = settle_capital(pool, &loan, &kes(25_000)).unwrap();
+        check_pool_invariants(&pool, &[loan]).unwrap();
+        assert_eq!(pool.total_funds, kes(250_000));
+        assert_eq!(pool.allocated_funds, kes(75_000));
+    }
+
+    #[test]
+    fn accrued_interest_is_paid_out_once() {
+        let (mut loan, installments) = defaulted_loan(100_000);
+        loan.status = LoanStatus::Active;
+        loan.days_past_due = 0;
+        let mut pool = reserve_capital(pool(250_000), &mut loan).unwrap();
+        let mut holder = empty_position(&pool, 1);
+        holder.shares = pool.total_shares;
+
+        // A second investor deposits, then interest accrues
+        let mut depositor = empty_position(&pool, 2);
+        let nav = net_asset_value(&pool, &[loan.clone()]).unwrap();
+        let minted = shares_for_amount(&pool, &nav, &kes(50_000), false).unwrap();
+        credit_position(&mut pool, &mut depositor, &kes(50_000), minted).unwrap();
+        let due = installments[0].due_date;
+        let (loan, installments) =
+            accrue_loan(loan, installments, due, &AccrualConfig::default()).unwrap();
+        assert_eq!(loan.interest_accrued, kes(1_000));
+
+        // Uncollected interest does not raise the price the depositor exits at
+        let nav = net_asset_value(&pool, &[loan.clone()]).unwrap();
+        assert_eq!(nav, pool.total_funds);
+        let burned = debit_position(&mut pool, &nav, &mut depositor, &kes(50_000)).unwrap();
+        assert_eq!(burned, minted);
+        assert_eq!(depositor.shares, 0);
+
+        // Collected interest goes to the holder's returns, not the capital
+        let payment = installments[0].total_due;
+        let settlement = apply_payment(loan, installments, payment, due).unwrap();
+        assert_eq!(settlement.allocation.interest, kes(1_000));
+        let pool =
+            settle_capital(pool, &settlement.loan, &settlement.allocation.principal).unwrap();
+        check_pool_invariants(&pool, &[settlement.loan.clone()]).unwrap();
+        assert_eq!(pool.total_funds, kes(250_000));
+        let distribution =
+            distribute_income(pool, vec![holder], &[], &settlement.allocation.interest, 0).unwrap();
+        assert_eq!(distribution.returns, vec![(1, kes(1_000))]);
+        assert_eq!(distribution.pool.total_funds, kes(250_000));
+        assert_eq!(
+            net_asset_value(&distribution.pool, &[settlement.loan]).unwrap(),
+            kes(250_000)
+        );
+    }
+}
@@ -0,0 +1,50 @@
+use crate::amount::Amount;
+use crate::loan::{apply_event, LoanEvent};
+use crate::models::*;
+use crate::pool::outstanding_principal;
+use crate::settlement::{allocate_payment, outstanding_balance, Settlement};
+use crate::{EntityKind, Error};
+
+// Checks that a recovery case has reached `expected` before moving it on
+pub(crate) fn ensure_stage(case: &RecoveryCase, expected: RecoveryStage) -> Result<(), Error> {
+    if case.stage == expected {
+        Ok(())
+    } else {
+        Err(Error::invalid_state(
+            EntityKind::RecoveryCase,
+            case.loan_id,
+            format!("Recovery case is {:?}, expected {:?}", case.stage, expected),
+        ))
+    }
+}
+
+// Applies the proceeds of a repossessed motorcycle's sale to a defaulted
+// loan like a payment: penalties, then accrued interest, then principal.
+// Proceeds beyond the outstanding balance are returned as surplus owed to the
+// borrower. A loan left with a balance is written off; its unpaid principal
+// is returned as the write-off.
+pub(crate) fn apply_recovery(
+    loan: Loan,
+    installments: Vec<Installment>,
+    sale_price: Amount,
+    today: u64,
+) -> Result<(Settlement, Amount, Amount), Error> {
+    if loan.status != LoanStatus::Defaulted {
+        return Err(Error::invalid_state(
+            EntityKind::Loan,
+            loan.id,
+            format!("{:?} loans cannot be recovered", loan.status),
+        ));
+    }
+    loan.principal_amount.ensure_same_currency(&sale_price)?;
+    let balance = outstanding_balance(&loan, &installments, today)?;
+    let applied = sale_price.min(&balance);
+    let surplus = sale_price.checked_sub(&applied)?;
+
+    let mut settlement = allocate_payment(loan, installments, applied, today)?;
+    let written_off = outstanding_principal(&settlement.loan)?;
+    if !settlement.allocation.remaining_balance.is_zero() {
+        apply_event(&mut settlement.loan, LoanEvent::WriteOff)?;
+    }
+    Ok((settlement, surplus, written_off))
+}
@@ -0,0 +1,184 @@
+use crate::amount::Amount;
+use crate::models::*;
+use crate::pool::empty_position;
+use crate::Error;
+
+// Result of sharing a repayment's income among a pool's investors
+pub(crate) struct Distribution {
+    pub(crate) pool: LoanPool,
+    pub(crate) positions: Vec<PoolPosition>,
+    pub(crate) returns: Vec<(u64, Amount)>, // Investor ID and the returns credited to it
+}
+
+// Credits `amount` to an investor's position, adding the position if the
+// investor holds none yet
+fn credit_returns(
+    pool: &LoanPool,
+    positions: &mut Vec<PoolPosition>,
+    returns: &mut Vec<(u64, Amount)>,
+    investor_id: u64,
+    amount: Amount,
+) -> Result<(), Error> {
+    if amount.is_zero() {
+        return Ok(());
+    }
+    let index = match positions
+        .iter()
+        .position(|position| position.investor_id == investor_id)
+    {
+        Some(index) => index,
+        None => {
+            positions.push(empty_position(pool, investor_id));
+            positions.len() - 1
+        }
+    };
+    positions[index].accrued_returns = positions[index].accrued_returns.checked_add(&amount)?;
+    match returns.iter_mut().find(|(id, _)| *id == investor_id) {
+        Some((_, total)) => *total = total.checked_add(&amount)?,
+        None => returns.push((investor_id, amount)),
+    }
+    Ok(())
+}
+
+// Claim of each position on the basis points of a loan's income that no
+// participation holds, scaled by the pool's share supply. Shares entitle
+// their holder to their part of every basis point not held outright plus
+// those drawn from pro-rata claims; basis points an investor drew by listing
+// them are then taken off its claim, which cannot go below zero. Without
+// draws the claims are in proportion to the shares.
+pub(crate) fn pro_rata_claims(
+    pool: &LoanPool,
+    positions: &[PoolPosition],
+    participations: &[LoanParticipation],
+) -> Vec<u128> {
+    let held: u128 = participations.iter().map(|p| p.bps as u128).sum();
+    let drawn: u128 = participations.iter().map(|p| p.drawn_bps as u128).sum();
+    let claimed = 10_000u128.saturating_sub(held) + drawn;
+    positions
+        .iter()
+        .map(|position| {
+            let drawn_by = participations
+                .iter()
+                .find(|p| p.investor_id == position.investor_id)
+                .map_or(0, |p| p.drawn_bps as u128);
+            (position.shares as u128 * claimed).saturating_sub(drawn_by * pool.total_shares as u128)
+        })
+        .collect()
+}
+
+// Splits interest and penalties collected on a pool-funded loan. The platform
+// fee is taken first. Holders of participations in the loan receive their
+// basis points of the rest; what remains is credited to the positions'
+// accrued returns in proportion to their claims (see `pro_rata_claims`), so
+// it follows the pool's current shareholders. Rounding remainders, and the
+// whole remainder when no position has a claim, go to the platform.
+pub(crate) fn distribute_income(
+    mut pool: LoanPool,
+    mut positions: Vec<PoolPosition>,
+    participations: &[LoanParticipation],
+    income: &Amount,
+    platform_fee_bps: u32,
+) -> Result<Distribution, Error> {
+    let fee = income.checked_mul_rate(platform_fee_bps as f64 / 10_000.0)?;
+    let fee = fee.min(income);
+    let net = income.checked_sub(&fee)?;
+
+    let mut returns = Vec::new();
+    let mut distributed = Amount::zero(income.currency);
+    for participation in participations {
+        let share = net.minor_units as u128 * participation.bps as u128 / 10_000;
+        let share = Amount::new(share as u64, income.currency);
+        credit_returns(
+            &pool,
+            &mut positions,
+            &mut returns,
+            participation.investor_id,
+            share,
+        )?;
+        distributed = distributed.checked_add(&share)?;
+    }
+
+    let residual = net.checked_sub(&distributed)?;
+    let claims = pro_rata_claims(&pool, &positions, participations);
+    let total_claims: u128 = claims.iter().sum();
+    if total_claims > 0 {
+        for (index, claim) in claims.into_iter().enumerate() {
+            let share = residual.minor_units as u128 * claim / total_claims;
+            let share = Amount::new(share as u64, income.currency);
+            let investor_id = positions[index].investor_id;
+            credit_returns(&pool, &mut positions, &mut returns, investor_id, share)?;
+            distributed = distributed.checked_add(&share)?;
+        }
+    }
+
+    let platform_fee = income.checked_sub(&distributed)?;
+    pool.platform_fees = pool.platform_fees.checked_add(&platform_fee)?;
+    Ok(Distribution {
+        pool,
+        positions,
+        returns,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::{kes, pool};
+
+    fn position(pool: &LoanPool, investor_id: u64, shares: u64) -> PoolPosition {
+        PoolPosition {
+            shares,
+            ..empty_position(pool, investor_id)
+        }
+    }
+
+    fn participation(investor_id: u64, bps: u32, drawn_bps: u32) -> LoanParticipation {
+        LoanParticipation {
+            loan_id: 7,
+            investor_id,
+            bps,
+            locked_bps: 0,
+            drawn_bps,
+        }
+    }
+
+    fn returns_of(distribution: &Distribution, investor_id: u64) -> Amount {
+        distribution
+            .returns
+            .iter()
+            .find(|(id, _)| *id == investor_id)
+            .map_or(kes(0), |(_, amount)| *amount)
+    }
+
+    #[test]
+    fn undrawn_income_follows_current_shareholders() {
+        // Investor 1 drew 1,000 bps from its half of the pool and sold them to 3
+        let participations = [participation(1, 0, 1_000), participation(3, 1_000, 0)];
+        let pool_before = pool(1_000);
+        let positions = vec![
+            position(&pool_before, 1, 500),
+            position(&pool_before, 2, 500),
+        ];
+        let distribution =
+            distribute_income(pool_before, positions, &participations, &kes(10_000), 0).unwrap();
+        assert_eq!(returns_of(&distribution, 1), kes(4_000));
+        assert_eq!(returns_of(&distribution, 2), kes(5_000));
+        assert_eq!(returns_of(&distribution, 3), kes(1_000));
+
+        // A later depositor earns on its shares; an investor that withdrew
+        // everything earns nothing
+        let pool_after = pool(2_000);
+        let positions = vec![
+            position(&pool_after, 1, 0),
+            position(&pool_after, 2, 500),
+            position(&pool_after, 4, 1_500),
+        ];
+        let distribution =
+            distribute_income(pool_after, positions, &participations, &kes(10_000), 0).unwrap();
+        assert_eq!(returns_of(&distribution, 1), kes(0));
+        assert_eq!(returns_of(&distribution, 2), kes(2_250));
+        assert_eq!(returns_of(&distribution, 3), kes(1_000));
+        assert_eq!(returns_of(&distribution, 4), kes(6_750));
+        assert_eq!(distribution.pool.platform_fees, kes(0));
+    }
+}
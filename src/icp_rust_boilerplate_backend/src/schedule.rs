@@ -0,0 +1,470 @@
+use crate::amount::Amount;
+use crate::models::*;
+use crate::Error;
+use chrono::{DateTime, Days, Months, NaiveDate, SecondsFormat, Utc};
+
+// Date format accepted in payloads besides RFC 3339 date-times
+pub(crate) const DATE_FORMAT: &str = "%Y-%m-%d";
+
+// Nanoseconds in a day
+pub(crate) const NANOS_PER_DAY: u64 = 86_400 * 1_000_000_000;
+
+// Longest schedule we accept (five years of daily installments)
+pub(crate) const MAX_TERM: u32 = 5 * 365;
+
+impl RepaymentFrequency {
+    // Number of installment periods in one year
+    pub(crate) fn periods_per_year(&self) -> f64 {
+        match self {
+            RepaymentFrequency::Daily => 365.0,
+            RepaymentFrequency::Weekly => 52.0,
+            RepaymentFrequency::Monthly => 12.0,
+        }
+    }
+
+    // Due date of the n-th installment counted from the loan start date
+    fn due_date(&self, start: NaiveDate, n: u32) -> Option<NaiveDate> {
+        match self {
+            RepaymentFrequency::Daily => start.checked_add_days(Days::new(n as u64)),
+            RepaymentFrequency::Weekly => start.checked_add_days(Days::new(7 * n as u64)),
+            RepaymentFrequency::Monthly => start.checked_add_months(Months::new(n)),
+        }
+    }
+}
+
+// Start of the UTC day containing a nanosecond IC timestamp. Dates on loans
+// and installments are stored as the start of their day.
+pub(crate) fn day_start(timestamp: u64) -> u64 {
+    timestamp - timestamp % NANOS_PER_DAY
+}
+
+// Whole days from `earlier` to `later`, zero if `later` is not later
+pub(crate) fn days_between(earlier: u64, later: u64) -> u64 {
+    later.saturating_sub(earlier) / NANOS_PER_DAY
+}
+
+// RFC 3339 date and time (UTC) of a nanosecond IC timestamp
+pub(crate) fn format_timestamp(timestamp: u64) -> String {
+    datetime_from_timestamp(timestamp).to_rfc3339_opts(SecondsFormat::Secs, true)
+}
+
+fn datetime_from_timestamp(timestamp: u64) -> DateTime<Utc> {
+    DateTime::from_timestamp(
+        (timestamp / 1_000_000_000) as i64,
+        (timestamp % 1_000_000_000) as u32,
+    )
+    .unwrap_or_default()
+}
+
+// Nanosecond timestamp of the start of a UTC calendar date
+fn timestamp_from_date(date: NaiveDate) -> Option<u64> {
+    date.and_hms_opt(0, 0, 0)?
+        .and_utc()
+        .timestamp_nanos_opt()
+        .and_then(|nanos| u64::try_from(nanos).ok())
+}
+
+// Parses an ISO-8601 date (YYYY-MM-DD, read as UTC midnight) or RFC 3339
+// date-time into a nanosecond timestamp
+pub(crate) fn parse_timestamp(text: &str, field: &str) -> Result<u64, Error> {
+    let timestamp = match DateTime::parse_from_rfc3339(text) {
+        Ok(datetime) => datetime
+            .timestamp_nanos_opt()
+            .and_then(|nanos| u64::try_from(nanos).ok()),
+        Err(_) => NaiveDate::parse_from_str(text, DATE_FORMAT)
+            .ok()
+            .and_then(timestamp_from_date),
+    };
+    timestamp.ok_or_else(|| {
+        Error::invalid_payload(
+            field,
+            format!(
+                "Invalid date '{}', expected YYYY-MM-DD or an RFC 3339 date-time",
+                text
+            ),
+        )
+    })
+}
+
+// Derive the installment plan for a loan from its principal, annual rate and term.
+// Installment parts are whole minor units; the final installment absorbs the
+// rounding remainder so the principal parts always sum to the principal.
+pub(crate) fn generate_schedule(
+    loan_id: u64,
+    principal: Amount,
+    interest_rate: f64,
+    term: u32,
+    frequency: RepaymentFrequency,
+    method: InterestMethod,
+    start_date: u64,
+) -> Result<Vec<Installment>, Error> {
+    if principal.is_zero() {
+        return Err(Error::invalid_payload(
+            "principal_amount",
+            "Principal amount must be greater than zero",
+        ));
+    }
+    if !interest_rate.is_finite() || interest_rate < 0.0 {
+        return Err(Error::invalid_payload(
+            "interest_rate",
+            "Interest rate cannot be negative",
+        ));
+    }
+    if term == 0 || term > MAX_TERM {
+        return Err(Error::invalid_payload(
+            "term",
+            format!("Term must be between 1 and {} installments", MAX_TERM),
+        ));
+    }
+    let start = datetime_from_timestamp(start_date).date_naive();
+
+    let currency = principal.currency;
+    let periodic_rate = interest_rate / 100.0 / frequency.periods_per_year();
+
+    // Flat interest is charged on the original principal for the whole term;
+    // reducing-balance interest is charged on what is still outstanding.
+    let flat_interest = principal.checked_mul_rate(periodic_rate * term as f64)?;
+    let flat_interest_part = flat_interest.minor_units / term as u64;
+    let flat_principal_part = principal.minor_units / term as u64;
+    let annuity_payment = if periodic_rate == 0.0 {
+        flat_principal_part
+    } else {
+        let annuity_factor = periodic_rate / (1.0 - (1.0 + periodic_rate).powi(-(term as i32)));
+        principal.checked_mul_rate(annuity_factor)?.minor_units
+    };
+
+    let mut schedule = Vec::with_capacity(term as usize);
+    let mut balance = principal;
+    let mut interest_charged = Amount::zero(currency);
+
+    for number in 1..=term {
+        let is_last = number == term;
+        let (principal_part, interest_part) = match method {
+            InterestMethod::Flat => {
+                if is_last {
+                    (balance, flat_interest.checked_sub(&interest_charged)?)
+                } else {
+                    let principal_part = Amount::new(flat_principal_part, currency);
+                    (
+                        principal_part.min(&balance),
+                        Amount::new(flat_interest_part, currency),
+                    )
+                }
+            }
+            InterestMethod::ReducingBalance => {
+                let interest_part = balance.checked_mul_rate(periodic_rate)?;
+                if is_last {
+                    (balance, interest_part)
+                } else {
+                    let principal_part = Amount::new(
+                        annuity_payment.saturating_sub(interest_part.minor_units),
+                        currency,
+                    );
+                    (principal_part.min(&balance), interest_part)
+                }
+            }
+        };
+
+        balance = balance.checked_sub(&principal_part)?;
+        interest_charged = interest_charged.checked_add(&interest_part)?;
+
+        let due_date = frequency
+            .due_date(start, number)
+            .and_then(timestamp_from_date)
+            .ok_or_else(|| {
+                Error::invalid_payload("term", "Schedule extends beyond the supported date range")
+            })?;
+
+        schedule.push(Installment {
+            loan_id,
+            number,
+            due_date,
+            principal_part,
+            interest_part,
+            total_due: principal_part.checked_add(&interest_part)?,
+            closing_balance: balance,
+            principal_paid: Amount::zero(currency),
+            interest_paid: Amount::zero(currency),
+            status: InstallmentStatus::Pending,
+        });
+    }
+
+    Ok(schedule)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::{kes, START};
+
+    fn schedule(
+        principal: u64,
+        rate: f64,
+        term: u32,
+        frequency: RepaymentFrequency,
+        method: InterestMethod,
+    ) -> Result<Vec<Installment>, Error> {
+        generate_schedule(7, kes(principal), rate, term, frequency, method, START)
+    }
+
+    fn total(installments: &[Installment], part: fn(&Installment) -> Amount) -> u64 {
+        installments
+            .iter()
+            .map(|installment| part(installment).minor_units)
+            .sum()
+    }
+
+    // Every installment is its principal and interest parts, and the principal
+    // parts pay the loan down to a zero closing balance
+    fn assert_reconciles(installments: &[Installment], principal: u64) {
+        for installment in installments {
+            assert_eq!(
+                installment.total_due,
+                installment
+                    .principal_part
+                    .checked_add(&installment.interest_part)
+                    .unwrap()
+            );
+        }
+        let interest = total(installments, |installment| installment.interest_part);
+        assert_eq!(
+            total(installments, |installment| installment.principal_part),
+            principal
+        );
+        assert_eq!(
+            total(installments, |installment| installment.total_due),
+            principal + interest
+        );
+        assert_eq!(installments.last().unwrap().closing_balance, kes(0));
+    }
+
+    #[test]
+    fn flat_interest_is_charged_on_the_original_principal() {
+        let installments = schedule(
+            100_000,
+            12.0,
+            4,
+            RepaymentFrequency::Monthly,
+            InterestMethod::Flat,
+        )
+        .unwrap();
+        assert_eq!(installments.len(), 4);
+        for (index, installment) in installments.iter().enumerate() {
+            assert_eq!(installment.number, index as u32 + 1);
+            assert_eq!(installment.principal_part, kes(25_000));
+            assert_eq!(installment.interest_part, kes(1_000));
+            assert_eq!(installment.status, InstallmentStatus::Pending);
+        }
+        assert_reconciles(&installments, 100_000);
+    }
+
+    #[test]
+    fn reducing_balance_interest_follows_the_outstanding_balance() {
+        let flat = schedule(
+            120_000,
+            12.0,
+            12,
+            RepaymentFrequency::Monthly,
+            InterestMethod::Flat,
+        )
+        .unwrap();
+        let reducing = schedule(
+            120_000,
+            12.0,
+            12,
+            RepaymentFrequency::Monthly,
+            InterestMethod::ReducingBalance,
+        )
+        .unwrap();
+        assert_reconciles(&reducing, 120_000);
+
+        // One percent a month on the full principal, then less each period
+        assert_eq!(reducing[0].interest_part, kes(1_200));
+        for pair in reducing.windows(2) {
+            assert!(pair[1].interest_part < pair[0].interest_part);
+            assert!(pair[1].principal_part > pair[0].principal_part);
+        }
+        // Level annuity payments up to the last installment
+        let payment = reducing[0].total_due;
+        assert!(reducing[..11]
+            .iter()
+            .all(|installment| installment.total_due == payment));
+
+        assert!(
+            total(&reducing, |installment| installment.interest_part)
+                < total(&flat, |installment| installment.interest_part)
+        );
+    }
+
+    #[test]
+    fn last_installment_absorbs_rounding() {
+        // 2 500 of interest and 100 000 of principal over three installments
+        let installments = schedule(
+            100_000,
+            10.0,
+            3,
+            RepaymentFrequency::Monthly,
+            InterestMethod::Flat,
+        )
+        .unwrap();
+        let principal: Vec<Amount> = installments
+            .iter()
+            .map(|installment| installment.principal_part)
+            .collect();
+        let interest: Vec<Amount> = installments
+            .iter()
+            .map(|installment| installment.interest_part)
+            .collect();
+        assert_eq!(principal, vec![kes(33_333), kes(33_333), kes(33_334)]);
+        assert_eq!(interest, vec![kes(833), kes(833), kes(834)]);
+        assert_reconciles(&installments, 100_000);
+
+        let installments = schedule(
+            100_000,
+            0.0,
+            7,
+            RepaymentFrequency::Weekly,
+            InterestMethod::ReducingBalance,
+        )
+        .unwrap();
+        assert_eq!(installments[0].principal_part, kes(14_285));
+        assert_eq!(installments[6].principal_part, kes(14_290));
+        assert_eq!(
+            total(&installments, |installment| installment.interest_part),
+            0
+        );
+        assert_reconciles(&installments, 100_000);
+    }
+
+    #[test]
+    fn due_dates_step_by_frequency() {
+        let start = parse_timestamp("2024-01-31", "start_date").unwrap();
+        let due_dates = |frequency| {
+            generate_schedule(
+                7,
+                kes(90_000),
+                12.0,
+                3,
+                frequency,
+                InterestMethod::Flat,
+                start,
+            )
+            .unwrap()
+            .iter()
+            .map(|installment| format_timestamp(installment.due_date))
+            .collect::<Vec<String>>()
+        };
+
+        assert_eq!(
+            due_dates(RepaymentFrequency::Daily),
+            [
+                "2024-02-01T00:00:00Z",
+                "2024-02-02T00:00:00Z",
+                "2024-02-03T00:00:00Z"
+            ]
+        );
+        assert_eq!(
+            due_dates(RepaymentFrequency::Weekly),
+            [
+                "2024-02-07T00:00:00Z",
+                "2024-02-14T00:00:00Z",
+                "2024-02-21T00:00:00Z"
+            ]
+        );
+        // Month ends clamp to the shorter month without drifting
+        assert_eq!(
+            due_dates(RepaymentFrequency::Monthly),
+            [
+                "2024-02-29T00:00:00Z",
+                "2024-03-31T00:00:00Z",
+                "2024-04-30T00:00:00Z"
+            ]
+        );
+    }
+
+    #[test]
+    fn term_must_fit_the_maximum() {
+        let daily = |term| {
+            schedule(
+                1_000_000,
+                12.0,
+                term,
+                RepaymentFrequency::Daily,
+                InterestMethod::ReducingBalance,
+            )
+        };
+        let term_error = Error::invalid_payload(
+            "term",
+            format!("Term must be between 1 and {} installments", MAX_TERM),
+        );
+        assert_eq!(daily(0).unwrap_err(), term_error);
+        assert_eq!(daily(MAX_TERM + 1).unwrap_err(), term_error);
+
+        let installments = daily(MAX_TERM).unwrap();
+        assert_eq!(installments.len(), MAX_TERM as usize);
+        assert_reconciles(&installments, 1_000_000);
+    }
+
+    #[test]
+    fn invalid_amounts_and_rates_are_rejected() {
+        let flat = |principal, rate| {
+            schedule(
+                principal,
+                rate,
+                4,
+                RepaymentFrequency::Monthly,
+                InterestMethod::Flat,
+            )
+        };
+        assert!(matches!(
+            flat(0, 12.0),
+            Err(Error::InvalidPayload { field, .. }) if field == "principal_amount"
+        ));
+        for rate in [-1.0, f64::NAN, f64::INFINITY] {
+            assert!(matches!(
+                flat(100_000, rate),
+                Err(Error::InvalidPayload { field, .. }) if field == "interest_rate"
+            ));
+        }
+    }
+
+    #[test]
+    fn dates_and_rfc_3339_date_times_are_parsed() {
+        let march_first = 19_783 * NANOS_PER_DAY;
+        let hour = 3_600 * 1_000_000_000;
+        assert_eq!(parse_timestamp("2024-03-01", "date"), Ok(march_first));
+        assert_eq!(
+            parse_timestamp("2024-03-01T09:30:00+03:00", "date"),
+            Ok(march_first + 6 * hour + hour / 2)
+        );
+        assert_eq!(
+            parse_timestamp("2024-03-01T00:00:00.25Z", "date"),
+            Ok(march_first + 250_000_000)
+        );
+        assert_eq!(format_timestamp(march_first), "2024-03-01T00:00:00Z");
+        assert_eq!(day_start(march_first + 6 * hour), march_first);
+    }
+
+    #[test]
+    fn malformed_dates_are_rejected() {
+        for text in [
+            "",
+            "2024-02-30",
+            "01/03/2024",
+            "2024-03-01 09:30",
+            "2024-03-01T09:30:00",
+            "1969-12-31",
+        ] {
+            assert_eq!(
+                parse_timestamp(text, "start_date"),
+                Err(Error::invalid_payload(
+                    "start_date",
+                    format!(
+                        "Invalid date '{}', expected YYYY-MM-DD or an RFC 3339 date-time",
+                        text
+                    ),
+                )),
+            );
+        }
+    }
+}
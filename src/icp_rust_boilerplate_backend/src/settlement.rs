@@ -0,0 +1,292 @@
+use crate::amount::Amount;
+use crate::loan::{accepts_payments, apply_event, LoanEvent};
+use crate::models::*;
+use crate::{EntityKind, Error};
+
+// Result of applying a payment to a loan and its schedule
+pub(crate) struct Settlement {
+    pub(crate) loan: Loan,
+    pub(crate) installments: Vec<Installment>,
+    pub(crate) allocation: PaymentAllocation,
+}
+
+// Interest of installments due on or before `today` that has not been paid yet
+fn accrued_interest(installments: &[Installment], today: u64) -> Result<Amount, Error> {
+    let currency = installments
+        .first()
+        .map(|installment| installment.interest_part.currency)
+        .unwrap_or_default();
+    installments
+        .iter()
+        .filter(|installment| installment.due_date <= today)
+        .try_fold(Amount::zero(currency), |total, installment| {
+            total.checked_add(
+                &installment
+                    .interest_part
+                    .checked_sub(&installment.interest_paid)?,
+            )
+        })
+}
+
+// Amount needed today to pay the loan off: penalties, accrued interest and the
+// principal still outstanding. Interest of installments not yet due is not owed.
+pub(crate) fn outstanding_balance(
+    loan: &Loan,
+    installments: &[Installment],
+    today: u64,
+) -> Result<Amount, Error> {
+    loan.principal_amount
+        .checked_sub(&loan.principal_paid)?
+        .checked_add(&accrued_interest(installments, today)?)?
+        .checked_add(&loan.penalties_due)
+}
+
+// Derives an installment's status from what has been paid and its due date
+pub(crate) fn refresh_status(installment: &mut Installment, today: u64) {
+    installment.status = if installment.principal_paid == installment.principal_part
+        && installment.interest_paid == installment.interest_part
+    {
+        InstallmentStatus::Paid
+    } else if installment.due_date < today {
+        InstallmentStatus::Overdue
+    } else if installment.principal_paid.is_zero() && installment.interest_paid.is_zero() {
+        InstallmentStatus::Pending
+    } else {
+        InstallmentStatus::PartiallyPaid
+    };
+}
+
+// Applies a payment to a disbursed or active loan
+pub(crate) fn apply_payment(
+    loan: Loan,
+    installments: Vec<Installment>,
+    amount: Amount,
+    today: u64,
+) -> Result<Settlement, Error> {
+    if !accepts_payments(&loan) {
+        return Err(Error::invalid_state(
+            EntityKind::Loan,
+            loan.id,
+            format!("{:?} loans cannot accept payments", loan.status),
+        ));
+    }
+    allocate_payment(loan, installments, amount, today)
+}
+
+// Applies an amount to penalties first, then to interest accrued on due
+// installments, and finally to principal in installment order. Once the
+// balance reaches zero the loan is completed and interest on installments
+// that have not fallen due yet is waived.
+pub(crate) fn allocate_payment(
+    mut loan: Loan,
+    mut installments: Vec<Installment>,
+    amount: Amount,
+    today: u64,
+) -> Result<Settlement, Error> {
+    loan.principal_amount.ensure_same_currency(&amount)?;
+    let balance = outstanding_balance(&loan, &installments, today)?;
+    if amount.minor_units > balance.minor_units {
+        return Err(Error::invalid_payload(
+            "amount",
+            format!(
+                "Payment of {} exceeds outstanding balance of {}",
+                amount, balance
+            ),
+        ));
+    }
+
+    let currency = amount.currency;
+    let mut remaining = amount;
+
+    let penalties = remaining.min(&loan.penalties_due);
+    remaining = remaining.checked_sub(&penalties)?;
+    loan.penalties_due = loan.penalties_due.checked_sub(&penalties)?;
+    loan.penalties_paid = loan.penalties_paid.checked_add(&penalties)?;
+
+    let mut interest = Amount::zero(currency);
+    for installment in installments
+        .iter_mut()
+        .filter(|installment| installment.due_date <= today)
+    {
+        let unpaid = installment
+            .interest_part
+            .checked_sub(&installment.interest_paid)?;
+        let paid = remaining.min(&unpaid);
+        installment.interest_paid = installment.interest_paid.checked_add(&paid)?;
+        interest = interest.checked_add(&paid)?;
+        remaining = remaining.checked_sub(&paid)?;
+    }
+    loan.interest_paid = loan.interest_paid.checked_add(&interest)?;
+
+    // Whatever is left never exceeds the outstanding principal
+    let principal = remaining;
+    for installment in installments.iter_mut() {
+        let unpaid = installment
+            .principal_part
+            .checked_sub(&installment.principal_paid)?;
+        let paid = remaining.min(&unpaid);
+        installment.principal_paid = installment.principal_paid.checked_add(&paid)?;
+        remaining = remaining.checked_sub(&paid)?;
+    }
+    loan.principal_paid = loan.principal_paid.checked_add(&principal)?;
+    loan.total_paid = loan.total_paid.checked_add(&amount)?;
+
+    let remaining_balance = outstanding_balance(&loan, &installments, today)?;
+    if remaining_balance.is_zero() {
+        apply_event(&mut loan, LoanEvent::PayOff)?;
+        for installment in installments.iter_mut() {
+            installment.status = InstallmentStatus::Paid;
+        }
+    } else {
+        installments
+            .iter_mut()
+            .for_each(|installment| refresh_status(installment, today));
+    }
+
+    Ok(Settlement {
+        loan,
+        installments,
+        allocation: PaymentAllocation {
+            penalties,
+            interest,
+            principal,
+            remaining_balance,
+        },
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::{defaulted_loan, kes};
+    use crate::motorcycle::{self, MotorcycleEvent};
+
+    // The fixture loan back in good standing: four installments of 25 000
+    // principal and 1 000 interest
+    fn active_loan() -> (Loan, Vec<Installment>) {
+        let (mut loan, installments) = defaulted_loan(100_000);
+        loan.status = LoanStatus::Active;
+        loan.days_past_due = 0;
+        (loan, installments)
+    }
+
+    #[test]
+    fn payments_cover_penalties_then_interest_then_principal() {
+        let (mut loan, installments) = active_loan();
+        loan.penalties_due = kes(500);
+        let today = installments[1].due_date;
+
+        // Too little to clear the penalties
+        let settlement =
+            apply_payment(loan.clone(), installments.clone(), kes(300), today).unwrap();
+        assert_eq!(settlement.allocation.penalties, kes(300));
+        assert_eq!(settlement.allocation.interest, kes(0));
+        assert_eq!(settlement.allocation.principal, kes(0));
+        assert_eq!(settlement.loan.penalties_due, kes(200));
+
+        // Penalties, the interest of both due installments, then principal
+        // from the first installment on
+        let settlement = apply_payment(loan, installments, kes(12_500), today).unwrap();
+        let allocation = &settlement.allocation;
+        assert_eq!(allocation.penalties, kes(500));
+        assert_eq!(allocation.interest, kes(2_000));
+        assert_eq!(allocation.principal, kes(10_000));
+        assert_eq!(allocation.remaining_balance, kes(90_000));
+
+        let loan = &settlement.loan;
+        assert_eq!(loan.status, LoanStatus::Active);
+        assert_eq!(loan.penalties_due, kes(0));
+        assert_eq!(loan.penalties_paid, kes(500));
+        assert_eq!(loan.interest_paid, kes(2_000));
+        assert_eq!(loan.principal_paid, kes(10_000));
+        assert_eq!(loan.total_paid, kes(12_500));
+
+        let installments = &settlement.installments;
+        assert_eq!(installments[0].interest_paid, kes(1_000));
+        assert_eq!(installments[0].principal_paid, kes(10_000));
+        assert_eq!(installments[0].status, InstallmentStatus::Overdue);
+        assert_eq!(installments[1].interest_paid, kes(1_000));
+        assert_eq!(installments[1].principal_paid, kes(0));
+        assert_eq!(installments[1].status, InstallmentStatus::PartiallyPaid);
+        assert_eq!(installments[2].interest_paid, kes(0));
+        assert_eq!(installments[2].status, InstallmentStatus::Pending);
+    }
+
+    #[test]
+    fn overpayment_is_rejected() {
+        let (loan, installments) = active_loan();
+        let today = installments[0].due_date;
+        // Interest of installments not yet due is not owed
+        assert_eq!(
+            outstanding_balance(&loan, &installments, today).unwrap(),
+            kes(101_000)
+        );
+
+        let error = apply_payment(loan, installments, kes(101_001), today).unwrap_err();
+        assert_eq!(
+            error,
+            Error::invalid_payload(
+                "amount",
+                format!(
+                    "Payment of {} exceeds outstanding balance of {}",
+                    kes(101_001),
+                    kes(101_000)
+                ),
+            )
+        );
+    }
+
+    #[test]
+    fn payoff_completes_the_loan_and_transfers_the_motorcycle() {
+        let (loan, installments) = active_loan();
+        let today = installments[0].due_date;
+
+        let settlement = apply_payment(loan, installments, kes(101_000), today).unwrap();
+        assert_eq!(settlement.allocation.interest, kes(1_000));
+        assert_eq!(settlement.allocation.principal, kes(100_000));
+        assert!(settlement.allocation.remaining_balance.is_zero());
+        assert_eq!(settlement.loan.status, LoanStatus::Completed);
+        assert_eq!(settlement.loan.principal_paid, kes(100_000));
+        // Interest of installments that had not fallen due is waived
+        assert_eq!(settlement.installments[3].interest_paid, kes(0));
+        assert!(settlement
+            .installments
+            .iter()
+            .all(|installment| installment.status == InstallmentStatus::Paid));
+
+        let mut motorcycle = Motorcycle {
+            id: settlement.loan.motorcycle_id,
+            model: "Boxer 150".to_string(),
+            manufacturer: "Bajaj".to_string(),
+            price: kes(100_000),
+            status: MotorcycleStatus::InLoan,
+        };
+        motorcycle::apply_event(&mut motorcycle, MotorcycleEvent::PaidOff).unwrap();
+        assert_eq!(motorcycle.status, MotorcycleStatus::FullyOwned);
+        assert!(motorcycle::apply_event(&mut motorcycle, MotorcycleEvent::PaidOff).is_err());
+
+        // A completed loan takes no further payments
+        assert!(apply_payment(settlement.loan, settlement.installments, kes(1), today).is_err());
+    }
+
+    #[test]
+    fn payments_to_loans_not_in_repayment_fail_with_a_reason() {
+        let (loan, installments) = defaulted_loan(100_000);
+        let today = installments[0].due_date;
+
+        // The message is what a failed payment records as its failure reason
+        let error = apply_payment(loan, installments, kes(26_000), today).unwrap_err();
+        assert_eq!(
+            error,
+            Error::invalid_state(
+                EntityKind::Loan,
+                7,
+                "Defaulted loans cannot accept payments"
+            )
+        );
+        assert_eq!(
+            error.to_string(),
+            "INVALID_STATE: Loan with ID 7: Defaulted loans cannot accept payments"
+        );
+    }
+}
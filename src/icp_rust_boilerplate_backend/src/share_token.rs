@@ -0,0 +1,253 @@
+use candid::{Nat, Principal};
+use icrc_ledger_types::icrc::generic_metadata_value::MetadataValue;
+use icrc_ledger_types::icrc1::account::Account;
+use icrc_ledger_types::icrc1::transfer::{TransferArg, TransferError};
+
+use crate::amount::{add_to_totals, sub_from_totals};
+use crate::models::*;
+use crate::pool::transfer_shares;
+use crate::{EntityKind, Error};
+
+// Every pool keeps its own ICRC-1 balances: an investor holds a pool's shares
+// in the pool's subaccount, a marker byte, 23 zero bytes and the pool ID as 8
+// big-endian bytes. Balances in different pool subaccounts are shares of
+// different pools and are never exchanged for one another.
+const POOL_SUBACCOUNT_MARKER: u8 = 1;
+
+pub(crate) fn pool_subaccount(pool_id: u64) -> [u8; 32] {
+    let mut subaccount = [0; 32];
+    subaccount[0] = POOL_SUBACCOUNT_MARKER;
+    subaccount[24..].copy_from_slice(&pool_id.to_be_bytes());
+    subaccount
+}
+
+// Pool whose shares a subaccount holds, if it is a pool subaccount
+pub(crate) fn subaccount_pool(subaccount: &Option<[u8; 32]>) -> Option<u64> {
+    let subaccount = subaccount.as_ref()?;
+    let is_pool_subaccount =
+        subaccount[0] == POOL_SUBACCOUNT_MARKER && subaccount[1..24].iter().all(|byte| *byte == 0);
+    is_pool_subaccount.then(|| u64::from_be_bytes(subaccount[24..].try_into().unwrap()))
+}
+
+fn is_default_subaccount(subaccount: &Option<[u8; 32]>) -> bool {
+    subaccount.map_or(true, |subaccount| subaccount == [0; 32])
+}
+
+// The pool-scoped endpoints name the pool, so they also accept the default
+// subaccount for its shares
+fn holds_pool(pool_id: u64, subaccount: &Option<[u8; 32]>) -> bool {
+    is_default_subaccount(subaccount) || subaccount_pool(subaccount) == Some(pool_id)
+}
+
+// Investor record of an account owner. The anonymous principal owns none.
+fn investor_by_owner(owner: Principal) -> Option<Investor> {
+    if owner == Principal::anonymous() {
+        return None;
+    }
+    let investor_id = crate::index::investor_by_owner(owner)?;
+    crate::INVESTORS_STORAGE.with(|storage| storage.borrow().get(&investor_id))
+}
+
+fn load_pool(pool_id: u64) -> Result<LoanPool, Error> {
+    crate::LOAN_POOLS_STORAGE
+        .with(|storage| storage.borrow().get(&pool_id))
+        .ok_or_else(|| Error::not_found(EntityKind::LoanPool, pool_id))
+}
+
+fn generic_error(error: Error) -> TransferError {
+    TransferError::GenericError {
+        error_code: Nat::from(0u64),
+        message: error.to_string(),
+    }
+}
+
+pub(crate) const TOKEN_NAME: &str = "Motorcycle Loan Pool Share";
+pub(crate) const TOKEN_SYMBOL: &str = "MLP";
+
+// Shares are whole units: a pool mints one per minor unit of its currency
+// when it opens, and pools in currencies with different minor units share
+// the one ledger
+pub(crate) const TOKEN_DECIMALS: u8 = 0;
+
+pub(crate) fn token_metadata() -> Vec<(String, MetadataValue)> {
+    vec![
+        (
+            "icrc1:name".to_string(),
+            MetadataValue::Text(TOKEN_NAME.to_string()),
+        ),
+        (
+            "icrc1:symbol".to_string(),
+            MetadataValue::Text(TOKEN_SYMBOL.to_string()),
+        ),
+        (
+            "icrc1:decimals".to_string(),
+            MetadataValue::Nat(Nat::from(TOKEN_DECIMALS as u64)),
+        ),
+        ("icrc1:fee".to_string(), MetadataValue::Nat(Nat::from(0u64))),
+    ]
+}
+
+// Shares of every pool
+pub(crate) fn total_supply() -> Nat {
+    let shares: u128 = crate::LOAN_POOLS_STORAGE.with(|storage| {
+        storage
+            .borrow()
+            .iter()
+            .map(|(_, pool)| pool.total_shares as u128)
+            .sum()
+    });
+    Nat::from(shares)
+}
+
+// Shares held by `account` in a pool
+pub(crate) fn balance_of(pool_id: u64, account: &Account) -> Nat {
+    if !holds_pool(pool_id, &account.subaccount) {
+        return Nat::from(0u64);
+    }
+    let shares = investor_by_owner(account.owner).and_then(|investor| {
+        let key = PositionKey {
+            pool_id,
+            investor_id: investor.id,
+        };
+        crate::POOL_POSITIONS_STORAGE.with(|storage| storage.borrow().get(&key))
+    });
+    Nat::from(shares.map_or(0, |position| position.shares))
+}
+
+// Moves `shares` of a pool between two investors; the matching part of the
+// sender's cost basis follows the shares
+pub(crate) fn move_shares(
+    pool_id: u64,
+    mut sender: Investor,
+    mut recipient: Investor,
+    shares: u64,
+    operation: &str,
+) -> Result<(), Error> {
+    let pool = load_pool(pool_id)?;
+    let mut from = crate::load_position(&pool, sender.id);
+    let mut to = crate::load_position(&pool, recipient.id);
+    let contributed = transfer_shares(&mut from, &mut to, shares)?;
+
+    sub_from_totals(&mut sender.total_invested, &contributed)?;
+    add_to_totals(&mut recipient.total_invested, &contributed)?;
+
+    crate::store_investor(&sender, operation);
+    crate::store_investor(&recipient, operation);
+    crate::store_position(&from, operation);
+    crate::store_position(&to, operation);
+    Ok(())
+}
+
+// Shares held by `account` in the pool its subaccount names
+pub(crate) fn icrc1_balance_of(account: &Account) -> Nat {
+    match subaccount_pool(&account.subaccount) {
+        Some(pool_id) => balance_of(pool_id, account),
+        None => Nat::from(0u64),
+    }
+}
+
+// Block index of the next share transfer; transfers are numbered in order
+fn next_block_index() -> u64 {
+    crate::SHARE_TRANSFER_COUNTER.with(|counter| {
+        let mut counter = counter.borrow_mut();
+        let index = *counter.get();
+        counter
+            .set(index + 1)
+            .expect("Cannot store the share transfer counter");
+        index
+    })
+}
+
+// ICRC-1 transfer between the subaccounts of one pool, the pool the sender's
+// subaccount names
+pub(crate) fn icrc1_transfer(arg: TransferArg) -> Result<Nat, TransferError> {
+    let pool_id = subaccount_pool(&arg.from_subaccount).ok_or_else(|| {
+        generic_error(Error::invalid_payload(
+            "from_subaccount",
+            "Shares are held in the subaccount of their pool",
+        ))
+    })?;
+    if subaccount_pool(&arg.to.subaccount) != Some(pool_id) {
+        return Err(generic_error(Error::invalid_payload(
+            "to",
+            format!(
+                "Shares of pool {} can only be sent to its subaccount",
+                pool_id
+            ),
+        )));
+    }
+    transfer(pool_id, arg, "icrc1_transfer")
+}
+
+// ICRC-1 transfer of pool shares between registered investors. Transfers are
+// fee-free; shares are only minted by deposits and burned by withdrawals, so
+// the minting account cannot send or receive them. Shares listed on the
+// secondary market cannot be transferred.
+pub(crate) fn transfer(
+    pool_id: u64,
+    arg: TransferArg,
+    operation: &str,
+) -> Result<Nat, TransferError> {
+    if !holds_pool(pool_id, &arg.from_subaccount) || !holds_pool(pool_id, &arg.to.subaccount) {
+        return Err(generic_error(Error::invalid_payload(
+            "subaccount",
+            format!(
+                "Shares of pool {} are held in the default account or the pool's subaccount",
+                pool_id
+            ),
+        )));
+    }
+    if arg.fee.as_ref().is_some_and(|fee| *fee != Nat::from(0u64)) {
+        return Err(TransferError::BadFee {
+            expected_fee: Nat::from(0u64),
+        });
+    }
+    let pool = load_pool(pool_id).map_err(generic_error)?;
+    let sender =
+        investor_by_owner(ic_cdk::api::caller()).ok_or(TransferError::InsufficientFunds {
+            balance: Nat::from(0u64),
+        })?;
+    let position = crate::load_position(&pool, sender.id);
+    let balance = Nat::from(position.shares - position.locked_shares);
+    let recipient = investor_by_owner(arg.to.owner).ok_or_else(|| {
+        generic_error(Error::invalid_payload(
+            "to",
+            "Recipient is not a registered investor",
+        ))
+    })?;
+    let shares = match arg.amount.0.to_u64_digits().as_slice() {
+        [] => 0,
+        [shares] if arg.amount <= balance => *shares,
+        _ => return Err(TransferError::InsufficientFunds { balance }),
+    };
+    if sender.id != recipient.id {
+        move_shares(pool_id, sender, recipient, shares, operation).map_err(generic_error)?;
+    }
+    Ok(Nat::from(next_block_index()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pool_subaccounts_name_their_pool() {
+        for pool_id in [0, 1, u64::MAX] {
+            assert_eq!(
+                subaccount_pool(&Some(pool_subaccount(pool_id))),
+                Some(pool_id)
+            );
+            assert!(holds_pool(pool_id, &Some(pool_subaccount(pool_id))));
+            assert!(!holds_pool(
+                pool_id.wrapping_add(1),
+                &Some(pool_subaccount(pool_id))
+            ));
+        }
+        assert_eq!(subaccount_pool(&None), None);
+        assert_eq!(subaccount_pool(&Some([0; 32])), None);
+        let mut other = pool_subaccount(5);
+        other[10] = 1;
+        assert_eq!(subaccount_pool(&Some(other)), None);
+        assert!(holds_pool(5, &None));
+    }
+}
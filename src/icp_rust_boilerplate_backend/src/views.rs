@@ -0,0 +1,79 @@
+use crate::models::*;
+use crate::schedule::format_timestamp;
+
+fn format_optional(timestamp: Option<u64>) -> Option<String> {
+    timestamp.map(format_timestamp)
+}
+
+impl From<Loan> for LoanView {
+    fn from(loan: Loan) -> Self {
+        LoanView {
+            start_date: format_timestamp(loan.start_date),
+            end_date: format_timestamp(loan.end_date),
+            last_accrued_on: format_optional(loan.last_accrued_on),
+            decided_at: format_optional(loan.decided_at),
+            loan,
+        }
+    }
+}
+
+impl From<Installment> for InstallmentView {
+    fn from(installment: Installment) -> Self {
+        InstallmentView {
+            due_date: format_timestamp(installment.due_date),
+            installment,
+        }
+    }
+}
+
+impl From<Payment> for PaymentView {
+    fn from(payment: Payment) -> Self {
+        PaymentView {
+            date: format_timestamp(payment.date),
+            payment,
+        }
+    }
+}
+
+impl From<RecoveryCase> for RecoveryCaseView {
+    fn from(case: RecoveryCase) -> Self {
+        RecoveryCaseView {
+            notice_issued_at: format_timestamp(case.notice_issued_at),
+            grace_period_ends_at: format_timestamp(case.grace_period_ends_at),
+            repossession_ordered_at: format_optional(case.repossession_ordered_at),
+            repossessed_at: format_optional(case.repossessed_at),
+            sold_at: format_optional(case.sold_at),
+            closed_at: format_optional(case.closed_at),
+            case,
+        }
+    }
+}
+
+impl From<KycRecord> for KycView {
+    fn from(record: KycRecord) -> Self {
+        KycView {
+            submitted_at: format_timestamp(record.submitted_at),
+            reviewed_at: format_optional(record.reviewed_at),
+            record,
+        }
+    }
+}
+
+impl From<Order> for OrderView {
+    fn from(order: Order) -> Self {
+        OrderView {
+            created_at: format_timestamp(order.created_at),
+            expires_at: format_optional(order.expires_at),
+            order,
+        }
+    }
+}
+
+impl From<Trade> for TradeView {
+    fn from(trade: Trade) -> Self {
+        TradeView {
+            executed_at: format_timestamp(trade.executed_at),
+            trade,
+        }
+    }
+}
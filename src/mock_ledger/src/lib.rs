@@ -0,0 +1,128 @@
+// A minimal, heap-only ICRC-1/ICRC-2 ledger used to exercise the loan
+// canister's repayments, deposits and disbursements on a local replica.
+// Transfers are fee-free and anyone may mint; never deploy it to mainnet.
+use candid::{Nat, Principal};
+use icrc_ledger_types::icrc1::account::Account;
+use icrc_ledger_types::icrc1::transfer::{TransferArg, TransferError};
+use icrc_ledger_types::icrc2::approve::{ApproveArgs, ApproveError};
+use icrc_ledger_types::icrc2::transfer_from::{TransferFromArgs, TransferFromError};
+use std::{cell::RefCell, collections::HashMap};
+
+thread_local! {
+    static BALANCES: RefCell<HashMap<Account, Nat>> = RefCell::new(HashMap::new());
+    static ALLOWANCES: RefCell<HashMap<(Account, Account), Nat>> = RefCell::new(HashMap::new());
+    static NEXT_BLOCK_INDEX: RefCell<u64> = RefCell::new(0);
+}
+
+fn next_block_index() -> Nat {
+    NEXT_BLOCK_INDEX.with(|index| {
+        let mut index = index.borrow_mut();
+        let current = *index;
+        *index += 1;
+        Nat::from(current)
+    })
+}
+
+fn balance(account: &Account) -> Nat {
+    BALANCES.with(|balances| balances.borrow().get(account).cloned().unwrap_or_default())
+}
+
+// Moves tokens between accounts, returning the sender's balance if it is too low
+fn move_tokens(from: &Account, to: &Account, amount: &Nat) -> Result<(), Nat> {
+    let from_balance = balance(from);
+    if from_balance < *amount {
+        return Err(from_balance);
+    }
+    let to_balance = balance(to);
+    BALANCES.with(|balances| {
+        let mut balances = balances.borrow_mut();
+        balances.insert(*from, from_balance - amount.clone());
+        balances.insert(*to, to_balance + amount.clone());
+    });
+    Ok(())
+}
+
+fn caller_account(subaccount: Option<[u8; 32]>) -> Account {
+    Account {
+        owner: ic_cdk::api::caller(),
+        subaccount,
+    }
+}
+
+#[ic_cdk::query]
+fn icrc1_name() -> String {
+    "Mock Ledger".to_string()
+}
+
+#[ic_cdk::query]
+fn icrc1_symbol() -> String {
+    "MOCK".to_string()
+}
+
+#[ic_cdk::query]
+fn icrc1_decimals() -> u8 {
+    8
+}
+
+#[ic_cdk::query]
+fn icrc1_fee() -> Nat {
+    Nat::from(0u64)
+}
+
+#[ic_cdk::query]
+fn icrc1_balance_of(account: Account) -> Nat {
+    balance(&account)
+}
+
+#[ic_cdk::update]
+fn icrc1_transfer(args: TransferArg) -> Result<Nat, TransferError> {
+    let from = caller_account(args.from_subaccount);
+    move_tokens(&from, &args.to, &args.amount)
+        .map_err(|balance| TransferError::InsufficientFunds { balance })?;
+    Ok(next_block_index())
+}
+
+#[ic_cdk::update]
+fn icrc2_approve(args: ApproveArgs) -> Result<Nat, ApproveError> {
+    let owner = caller_account(args.from_subaccount);
+    ALLOWANCES.with(|allowances| {
+        allowances
+            .borrow_mut()
+            .insert((owner, args.spender), args.amount)
+    });
+    Ok(next_block_index())
+}
+
+#[ic_cdk::update]
+fn icrc2_transfer_from(args: TransferFromArgs) -> Result<Nat, TransferFromError> {
+    let spender = caller_account(args.spender_subaccount);
+    let allowance = ALLOWANCES.with(|allowances| {
+        allowances
+            .borrow()
+            .get(&(args.from, spender))
+            .cloned()
+            .unwrap_or_default()
+    });
+    if allowance < args.amount {
+        return Err(TransferFromError::InsufficientAllowance { allowance });
+    }
+    move_tokens(&args.from, &args.to, &args.amount)
+        .map_err(|balance| TransferFromError::InsufficientFunds { balance })?;
+    ALLOWANCES.with(|allowances| {
+        allowances
+            .borrow_mut()
+            .insert((args.from, spender), allowance - args.amount)
+    });
+    Ok(next_block_index())
+}
+
+// Test helper: credits `amount` to `owner`'s default account
+#[ic_cdk::update]
+fn mint(owner: Principal, amount: Nat) -> Nat {
+    let account = Account::from(owner);
+    let balance = balance(&account) + amount;
+    BALANCES.with(|balances| balances.borrow_mut().insert(account, balance));
+    next_block_index()
+}
+
+ic_cdk::export_candid!();